@@ -0,0 +1,248 @@
+// Compares how the exFAT backend indexes an image (runs of clusters read at once into one reused buffer, the
+// directories of each level in the order they are on the drive) with how it used to read the directories (one cluster
+// at a time into a new Vec, going down one directory at a time), on an image whose directories are each in one run
+// of clusters and on the same tree with fragmented directories.
+//
+// cargo bench --bench exfat_read
+//
+// It builds an image with 100 directories of 20 subdirectories each, every subdirectory holding
+// ANYTHING_BENCH_FILES_PER_DIR empty files (100 by default, so 200000 files). In the fragmented image the clusters of
+// the directories are handed out a few at a time to all of them in turn, like on a card that filled up slowly, so
+// directories are fragmented and not in the order they are listed in. The images are in the page cache so the
+// timings only show the cost of the reads themselves, on a real drive (with `sync; echo 3 > /proc/sys/vm/drop_caches`
+// before each run) the seeks count too
+// Methods are written with an explicit `self: &Self` like in the crate
+#![allow(clippy::needless_arbitrary_self_type)]
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, Instant};
+use anything::Drive;
+
+const SECTOR: usize = 512;
+const CLUSTER: usize = 4096;
+const FAT_OFFSET: usize = 24;
+/// Clusters given to a directory at a time in the fragmented image
+const FRAGMENTED_RUN: usize = 2;
+const RUNS: usize = 5;
+
+/// A directory of the image
+struct Dir{
+    clusters: Vec<u32>,
+    /// Directories in it, as indices of Layout::dirs
+    children: Vec<usize>,
+    entries: Vec<u8>,
+}
+struct Layout{
+    dirs: Vec<Dir>,
+    /// Number of files in the image
+    files: usize,
+}
+/// A minimal linear congruential generator, so that the image is the same every time
+struct Lcg(u64);
+impl Lcg{
+    fn next(self: &mut Self, n: usize) -> usize{
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % n
+    }
+}
+fn set_checksum(entries: &[u8]) -> u16{
+    let mut checksum: u16 = 0;
+    for (i, b) in entries.iter().enumerate(){
+        if i == 2 || i == 3{
+            continue;
+        }
+        checksum = checksum.rotate_right(1).wrapping_add(*b as u16);
+    }
+    checksum
+}
+/// The entry set of a file or directory whose name fits in one file name entry
+fn entry_set(name: &str, is_dir: bool, first_cluster: u32, size: u64) -> [u8; 96]{
+    let mut e = [0_u8; 96];
+    let units: Vec<u16> = name.encode_utf16().collect();
+    e[0] = 0x85;
+    e[1] = 2;
+    e[4] = if is_dir{0x10}else{0x20};
+    e[32] = 0xC0;
+    e[33] = 0x01;
+    e[35] = units.len() as u8;
+    e[40..48].copy_from_slice(&size.to_le_bytes());
+    e[52..56].copy_from_slice(&first_cluster.to_le_bytes());
+    e[56..64].copy_from_slice(&size.to_le_bytes());
+    e[64] = 0xC1;
+    for (k, u) in units.iter().enumerate(){
+        e[66 + 2*k..68 + 2*k].copy_from_slice(&u.to_le_bytes());
+    }
+    let checksum = set_checksum(&e);
+    e[2..4].copy_from_slice(&checksum.to_le_bytes());
+    e
+}
+/// Directories get `run` clusters at a time, usize::MAX puts every directory in one run
+fn build_image(path: &str, files_per_dir: usize, run: usize) -> Layout{
+    let mut dirs = vec![Dir{clusters: Vec::new(), children: Vec::new(), entries: Vec::new()}];
+    let mut files = 0;
+    for a in 0..100{
+        dirs.push(Dir{clusters: Vec::new(), children: Vec::new(), entries: Vec::new()});
+        let parent = dirs.len() - 1;
+        dirs[0].children.push(parent);
+        for b in 0..20{
+            let mut entries = Vec::new();
+            for f in 0..files_per_dir{
+                entries.extend_from_slice(&entry_set(&format!("f{a}-{b}-{f}.txt"), false, 0, 0));
+                files += 1;
+            }
+            dirs.push(Dir{clusters: Vec::new(), children: Vec::new(), entries});
+            let child = dirs.len() - 1;
+            dirs[parent].children.push(child);
+        }
+    }
+    // Every directory needs room for its entries (the ones of directories are 96 bytes each) and the end of directory
+    let sizes: Vec<usize> = dirs.iter().map(|d| (d.entries.len() + 96*d.children.len() + 32).div_ceil(CLUSTER)).collect();
+    // The root and the bitmap come first, then the other directories get `run` clusters at a time in a random order
+    let mut next_cluster = 2_u32;
+    let bitmap_clusters = 1;
+    let bitmap_cluster = next_cluster;
+    next_cluster += bitmap_clusters;
+    dirs[0].clusters = (next_cluster..next_cluster + sizes[0] as u32).collect();
+    next_cluster += sizes[0] as u32;
+    let mut rng = Lcg(0x5eed);
+    let mut left: Vec<usize> = (1..dirs.len()).collect();
+    while !left.is_empty(){
+        for k in (1..left.len()).rev(){
+            left.swap(k, rng.next(k + 1));
+        }
+        for &d in &left{
+            let n = run.min(sizes[d] - dirs[d].clusters.len());
+            dirs[d].clusters.extend(next_cluster..next_cluster + n as u32);
+            next_cluster += n as u32;
+        }
+        left.retain(|&d| dirs[d].clusters.len() < sizes[d]);
+    }
+    let cluster_count = next_cluster as usize - 2;
+    assert!(cluster_count <= bitmap_clusters as usize*CLUSTER*8, "too many clusters for the bitmap");
+    // Entries of the directories, now that their clusters are known
+    for d in 0..dirs.len(){
+        let mut entries = Vec::new();
+        if d == 0{
+            let mut bitmap = [0_u8; 32];
+            bitmap[0] = 0x81;
+            bitmap[20..24].copy_from_slice(&bitmap_cluster.to_le_bytes());
+            bitmap[24..32].copy_from_slice(&(cluster_count.div_ceil(8) as u64).to_le_bytes());
+            entries.extend_from_slice(&bitmap);
+        }
+        for (k, &c) in dirs[d].children.iter().enumerate(){
+            let size = (dirs[c].clusters.len()*CLUSTER) as u64;
+            entries.extend_from_slice(&entry_set(&format!("d{d}-{k}"), true, dirs[c].clusters[0], size));
+        }
+        let files = std::mem::take(&mut dirs[d].entries);
+        entries.extend(files);
+        dirs[d].entries = entries;
+    }
+    let fat_sectors = ((cluster_count + 2)*4).div_ceil(SECTOR);
+    let heap = (FAT_OFFSET + fat_sectors).next_multiple_of(CLUSTER/SECTOR);
+    let volume_sectors = heap + cluster_count*CLUSTER/SECTOR;
+    let mut image = vec![0_u8; volume_sectors*SECTOR];
+    let cluster_byte = |c: u32| heap*SECTOR + (c as usize - 2)*CLUSTER;
+    let mut fat = vec![0_u32; cluster_count + 2];
+    fat[0] = 0xFFFFFFF8;
+    fat[1] = 0xFFFFFFFF;
+    fat[bitmap_cluster as usize] = 0xFFFFFFFF;
+    for d in &dirs{
+        for w in d.clusters.windows(2){
+            fat[w[0] as usize] = w[1];
+        }
+        fat[*d.clusters.last().unwrap() as usize] = 0xFFFFFFFF;
+        for (k, chunk) in d.entries.chunks(CLUSTER).enumerate(){
+            let at = cluster_byte(d.clusters[k]);
+            image[at..at + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+    for (c, entry) in fat.iter().enumerate(){
+        let at = FAT_OFFSET*SECTOR + 4*c;
+        image[at..at + 4].copy_from_slice(&entry.to_le_bytes());
+    }
+    let at = cluster_byte(bitmap_cluster);
+    for bit in 0..cluster_count{
+        image[at + bit/8] |= 1 << (bit % 8);
+    }
+    let b = &mut image[0..SECTOR];
+    b[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    b[3..11].copy_from_slice(b"EXFAT   ");
+    b[72..80].copy_from_slice(&(volume_sectors as u64).to_le_bytes());
+    b[80..84].copy_from_slice(&(FAT_OFFSET as u32).to_le_bytes());
+    b[84..88].copy_from_slice(&(fat_sectors as u32).to_le_bytes());
+    b[88..92].copy_from_slice(&(heap as u32).to_le_bytes());
+    b[92..96].copy_from_slice(&(cluster_count as u32).to_le_bytes());
+    b[96..100].copy_from_slice(&dirs[0].clusters[0].to_le_bytes());
+    b[104..106].copy_from_slice(&0x0100_u16.to_le_bytes());
+    b[108] = SECTOR.trailing_zeros() as u8;
+    b[109] = (CLUSTER/SECTOR).trailing_zeros() as u8;
+    b[110] = 1;
+    b[510] = 0x55;
+    b[511] = 0xAA;
+    std::fs::write(path, &image).unwrap();
+    Layout{dirs, files}
+}
+/// The old way: one read per cluster into a new Vec, a directory and then everything below it
+fn read_per_cluster(file: &File, layout: &Layout, dir: usize, cluster_byte: &impl Fn(u32) -> u64, reads: &mut usize) -> usize{
+    let mut bytes = Vec::new();
+    for &c in &layout.dirs[dir].clusters{
+        let mut b = vec![0_u8; CLUSTER];
+        file.read_at(&mut b, cluster_byte(c)).unwrap();
+        *reads += 1;
+        bytes.append(&mut b);
+    }
+    let mut total = bytes.len();
+    for &child in &layout.dirs[dir].children{
+        total += read_per_cluster(file, layout, child, cluster_byte, reads);
+    }
+    total
+}
+/// Runs `f` RUNS times (after a warm up) and returns the fastest time with what the last run returned
+fn time<T>(mut f: impl FnMut() -> T) -> (Duration, T){
+    f();
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..RUNS{
+        let start = Instant::now();
+        result = Some(f());
+        best = best.min(start.elapsed());
+    }
+    (best, result.unwrap())
+}
+/// Builds an image, times reading its directories the old way and indexing it
+fn bench(description: &str, files_per_dir: usize, run: usize){
+    let path = std::env::temp_dir().join("anything_exfat_bench.img").to_string_lossy().to_string();
+    let layout = build_image(&path, files_per_dir, run);
+    let directory_bytes: usize = layout.dirs.iter().map(|d| d.clusters.len()*CLUSTER).sum();
+    let fragments: usize = layout.dirs.iter().map(|d| 1 + d.clusters.windows(2).filter(|w| w[1] != w[0] + 1).count()).sum();
+
+    println!("{description}: {} files in {} directories, {} KiB of directories in {fragments} runs of clusters",
+        layout.files, layout.dirs.len(), directory_bytes/1024);
+
+    let file = File::open(&path).unwrap();
+    let mut boot = [0_u8; SECTOR];
+    file.read_at(&mut boot, 0).unwrap();
+    let heap = u32::from_le_bytes([boot[88], boot[89], boot[90], boot[91]]) as u64*SECTOR as u64;
+    let cluster_byte = |c: u32| heap + (c as u64 - 2)*CLUSTER as u64;
+    let (old, (old_reads, old_bytes)) = time(||{
+        let mut reads = 0;
+        let bytes = read_per_cluster(&file, &layout, 0, &cluster_byte, &mut reads);
+        (reads, bytes)
+    });
+    assert_eq!(old_bytes, directory_bytes);
+    println!("    directory reads, one cluster at a time: {old:?} ({old_reads} reads)");
+
+    let drive = Drive{fs: "Exfat", drive: path.clone(), mounted_at: String::from("/bench"), ..Drive::default()};
+    let registry = anything::indexer::registry().read().unwrap();
+    let exfat = registry.get("Exfat").unwrap();
+    let (index, (files, _)) = time(|| exfat.index(&drive, 0).unwrap());
+    assert_eq!(files.len(), layout.files + layout.dirs.len() - 1);
+    // The backend reads every run of clusters of a directory at once
+    println!("    exfat.index (reads and parses the directories): {index:?} ({fragments} directory reads)");
+    let _ = std::fs::remove_file(&path);
+}
+fn main(){
+    let files_per_dir = std::env::var("ANYTHING_BENCH_FILES_PER_DIR").ok().and_then(|n| n.parse().ok()).unwrap_or(100);
+    bench("contiguous directories", files_per_dir, usize::MAX);
+    bench("fragmented directories", files_per_dir, FRAGMENTED_RUN);
+}
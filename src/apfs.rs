@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet};
+use crate::partitions::{self, PartitionFile};
+use crate::unicode::compose;
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at, u64_at};
+
+// The following code decodes APFS following the Apple File System Reference
+// https://developer.apple.com/support/downloads/Apple-File-System-Reference.pdf
+// Every unencrypted volume of the container is indexed (encrypted ones can't be read without the password),
+// if the container has more than one volume each one is shown as a directory named like the volume
+
+/// Type of objects in the object header (the low 16 bits)
+const OBJECT_TYPE_NX_SUPERBLOCK: u32 = 0x1;
+const OBJ_PHYSICAL: u32 = 0x4000_0000;
+const BTNODE_ROOT: u16 = 0x1;
+const BTNODE_FIXED_KV_SIZE: u16 = 0x4;
+/// The root node of a tree ends with the btree_info_t
+const BTREE_INFO_SIZE: usize = 40;
+const APFS_TYPE_INODE: u64 = 3;
+const APFS_TYPE_DIR_REC: u64 = 9;
+const APFS_INCOMPAT_CASE_INSENSITIVE: u64 = 0x1;
+const APFS_INCOMPAT_NORMALIZATION_INSENSITIVE: u64 = 0x8;
+const APFS_FS_UNENCRYPTED: u64 = 0x1;
+const INO_EXT_TYPE_DSTREAM: u8 = 8;
+const ROOT_DIR_INO_NUM: u64 = 2;
+const DT_DIR: u16 = 4;
+
+/// Every object starts with this checksum of the rest of the block
+fn fletcher64(b: &[u8]) -> u64{
+    let mut sum1: u64 = 0;
+    let mut sum2: u64 = 0;
+    for c in b[8..].chunks_exact(4){
+        sum1 = (sum1 + u32_at(c, 0) as u64) % 0xFFFF_FFFF;
+        sum2 = (sum2 + sum1) % 0xFFFF_FFFF;
+    }
+    let c1 = 0xFFFF_FFFF - (sum1 + sum2) % 0xFFFF_FFFF;
+    let c2 = 0xFFFF_FFFF - (sum1 + c1) % 0xFFFF_FFFF;
+    (c2 << 32) | c1
+}
+#[derive(Debug, Default, Clone)]
+struct Inode{
+    size: u64,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+/// A directory record: (name, inode number, is a directory)
+type DirRecord = (String, u64, bool);
+struct Volume{
+    name: String,
+    inodes: HashMap<u64, Inode>,
+    /// Directory inode number -> its entries
+    children: HashMap<u64, Vec<DirRecord>>,
+}
+struct ApfsDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    /// Container superblock of the latest checkpoint
+    superblock: Vec<u8>,
+    /// Directories of the current volume already indexed, a damaged record naming a parent as child would loop
+    visited: HashSet<u64>,
+    files: Vec<ApfsFile>,
+    ignored_dirs: Vec<String>
+}
+impl ApfsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut sb = vec![0u8; 4096];
+        file.read_at(&mut sb, 0).map_err(Error::io(0))?;
+        if &sb[32..36] != b"NXSB"{
+            return Err(Error::WrongFilesystem("APFS"));
+        }
+        let block_size = u32_at(&sb, 36) as u64;
+        if !block_size.is_power_of_two() || !(4096..=65536).contains(&block_size){
+            return Err(Error::corrupt(36, format!("invalid block size {block_size}")));
+        }
+        let mut drive = ApfsDrive {file, directories: Vec::new(), mounted_at, block_size, superblock: Vec::new(), visited: HashSet::new(), files: Vec::new(), ignored_dirs};
+        // Block 0 may be an old copy, the newest valid superblock in the checkpoint descriptor area is used
+        let mut best = drive.read_block(0)?;
+        let desc_blocks = u32_at(&sb, 104);
+        let desc_base = u64_at(&sb, 112);
+        // The highest bit means the area is not contiguous (it is described by a B-tree), then block 0 is used
+        if desc_blocks & 0x8000_0000 == 0{
+            for i in 0..desc_blocks as u64{
+                let b = drive.read_block(desc_base + i)?;
+                if u32_at(&b, 24) & 0xFFFF == OBJECT_TYPE_NX_SUPERBLOCK && &b[32..36] == b"NXSB"
+                    && fletcher64(&b) == u64_at(&b, 0) && u64_at(&b, 16) > u64_at(&best, 16){
+                    best = b;
+                }
+            }
+        }
+        drive.superblock = best;
+        Ok(drive)
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    fn read_block(self: &Self, address: u64) -> Result<Vec<u8>, Error>{
+        self.read_bytes(address*self.block_size, self.block_size)
+    }
+    /// Calls `f` with (key, value) of every record in the leaves of a B-tree.
+    /// Child nodes of virtual trees are found through the object map, their level must be below `parent_level`
+    fn walk_tree(self: &Self, address: u64, omap: Option<&HashMap<u64, u64>>, f: &mut impl FnMut(&[u8], &[u8]), parent_level: u16) -> Result<(), Error>{
+        let node = self.read_block(address)?;
+        let flags = u16_at(&node, 32);
+        let level = u16_at(&node, 34);
+        // A child pointing back up the tree would be walked again and again
+        if level >= parent_level{
+            return Ok(());
+        }
+        let toc_start = 56 + u16_at(&node, 40) as usize;
+        let toc_entry_size = if flags & BTNODE_FIXED_KV_SIZE != 0{4}else{8};
+        let keys = (u32_at(&node, 36) as usize).min(node.len().saturating_sub(toc_start) / toc_entry_size);
+        let key_start = toc_start + u16_at(&node, 42) as usize;
+        let value_end = node.len() - if flags & BTNODE_ROOT != 0{BTREE_INFO_SIZE}else{0};
+        for i in 0..keys{
+            // Fixed size trees (the object maps) have 16 bytes keys and values, 8 bytes child addresses
+            let (k, k_len, v, v_len) = if flags & BTNODE_FIXED_KV_SIZE != 0{
+                let e = toc_start + i*4;
+                (u16_at(&node, e) as usize, 16, u16_at(&node, e+2) as usize, if level == 0{16}else{8})
+            }else{
+                let e = toc_start + i*8;
+                (u16_at(&node, e) as usize, u16_at(&node, e+2) as usize, u16_at(&node, e+4) as usize, u16_at(&node, e+6) as usize)
+            };
+            if key_start + k + k_len > node.len() || v > value_end || value_end - v + v_len > node.len(){
+                continue;
+            }
+            let key = &node[key_start + k..key_start + k + k_len];
+            let value = &node[value_end - v..value_end - v + v_len];
+            if level == 0{
+                f(key, value);
+            }else if value.len() >= 8{
+                let child = u64_at(value, 0);
+                let child = match omap{
+                    Some(omap) => {match omap.get(&child){Some(&a) => {a} None => {continue;}}}
+                    None => {child}
+                };
+                self.walk_tree(child, omap, f, level)?;
+            }
+        }
+        Ok(())
+    }
+    /// Object id -> physical address of its newest version
+    fn read_omap(self: &Self, omap_address: u64) -> Result<HashMap<u64, u64>, Error>{
+        let omap = self.read_block(omap_address)?;
+        let mut newest: HashMap<u64, (u64, u64)> = HashMap::new();
+        self.walk_tree(u64_at(&omap, 48), None, &mut |key, value|{
+            if key.len() < 16 || value.len() < 16{
+                return;
+            }
+            let (oid, xid, address) = (u64_at(key, 0), u64_at(key, 8), u64_at(value, 8));
+            let e = newest.entry(oid).or_insert((xid, address));
+            if xid >= e.0{
+                *e = (xid, address);
+            }
+        }, u16::MAX)?;
+        Ok(newest.into_iter().map(|(oid, (_, address))| (oid, address)).collect())
+    }
+    fn read_volume(self: &Self, address: u64) -> Result<Option<Volume>, Error>{
+        let sb = self.read_block(address)?;
+        if &sb[32..36] != b"APSB" || u64_at(&sb, 264) & APFS_FS_UNENCRYPTED == 0{
+            return Ok(None);
+        }
+        let incompatible = u64_at(&sb, 56);
+        let hashed_names = incompatible & (APFS_INCOMPAT_CASE_INSENSITIVE | APFS_INCOMPAT_NORMALIZATION_INSENSITIVE) != 0;
+        let name_end = sb[704..960].iter().position(|&c| c == 0).unwrap_or(256);
+        let mut volume = Volume{name: String::from_utf8_lossy(&sb[704..704 + name_end]).to_string(), inodes: HashMap::new(), children: HashMap::new()};
+        let omap = self.read_omap(u64_at(&sb, 128))?;
+        // Sealed volumes have a physical tree
+        let root_tree = u64_at(&sb, 136);
+        let (root, tree_omap) = if u32_at(&sb, 116) & OBJ_PHYSICAL != 0{
+            (root_tree, None)
+        }else{
+            match omap.get(&root_tree){Some(&root) => {(root, Some(&omap))} None => {return Ok(None);}}
+        };
+        self.walk_tree(root, tree_omap, &mut |key, value|{
+            if key.len() < 8{
+                return;
+            }
+            let id = u64_at(key, 0) & 0x0FFF_FFFF_FFFF_FFFF;
+            match u64_at(key, 0) >> 60{
+                APFS_TYPE_INODE if value.len() >= 92 => {
+                    let mut inode = Inode{
+                        size: 0,
+                        create_timestamp: (u64_at(value, 16) / 1_000_000_000) as i64,
+                        last_modified_timestamp: (u64_at(value, 24) / 1_000_000_000) as i64,
+                    };
+                    // Extended fields: count, used bytes, (type, flags, size) for each field, then the data
+                    if value.len() >= 96{
+                        let count = u16_at(value, 92) as usize;
+                        let mut data = 96 + count*4;
+                        for i in 0..count{
+                            let field = 96 + i*4;
+                            if field + 4 > value.len(){
+                                break;
+                            }
+                            let size = u16_at(value, field + 2) as usize;
+                            if value[field] == INO_EXT_TYPE_DSTREAM && data + 8 <= value.len(){
+                                inode.size = u64_at(value, data);
+                            }
+                            data += size.div_ceil(8)*8;
+                        }
+                    }
+                    volume.inodes.insert(id, inode);
+                }
+                APFS_TYPE_DIR_REC if key.len() >= 12 && value.len() >= 18 => {
+                    let name = if hashed_names{
+                        let len = (u32_at(key, 8) & 0x3FF) as usize;
+                        &key[12..(12 + len).min(key.len())]
+                    }else{
+                        let len = u16_at(key, 8) as usize;
+                        &key[10..(10 + len).min(key.len())]
+                    };
+                    // Names are NUL terminated
+                    let name = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(name));
+                    let is_dir = u16_at(value, 16) & 0xF == DT_DIR;
+                    volume.children.entry(id).or_default().push((compose(&name), u64_at(value, 0), is_dir));
+                }
+                _ => {}
+            }
+        }, u16::MAX)?;
+        Ok(Some(volume))
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let omap = self.read_omap(u64_at(&self.superblock, 160))?;
+        let max_volumes = (u32_at(&self.superblock, 180) as usize).min(100);
+        let mut volumes = Vec::new();
+        for i in 0..max_volumes{
+            let oid = u64_at(&self.superblock, 184 + i*8);
+            let Some(&address) = omap.get(&oid) else{continue;};
+            if let Some(volume) = self.read_volume(address)?{
+                volumes.push(volume);
+            }
+        }
+        let many = volumes.len() > 1;
+        for volume in volumes{
+            let mut parent = 0;
+            if many{
+                self.files.push(ApfsFile{name: volume.name.clone(), parent: 0, is_dir: true, ..Default::default()});
+                let name = self.directories[0].name.clone() + &volume.name + "/";
+                self.directories.push(Directory{name});
+                parent = self.directories.len() as u32 - 1;
+            }
+            self.visited = HashSet::from([ROOT_DIR_INO_NUM]);
+            self.index(&volume, ROOT_DIR_INO_NUM, parent);
+        }
+        Ok(self)
+    }
+    fn index(self: &mut Self, volume: &Volume, dir: u64, parent: u32){
+        let Some(children) = volume.children.get(&dir) else{
+            return;
+        };
+        let mut new_dirs = Vec::new();
+        for (name, id, is_dir) in children{
+            let inode = volume.inodes.get(id).cloned().unwrap_or_default();
+            let mut full_name = self.directories[parent as usize].name.clone() + name;
+            if *is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(ApfsFile{
+                name: name.clone(),
+                parent,
+                size: inode.size,
+                is_dir: *is_dir,
+                create_timestamp: inode.create_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if *is_dir && self.visited.insert(*id){
+                new_dirs.push((full_name, *id));
+            }
+        }
+        for (name, id) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(volume, id, self.directories.len() as u32 - 1);
+        }
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct ApfsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_apfs_files_to_files(f: &ApfsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// Unencrypted volumes only
+pub struct ApfsIndexer;
+impl FilesystemIndexer for ApfsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Apfs"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[32..36] == b"NXSB"
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = ApfsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_apfs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
@@ -0,0 +1,841 @@
+use std::collections::HashMap;
+use std::os::unix::fs::FileExt;
+use chrono::NaiveDate;
+use crate::ntfs::filetime_to_epoch;
+use crate::{Attributes, Directory, File, inflate, lzma, u16_at, u32_at, u64_at, zstd};
+
+// The following code lists the members of the archives found on a drive, they are added as children of
+// a directory named like the archive followed by "!/" (/mnt/data/backup.zip!/docs/notes.txt)
+// zip: https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+// tar: https://www.gnu.org/software/tar/manual/html_node/Standard.html (ustar with the GNU and pax extensions)
+// 7z: 7zFormat.txt from the LZMA SDK https://www.7-zip.org/sdk.html
+// Archives are opened through their path, so only the archives of mounted drives can be read
+
+const ZIP_END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+const ZIP64_LOCATOR: &[u8] = b"PK\x06\x07";
+const ZIP64_END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x06\x06";
+const ZIP_CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const ZIP_LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+/// The end of central directory record is 22 bytes followed by a comment of up to 65535 bytes
+const ZIP_MAX_TAIL: u64 = 22 + 0xFFFF;
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+const ZIP64_EXTRA: u16 = 0x0001;
+const NTFS_EXTRA: u16 = 0x000A;
+const EXTENDED_TIMESTAMP_EXTRA: u16 = 0x5455;
+const TAR_BLOCK: u64 = 512;
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+const SEVEN_ZIP_SIGNATURE_HEADER_SIZE: u64 = 32;
+// 7z property ids
+const K_END: u64 = 0x00;
+const K_HEADER: u64 = 0x01;
+const K_ARCHIVE_PROPERTIES: u64 = 0x02;
+const K_ADDITIONAL_STREAMS_INFO: u64 = 0x03;
+const K_MAIN_STREAMS_INFO: u64 = 0x04;
+const K_FILES_INFO: u64 = 0x05;
+const K_PACK_INFO: u64 = 0x06;
+const K_UNPACK_INFO: u64 = 0x07;
+const K_SUBSTREAMS_INFO: u64 = 0x08;
+const K_SIZE: u64 = 0x09;
+const K_CRC: u64 = 0x0A;
+const K_FOLDER: u64 = 0x0B;
+const K_CODERS_UNPACK_SIZE: u64 = 0x0C;
+const K_NUM_UNPACK_STREAM: u64 = 0x0D;
+const K_EMPTY_STREAM: u64 = 0x0E;
+const K_EMPTY_FILE: u64 = 0x0F;
+const K_NAME: u64 = 0x11;
+const K_CTIME: u64 = 0x12;
+const K_MTIME: u64 = 0x14;
+const K_ATTRIBUTES: u64 = 0x15;
+const K_ENCODED_HEADER: u64 = 0x17;
+const METHOD_COPY: &[u8] = &[0x00];
+const METHOD_LZMA: &[u8] = &[0x03, 0x01, 0x01];
+const METHOD_LZMA2: &[u8] = &[0x21];
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind{
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    TarZstd,
+    SevenZip,
+}
+/// Archives are recognized by their extension, so that other files never get opened
+fn kind(name: &str) -> Option<Kind>{
+    let name = name.to_lowercase();
+    if name.ends_with(".zip"){
+        Some(Kind::Zip)
+    }else if name.ends_with(".tar"){
+        Some(Kind::Tar)
+    }else if name.ends_with(".tar.gz") || name.ends_with(".tgz"){
+        Some(Kind::TarGz)
+    }else if name.ends_with(".tar.xz") || name.ends_with(".txz"){
+        Some(Kind::TarXz)
+    }else if name.ends_with(".tar.zst") || name.ends_with(".tzst"){
+        Some(Kind::TarZstd)
+    }else if name.ends_with(".7z"){
+        Some(Kind::SevenZip)
+    }else{
+        None
+    }
+}
+/// Where an archive is read from: a file on the drive or the data of a member of another archive
+enum Source{
+    File(std::fs::File, u64),
+    Memory(Vec<u8>),
+}
+impl Source{
+    fn len(self: &Self) -> u64{
+        match self{
+            Source::File(_, len) => {*len}
+            Source::Memory(b) => {b.len() as u64}
+        }
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Option<Vec<u8>>{
+        // Sizes come from the archive, broken ones must not allocate more than the archive
+        if from.checked_add(size)? > self.len(){
+            return None;
+        }
+        match self{
+            Source::File(f, _) => {
+                let mut b = vec![0_u8; size as usize];
+                f.read_exact_at(&mut b, from).ok()?;
+                Some(b)
+            }
+            Source::Memory(b) => {Some(b[from as usize..(from + size) as usize].to_vec())}
+        }
+    }
+}
+/// Where the data of a member is, to open archives inside of archives
+#[derive(Debug, Clone, Copy)]
+enum Data{
+    /// Stored as is: offset and size
+    Stored(u64, u64),
+    /// Offset of the local header, compressed size and compression method
+    Zip(u64, u64, u16),
+}
+/// A member of an archive, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct Member{
+    path: String,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    data: Option<Data>,
+}
+
+/// Opens the archives among `files[first..]` (the files of the drive that was just indexed) and adds their members,
+/// archives bigger than `max_size` bytes are skipped and archives inside of archives are opened up to `max_depth` levels
+pub fn index_archives(files: &mut Vec<File>, directories: &mut Vec<Directory>, first: usize, max_size: u64, max_depth: u32){
+    if max_depth == 0{
+        return;
+    }
+    for i in first..files.len(){
+        // A deleted file's path may now be another file
+        if files[i].is_dir || files[i].size > max_size || files[i].deleted.is_some(){
+            continue;
+        }
+        let Some(kind) = kind(&files[i].name) else{
+            continue;
+        };
+        let path = directories[files[i].parent as usize].name.clone() + &files[i].name;
+        // Drives that aren't mounted (disk images) have no path to open the archive from
+        let Ok(file) = std::fs::File::open(&path) else{
+            continue;
+        };
+        let Ok(meta) = file.metadata() else{
+            continue;
+        };
+        add_archive(Source::File(file, meta.len()), kind, path, files, directories, max_size, max_depth);
+    }
+}
+/// Adds the members of an archive as children of "path!/", broken or unsupported archives are skipped.
+/// Archives that decompress to more than `max_size` bytes are skipped too
+fn add_archive(source: Source, kind: Kind, path: String, files: &mut Vec<File>, directories: &mut Vec<Directory>, max_size: u64, depth: u32){
+    let max = usize::try_from(max_size).unwrap_or(usize::MAX);
+    // Compressed tar archives have to be decompressed whole to find the headers
+    let source = match kind{
+        Kind::TarGz | Kind::TarXz | Kind::TarZstd => {
+            let Some(b) = source.read_bytes(0, source.len()) else{
+                return;
+            };
+            let tar = match kind{
+                Kind::TarGz => {gunzip(&b, max)}
+                Kind::TarXz => {lzma::unxz(&b, max)}
+                _ => {zstd::decompress(&b, max)}
+            };
+            let Some(tar) = tar else{
+                return;
+            };
+            Source::Memory(tar)
+        }
+        _ => {source}
+    };
+    let members = match kind{
+        Kind::Zip => {zip_members(&source)}
+        Kind::SevenZip => {seven_zip_members(&source, max)}
+        _ => {tar_members(&source)}
+    };
+    let Some(members) = members else{
+        return;
+    };
+    directories.push(Directory{name: path + "!/"});
+    let root = directories.len() as u32 - 1;
+    // Members can be in any order and their parent directories don't need to have their own entry,
+    // directories are created the first time they are seen (index in directories and in files)
+    let mut known_dirs: HashMap<String, (u32, usize)> = HashMap::new();
+    for m in members{
+        let parts: Vec<&str> = m.path.split('/').filter(|p| !p.is_empty() && *p != ".").collect();
+        let Some((name, parents)) = parts.split_last() else{
+            continue;
+        };
+        let mut parent = root;
+        let mut dir_path = String::new();
+        for p in parents.iter().chain(if m.is_dir{Some(name)}else{None}){
+            dir_path = dir_path + p + "/";
+            parent = match known_dirs.get(&dir_path){
+                Some(&(i, _)) => {i}
+                None => {
+                    files.push(File{name: p.to_string(), parent, size: 0, is_dir: true,
+                        create_timestamp: m.create_timestamp, last_modified_timestamp: m.last_modified_timestamp, deleted: None,
+                        attributes: Attributes::only_directory(true)});
+                    let name = directories[parent as usize].name.clone() + p + "/";
+                    directories.push(Directory{name});
+                    known_dirs.insert(dir_path.clone(), (directories.len() as u32 - 1, files.len() - 1));
+                    directories.len() as u32 - 1
+                }
+            };
+        }
+        if m.is_dir{
+            // The directory can be listed after its children, which created it with their timestamps
+            let (_, file) = known_dirs[&dir_path];
+            files[file].create_timestamp = m.create_timestamp;
+            files[file].last_modified_timestamp = m.last_modified_timestamp;
+            continue;
+        }
+        files.push(File{name: name.to_string(), parent, size: m.size, is_dir: false,
+            create_timestamp: m.create_timestamp, last_modified_timestamp: m.last_modified_timestamp, deleted: None,
+            attributes: Attributes::default()});
+        if depth > 1 && m.size <= max_size && let Some(nested) = self::kind(name) && let Some(data) = m.data
+            && let Some(b) = extract(&source, data, max){
+            let path = directories[parent as usize].name.clone() + name;
+            add_archive(Source::Memory(b), nested, path, files, directories, max_size, depth - 1);
+        }
+    }
+}
+/// Reads the data of a member, only needed for archives inside of archives.
+/// None if it decompresses to more than `max` bytes
+fn extract(source: &Source, data: Data, max: usize) -> Option<Vec<u8>>{
+    match data{
+        Data::Stored(offset, size) => {source.read_bytes(offset, size)}
+        Data::Zip(offset, compressed_size, method) => {
+            let header = source.read_bytes(offset, 30)?;
+            if &header[0..4] != ZIP_LOCAL_HEADER{
+                return None;
+            }
+            // The name and the extra field of the local header can differ from the central directory ones
+            let start = offset + 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+            let raw = source.read_bytes(start, compressed_size)?;
+            match method{
+                ZIP_STORED => {Some(raw)}
+                ZIP_DEFLATED => {
+                    let mut out = Vec::new();
+                    inflate::inflate(&raw, &mut out, max)?;
+                    Some(out)
+                }
+                _ => {None}
+            }
+        }
+    }
+}
+/// Skips the gzip header and inflates the first member, None if it is bigger than `max` bytes
+fn gunzip(b: &[u8], max: usize) -> Option<Vec<u8>>{
+    if b.len() < 10 || b[0..3] != [0x1F, 0x8B, 8]{
+        return None;
+    }
+    let flags = b[3];
+    let mut pos = 10;
+    // Optional fields: extra field, file name, comment and header crc
+    if flags & 0x4 != 0{
+        pos += 2 + u16::from_le_bytes([*b.get(pos)?, *b.get(pos + 1)?]) as usize;
+    }
+    for flag in [0x8, 0x10]{
+        if flags & flag != 0{
+            pos += b.get(pos..)?.iter().position(|&c| c == 0)? + 1;
+        }
+    }
+    if flags & 0x2 != 0{
+        pos += 2;
+    }
+    let mut out = Vec::new();
+    inflate::inflate(b.get(pos..)?, &mut out, max)?;
+    Some(out)
+}
+/// MS-DOS date and time, it has no timezone so it is read as UTC like the FAT backend does
+fn dos_time(time: u16, date: u16) -> i64{
+    NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, ((date >> 5) & 0xF) as u32, (date & 0x1F) as u32)
+        .and_then(|d| d.and_hms_opt((time >> 11) as u32, ((time >> 5) & 0x3F) as u32, ((time & 0x1F)*2) as u32))
+        .map(|t| t.and_utc().timestamp())
+        .unwrap_or(0)
+}
+/// Lists a zip archive through its central directory (at the end of the archive)
+fn zip_members(source: &Source) -> Option<Vec<Member>>{
+    let len = source.len();
+    let tail_size = len.min(ZIP_MAX_TAIL);
+    let tail = source.read_bytes(len - tail_size, tail_size)?;
+    let eocd = (0..=tail.len().checked_sub(22)?).rev().find(|&i| &tail[i..i + 4] == ZIP_END_OF_CENTRAL_DIRECTORY)?;
+    let mut entries = u16_at(&tail, eocd + 10) as u64;
+    let mut directory_size = u32_at(&tail, eocd + 12) as u64;
+    let mut directory_offset = u32_at(&tail, eocd + 16) as u64;
+    // Zip64 archives keep the real values in another record, found through the locator right before this one
+    if eocd >= 20 && &tail[eocd - 20..eocd - 16] == ZIP64_LOCATOR{
+        let record = source.read_bytes(u64_at(&tail, eocd - 12), 56)?;
+        if &record[0..4] == ZIP64_END_OF_CENTRAL_DIRECTORY{
+            entries = u64_at(&record, 32);
+            directory_size = u64_at(&record, 40);
+            directory_offset = u64_at(&record, 48);
+        }
+    }
+    let cd = source.read_bytes(directory_offset, directory_size)?;
+    let mut members = Vec::new();
+    let mut i = 0;
+    for _ in 0..entries{
+        if i + 46 > cd.len() || &cd[i..i + 4] != ZIP_CENTRAL_HEADER{
+            break;
+        }
+        let flags = u16_at(&cd, i + 8);
+        let method = u16_at(&cd, i + 10);
+        let mut compressed_size = u32_at(&cd, i + 20) as u64;
+        let mut size = u32_at(&cd, i + 24) as u64;
+        let name_len = u16_at(&cd, i + 28) as usize;
+        let extra_len = u16_at(&cd, i + 30) as usize;
+        let comment_len = u16_at(&cd, i + 32) as usize;
+        let external_attributes = u32_at(&cd, i + 38);
+        let mut offset = u32_at(&cd, i + 42) as u64;
+        let name = cd.get(i + 46..i + 46 + name_len)?;
+        let extra = cd.get(i + 46 + name_len..i + 46 + name_len + extra_len)?;
+        let mut last_modified_timestamp = dos_time(u16_at(&cd, i + 12), u16_at(&cd, i + 14));
+        let mut create_timestamp = None;
+        let mut j = 0;
+        while j + 4 <= extra.len(){
+            let id = u16_at(extra, j);
+            let Some(field) = extra.get(j + 4..j + 4 + u16_at(extra, j + 2) as usize) else{
+                break;
+            };
+            match id{
+                // Only the values that didn't fit are there, in this order
+                ZIP64_EXTRA => {
+                    let mut k = 0;
+                    for v in [&mut size, &mut compressed_size, &mut offset]{
+                        if *v == 0xFFFF_FFFF && k + 8 <= field.len(){
+                            *v = u64_at(field, k);
+                            k += 8;
+                        }
+                    }
+                }
+                // Reserved, then a tag with modification, access and creation FILETIMEs
+                NTFS_EXTRA if field.len() >= 32 && u16_at(field, 4) == 1 && u16_at(field, 6) >= 24 => {
+                    last_modified_timestamp = filetime_to_epoch(u64_at(field, 8));
+                    create_timestamp = Some(filetime_to_epoch(u64_at(field, 24)));
+                }
+                // Unix timestamps, the central directory only has the modification time
+                EXTENDED_TIMESTAMP_EXTRA if field.len() >= 5 && field[0] & 1 != 0 => {
+                    last_modified_timestamp = u32_at(field, 1) as i32 as i64;
+                }
+                _ => {}
+            }
+            j += 4 + field.len();
+        }
+        let path = String::from_utf8_lossy(name).to_string();
+        // Zips made on MS-DOS/Windows can mark directories only with the attribute
+        let is_dir = path.ends_with('/') || (u16_at(&cd, i + 4) >> 8 == 0 && external_attributes & FILE_ATTRIBUTE_DIRECTORY != 0);
+        members.push(Member{
+            path,
+            size,
+            is_dir,
+            create_timestamp: create_timestamp.unwrap_or(last_modified_timestamp),
+            last_modified_timestamp,
+            // Encrypted members can't be opened
+            data: if flags & 1 == 0{Some(Data::Zip(offset, compressed_size, method))}else{None}
+        });
+        i += 46 + name_len + extra_len + comment_len;
+    }
+    Some(members)
+}
+/// Numbers are octal text, GNU tar stores the ones that don't fit in base 256 (highest bit of the first byte set)
+fn tar_number(b: &[u8]) -> u64{
+    if b[0] & 0x80 != 0{
+        return b[1..].iter().fold(0, |v, &c| (v << 8) | c as u64);
+    }
+    let s = String::from_utf8_lossy(b);
+    u64::from_str_radix(s.trim_matches(|c: char| c == '\0' || c == ' '), 8).unwrap_or(0)
+}
+/// The checksum is the sum of the bytes of the header, with the checksum itself counted as spaces
+fn tar_checksum_ok(h: &[u8]) -> bool{
+    let sum: u64 = h.iter().enumerate().map(|(i, &c)| if (148..156).contains(&i){b' ' as u64}else{c as u64}).sum();
+    sum == tar_number(&h[148..156])
+}
+fn c_string(b: &[u8]) -> String{
+    String::from_utf8_lossy(&b[..b.iter().position(|&c| c == 0).unwrap_or(b.len())]).to_string()
+}
+/// Pax records look like "30 mtime=1700000000.123456789\n", the length counts the whole record
+fn pax_records(b: &[u8], pax: &mut HashMap<String, String>){
+    let mut i = 0;
+    while i < b.len(){
+        let Some(space) = b[i..].iter().position(|&c| c == b' ') else{
+            break;
+        };
+        let Some(len) = std::str::from_utf8(&b[i..i + space]).ok().and_then(|l| l.parse::<usize>().ok()) else{
+            break;
+        };
+        let Some(record) = b.get(i + space + 1..i + len) else{
+            break;
+        };
+        let record = String::from_utf8_lossy(record);
+        if let Some((key, value)) = record.trim_end_matches('\n').split_once('='){
+            pax.insert(key.to_string(), value.to_string());
+        }
+        i += len;
+    }
+}
+/// Lists a tar archive by walking its headers, each one is followed by the data of its member
+fn tar_members(source: &Source) -> Option<Vec<Member>>{
+    let mut members = Vec::new();
+    let mut pos = 0;
+    let mut long_name: Option<String> = None;
+    let mut pax: HashMap<String, String> = HashMap::new();
+    while pos + TAR_BLOCK <= source.len(){
+        let h = source.read_bytes(pos, TAR_BLOCK)?;
+        // The archive ends with empty blocks
+        if h.iter().all(|&c| c == 0){
+            break;
+        }
+        if !tar_checksum_ok(&h){
+            // Not a tar archive at all, or a broken one which is listed up to here
+            if members.is_empty(){
+                return None;
+            }
+            break;
+        }
+        let type_flag = h[156];
+        let size = pax.get("size").and_then(|s| s.parse().ok()).unwrap_or(tar_number(&h[124..136]));
+        let data = pos + TAR_BLOCK;
+        pos = data + size.next_multiple_of(TAR_BLOCK);
+        match type_flag{
+            // GNU long names are the data of a fake member right before the real one
+            b'L' => {
+                long_name = Some(c_string(&source.read_bytes(data, size)?));
+                continue;
+            }
+            b'x' => {
+                pax_records(&source.read_bytes(data, size)?, &mut pax);
+                continue;
+            }
+            // Global pax headers, GNU long link names and volume labels
+            b'g' | b'K' | b'V' => {continue;}
+            _ => {}
+        }
+        let path = match (pax.remove("path"), long_name.take()){
+            (Some(path), _) => {path}
+            (None, Some(path)) => {path}
+            _ => {
+                let name = c_string(&h[0..100]);
+                let prefix = c_string(&h[345..500]);
+                if &h[257..262] == b"ustar" && !prefix.is_empty(){prefix + "/" + &name}else{name}
+            }
+        };
+        let last_modified_timestamp = pax.get("mtime").and_then(|t| t.split('.').next()?.parse().ok())
+            .unwrap_or(tar_number(&h[136..148]) as i64);
+        pax.clear();
+        // Links, devices and fifos have no data
+        let is_file = matches!(type_flag, b'0' | 0 | b'7');
+        members.push(Member{
+            is_dir: type_flag == b'5' || path.ends_with('/'),
+            path,
+            size: if is_file{size}else{0},
+            // tar only stores the modification time
+            create_timestamp: last_modified_timestamp,
+            last_modified_timestamp,
+            data: if is_file{Some(Data::Stored(data, size))}else{None}
+        });
+    }
+    Some(members)
+}
+struct Reader<'a>{
+    b: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a>{
+    fn byte(self: &mut Self) -> Option<u8>{
+        let c = *self.b.get(self.pos)?;
+        self.pos += 1;
+        Some(c)
+    }
+    fn bytes(self: &mut Self, n: u64) -> Option<&'a [u8]>{
+        let s = self.b.get(self.pos..self.pos.checked_add(n as usize)?)?;
+        self.pos += n as usize;
+        Some(s)
+    }
+    /// Numbers take from 1 to 9 bytes, the high bits of the first byte tell how many bytes follow
+    /// and its remaining bits are the highest bits of the number
+    fn number(self: &mut Self) -> Option<u64>{
+        let first = self.byte()?;
+        let mut value = 0;
+        for i in 0..8{
+            let mask = 0x80 >> i;
+            if first & mask == 0{
+                return Some(value | (((first & (mask - 1)) as u64) << (8*i)));
+            }
+            value |= (self.byte()? as u64) << (8*i);
+        }
+        Some(value)
+    }
+    /// A number of items, which can't be more than the bytes of the header
+    fn count(self: &mut Self) -> Option<usize>{
+        let n = self.number()?;
+        if n > self.b.len() as u64{
+            return None;
+        }
+        Some(n as usize)
+    }
+    fn bits(self: &mut Self, n: usize) -> Option<Vec<bool>>{
+        let mut v = Vec::with_capacity(n);
+        let mut byte = 0;
+        for i in 0..n{
+            if i % 8 == 0{
+                byte = self.byte()?;
+            }
+            v.push(byte & (0x80 >> (i % 8)) != 0);
+        }
+        Some(v)
+    }
+    /// Which items are defined, a first byte that isn't 0 means that all of them are
+    fn defined(self: &mut Self, n: usize) -> Option<Vec<bool>>{
+        if self.byte()? != 0{
+            return Some(vec![true; n]);
+        }
+        self.bits(n)
+    }
+    fn skip_crcs(self: &mut Self, n: usize) -> Option<Vec<bool>>{
+        let defined = self.defined(n)?;
+        self.bytes(4*defined.iter().filter(|&&d| d).count() as u64)?;
+        Some(defined)
+    }
+}
+/// A chain of coders which unpacks one or more packed streams
+#[derive(Debug, Default)]
+struct Folder{
+    /// Method id and properties of each coder
+    coders: Vec<(Vec<u8>, Vec<u8>)>,
+    outputs: usize,
+    unpack_sizes: Vec<u64>,
+    /// The output of the folder is the only output of its coders that isn't the input of another coder
+    main_output: usize,
+    crc_defined: bool,
+}
+impl Folder{
+    fn unpack_size(self: &Self) -> u64{
+        self.unpack_sizes.get(self.main_output).copied().unwrap_or(0)
+    }
+}
+#[derive(Debug, Default)]
+struct StreamsInfo{
+    pack_pos: u64,
+    pack_sizes: Vec<u64>,
+    folders: Vec<Folder>,
+    /// Size of every file that has data, in order
+    sizes: Vec<u64>,
+}
+fn read_folder(r: &mut Reader) -> Option<Folder>{
+    let mut folder = Folder::default();
+    let mut inputs = 0;
+    for _ in 0..r.count()?{
+        let flags = r.byte()?;
+        // Alternative methods were never used by 7-Zip
+        if flags & 0x80 != 0{
+            return None;
+        }
+        let id = r.bytes((flags & 0xF) as u64)?.to_vec();
+        if flags & 0x10 != 0{
+            inputs += r.count()?;
+            folder.outputs += r.count()?;
+        }else{
+            inputs += 1;
+            folder.outputs += 1;
+        }
+        let properties = if flags & 0x20 != 0{
+            let n = r.number()?;
+            r.bytes(n)?.to_vec()
+        }else{
+            Vec::new()
+        };
+        folder.coders.push((id, properties));
+    }
+    // Bind pairs connect an output of a coder to the input of another one
+    let mut bound = Vec::new();
+    for _ in 0..folder.outputs.checked_sub(1)?{
+        r.number()?;
+        bound.push(r.number()? as usize);
+    }
+    let packed_streams = inputs.checked_sub(folder.outputs - 1)?;
+    if packed_streams > 1{
+        for _ in 0..packed_streams{
+            r.number()?;
+        }
+    }
+    folder.main_output = (0..folder.outputs).find(|o| !bound.contains(o))?;
+    Some(folder)
+}
+fn read_streams_info(r: &mut Reader) -> Option<StreamsInfo>{
+    let mut s = StreamsInfo::default();
+    let mut has_substreams = false;
+    loop{
+        match r.number()?{
+            K_END => {break;}
+            K_PACK_INFO => {
+                s.pack_pos = r.number()?;
+                let n = r.count()?;
+                loop{
+                    match r.number()?{
+                        K_END => {break;}
+                        K_SIZE => {
+                            for _ in 0..n{
+                                s.pack_sizes.push(r.number()?);
+                            }
+                        }
+                        K_CRC => {r.skip_crcs(n)?;}
+                        _ => {return None;}
+                    }
+                }
+            }
+            K_UNPACK_INFO => {
+                // External folders (stored in another stream) are never made
+                if r.number()? != K_FOLDER{
+                    return None;
+                }
+                let n = r.count()?;
+                if r.byte()? != 0{
+                    return None;
+                }
+                for _ in 0..n{
+                    s.folders.push(read_folder(r)?);
+                }
+                if r.number()? != K_CODERS_UNPACK_SIZE{
+                    return None;
+                }
+                for folder in &mut s.folders{
+                    for _ in 0..folder.outputs{
+                        folder.unpack_sizes.push(r.number()?);
+                    }
+                }
+                loop{
+                    match r.number()?{
+                        K_END => {break;}
+                        K_CRC => {
+                            let defined = r.skip_crcs(n)?;
+                            for (folder, defined) in s.folders.iter_mut().zip(defined){
+                                folder.crc_defined = defined;
+                            }
+                        }
+                        _ => {return None;}
+                    }
+                }
+            }
+            // A folder can unpack more than one file
+            K_SUBSTREAMS_INFO => {
+                has_substreams = true;
+                let mut counts = vec![1; s.folders.len()];
+                let mut has_sizes = false;
+                loop{
+                    match r.number()?{
+                        K_END => {break;}
+                        K_NUM_UNPACK_STREAM => {
+                            for c in counts.iter_mut(){
+                                *c = r.count()?;
+                            }
+                        }
+                        // Every size but the last one of each folder, which is what is left
+                        K_SIZE => {
+                            has_sizes = true;
+                            for (folder, &count) in s.folders.iter().zip(&counts){
+                                if count == 0{
+                                    continue;
+                                }
+                                let mut sum = 0;
+                                for _ in 1..count{
+                                    let size = r.number()?;
+                                    sum += size;
+                                    s.sizes.push(size);
+                                }
+                                s.sizes.push(folder.unpack_size().checked_sub(sum)?);
+                            }
+                        }
+                        // Folders with a single file have its crc already
+                        K_CRC => {
+                            let n = s.folders.iter().zip(&counts).map(|(f, &c)| if c == 1 && f.crc_defined{0}else{c}).sum();
+                            r.skip_crcs(n)?;
+                        }
+                        _ => {return None;}
+                    }
+                }
+                if !has_sizes{
+                    for (folder, &count) in s.folders.iter().zip(&counts){
+                        if count == 1{
+                            s.sizes.push(folder.unpack_size());
+                        }
+                    }
+                }
+            }
+            _ => {return None;}
+        }
+    }
+    if !has_substreams{
+        s.sizes = s.folders.iter().map(|f| f.unpack_size()).collect();
+    }
+    Some(s)
+}
+/// Unpacks the first folder, used for compressed headers (which only use a single coder).
+/// None if it is bigger than `max` bytes
+fn unpack_folder(source: &Source, s: &StreamsInfo, max: usize) -> Option<Vec<u8>>{
+    let folder = s.folders.first()?;
+    if folder.coders.len() != 1{
+        return None;
+    }
+    let packed = source.read_bytes(SEVEN_ZIP_SIGNATURE_HEADER_SIZE.checked_add(s.pack_pos)?, *s.pack_sizes.first()?)?;
+    let (id, properties) = &folder.coders[0];
+    match id.as_slice(){
+        METHOD_COPY => {Some(packed)}
+        METHOD_LZMA => {
+            // .lzma files start with the same properties followed by the unpacked size
+            if properties.len() != 5{
+                return None;
+            }
+            let mut b = properties.clone();
+            b.extend_from_slice(&folder.unpack_size().to_le_bytes());
+            b.extend_from_slice(&packed);
+            lzma::unlzma(&b, max)
+        }
+        METHOD_LZMA2 => {
+            let mut out = Vec::new();
+            lzma::lzma2(&packed, &mut out, max)?;
+            Some(out)
+        }
+        _ => {None}
+    }
+}
+fn read_files_info(r: &mut Reader, sizes: &[u64]) -> Option<Vec<Member>>{
+    let n = r.count()?;
+    let mut empty_stream = vec![false; n];
+    let mut empty_file = Vec::new();
+    let mut names = Vec::new();
+    let mut create_timestamps = vec![None; n];
+    let mut modified_timestamps = vec![None; n];
+    let mut attributes = vec![None; n];
+    loop{
+        let property = r.number()?;
+        if property == K_END{
+            break;
+        }
+        let size = r.number()?;
+        let mut p = Reader{b: r.bytes(size)?, pos: 0};
+        match property{
+            K_EMPTY_STREAM => {empty_stream = p.bits(n)?;}
+            K_EMPTY_FILE => {empty_file = p.bits(empty_stream.iter().filter(|&&e| e).count())?;}
+            // UTF-16 names, each one ends with a 0
+            K_NAME => {
+                if p.byte()? != 0{
+                    return None;
+                }
+                let utf16: Vec<u16> = p.b[p.pos..].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                names = utf16.split(|&c| c == 0).map(String::from_utf16_lossy).collect();
+            }
+            K_CTIME | K_MTIME => {
+                let defined = p.defined(n)?;
+                if p.byte()? != 0{
+                    return None;
+                }
+                let timestamps = if property == K_CTIME{&mut create_timestamps}else{&mut modified_timestamps};
+                for (i, defined) in defined.into_iter().enumerate(){
+                    if defined{
+                        timestamps[i] = Some(filetime_to_epoch(u64_at(p.bytes(8)?, 0)));
+                    }
+                }
+            }
+            K_ATTRIBUTES => {
+                let defined = p.defined(n)?;
+                if p.byte()? != 0{
+                    return None;
+                }
+                for (i, defined) in defined.into_iter().enumerate(){
+                    if defined{
+                        attributes[i] = Some(u32_at(p.bytes(4)?, 0));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut members = Vec::new();
+    let mut sizes = sizes.iter();
+    let mut empty_index = 0;
+    for i in 0..n{
+        // 7-Zip on Windows can store its own path separator
+        let mut m = Member{path: names.get(i)?.replace('\\', "/"), ..Default::default()};
+        // Files without data are directories unless they are marked as empty files
+        if empty_stream[i]{
+            m.is_dir = !empty_file.get(empty_index).copied().unwrap_or(false);
+            empty_index += 1;
+        }else{
+            m.size = *sizes.next()?;
+        }
+        if attributes[i].is_some_and(|a| a & FILE_ATTRIBUTE_DIRECTORY != 0){
+            m.is_dir = true;
+        }
+        m.last_modified_timestamp = modified_timestamps[i].unwrap_or(0);
+        m.create_timestamp = create_timestamps[i].unwrap_or(m.last_modified_timestamp);
+        members.push(m);
+    }
+    Some(members)
+}
+/// Lists a 7z archive through its header (at the end of the archive), archives inside of 7z archives aren't opened
+/// because the data of the files is compressed in solid blocks. None if the header unpacks to more than `max` bytes
+fn seven_zip_members(source: &Source, max: usize) -> Option<Vec<Member>>{
+    let start = source.read_bytes(0, SEVEN_ZIP_SIGNATURE_HEADER_SIZE)?;
+    if &start[0..6] != SEVEN_ZIP_MAGIC{
+        return None;
+    }
+    let mut header = source.read_bytes(SEVEN_ZIP_SIGNATURE_HEADER_SIZE.checked_add(u64_at(&start, 12))?, u64_at(&start, 20))?;
+    // The header is usually compressed, it is then described the same way the data of the files is
+    loop{
+        let mut r = Reader{b: &header, pos: 0};
+        match r.number()?{
+            K_ENCODED_HEADER => {
+                let s = read_streams_info(&mut r)?;
+                header = unpack_folder(source, &s, max)?;
+            }
+            K_HEADER => {
+                let mut streams = StreamsInfo::default();
+                let mut members = Vec::new();
+                loop{
+                    match r.number()?{
+                        K_END => {return Some(members);}
+                        K_ARCHIVE_PROPERTIES => {
+                            while r.number()? != K_END{
+                                let n = r.number()?;
+                                r.bytes(n)?;
+                            }
+                        }
+                        K_ADDITIONAL_STREAMS_INFO => {read_streams_info(&mut r)?;}
+                        K_MAIN_STREAMS_INFO => {streams = read_streams_info(&mut r)?;}
+                        K_FILES_INFO => {members = read_files_info(&mut r, &streams.sizes)?;}
+                        _ => {return None;}
+                    }
+                }
+            }
+            _ => {return None;}
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Seek};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use crate::{u16_at, u32_at, u64_at};
+use crate::inflate::inflate;
+
+// The following code reads the virtual disk inside of VM disk images so that filesystems can be read
+// without attaching the image:
+// VHD (https://learn.microsoft.com/en-us/windows/win32/vstor/about-vhd)
+// VHDX (MS-VHDX https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-vhdx)
+// qcow2 (https://gitlab.com/qemu-project/qemu/-/blob/master/docs/interop/qcow2.txt)
+// Fixed VHDs are a raw disk followed by a 512 bytes footer so they are read as raw files.
+// Differencing VHD/VHDX images are not supported, qcow2 backing files are
+
+const VHD_DYNAMIC: u32 = 3;
+const VHDX_BAT_GUID: [u8; 16] = [0x66, 0x77, 0xC2, 0x2D, 0x23, 0xF6, 0x00, 0x42, 0x9D, 0x64, 0x11, 0x5E, 0x9B, 0xFD, 0x4A, 0x08];
+const VHDX_METADATA_GUID: [u8; 16] = [0x06, 0xA2, 0x7C, 0x8B, 0x90, 0x47, 0x9A, 0x4B, 0xB8, 0xFE, 0x57, 0x5F, 0x05, 0x0F, 0x88, 0x6E];
+const VHDX_FILE_PARAMETERS_GUID: [u8; 16] = [0x37, 0x67, 0xA1, 0xCA, 0x36, 0xFA, 0x43, 0x4D, 0xB3, 0xB6, 0x33, 0xF0, 0xAA, 0x44, 0xE7, 0x6B];
+const VHDX_VIRTUAL_DISK_SIZE_GUID: [u8; 16] = [0x24, 0x42, 0xA5, 0x2F, 0x1B, 0xCD, 0x76, 0x48, 0xB2, 0x11, 0x5D, 0xBE, 0xD8, 0x3B, 0xF4, 0xB8];
+const VHDX_LOGICAL_SECTOR_SIZE_GUID: [u8; 16] = [0x1D, 0xBF, 0x41, 0x81, 0x6F, 0xA9, 0x09, 0x47, 0xBA, 0x47, 0xF2, 0x33, 0xA8, 0xFA, 0xAB, 0x5F];
+/// VHDX block states that have data in the file
+const VHDX_FULLY_PRESENT: u64 = 6;
+const QCOW2_OFFSET_MASK: u64 = 0x00FF_FFFF_FFFF_FE00;
+const QCOW2_COMPRESSED: u64 = 1 << 62;
+const QCOW2_ZERO: u64 = 1;
+const QCOW2_INCOMPAT_EXTERNAL_DATA: u64 = 1 << 2;
+const QCOW2_INCOMPAT_EXTENDED_L2: u64 = 1 << 4;
+/// A longer chain of qcow2 backing files is an image that is (indirectly) its own backing file
+const QCOW2_MAX_BACKING_CHAIN: u32 = 16;
+
+fn be32(b: &[u8], i: usize) -> u32{
+    u32::from_be_bytes([b[i], b[i+1], b[i+2], b[i+3]])
+}
+fn be64(b: &[u8], i: usize) -> u64{
+    u64::from_be_bytes([b[i], b[i+1], b[i+2], b[i+3], b[i+4], b[i+5], b[i+6], b[i+7]])
+}
+fn unsupported(what: &str) -> io::Error{
+    io::Error::new(io::ErrorKind::Unsupported, what.to_string())
+}
+fn invalid(what: &str) -> io::Error{
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+/// Reads a table whose size comes from a header, a broken header can't make it allocate more than the file has
+fn read_table(file: &fs::File, from: u64, size: usize) -> io::Result<Vec<u8>>{
+    if from.saturating_add(size as u64) > file.metadata()?.len(){
+        return Err(invalid("A table of the image is past the end of the file"));
+    }
+    read_vec(file, from, size)
+}
+fn read_vec(file: &fs::File, from: u64, size: usize) -> io::Result<Vec<u8>>{
+    let mut b = vec![0u8; size];
+    file.read_exact_at(&mut b, from)?;
+    Ok(b)
+}
+/// Splits a read into pieces that don't cross a block, `fill(block, offset inside of the block, piece)`
+/// fills every piece. Returns how many bytes were read (nothing past the end of the virtual disk)
+fn read_in_blocks(buf: &mut [u8], offset: u64, size: u64, block_size: u64,
+    mut fill: impl FnMut(u64, u64, &mut [u8]) -> io::Result<()>) -> io::Result<usize>{
+    if offset >= size{
+        return Ok(0);
+    }
+    let len = buf.len().min((size - offset) as usize);
+    let mut done = 0;
+    while done < len{
+        let pos = offset + done as u64;
+        let in_block = pos % block_size;
+        let n = (len - done).min((block_size - in_block) as usize);
+        fill(pos / block_size, in_block, &mut buf[done..done+n])?;
+        done += n;
+    }
+    Ok(len)
+}
+pub struct Vhd{
+    file: fs::File,
+    size: u64,
+    block_size: u64,
+    /// Every block has a sector bitmap before its data
+    bitmap_size: u64,
+    /// Sector of every block in the file, 0xFFFFFFFF if the block was never written
+    bat: Vec<u32>,
+}
+impl Vhd{
+    fn open(file: fs::File, footer: &[u8]) -> io::Result<Vhd>{
+        if be32(footer, 60) != VHD_DYNAMIC{
+            return Err(unsupported("Differencing VHDs are not supported"));
+        }
+        let size = be64(footer, 48);
+        let header = read_vec(&file, be64(footer, 16), 1024)?;
+        if &header[0..8] != b"cxsparse"{
+            return Err(invalid("Broken VHD dynamic header"));
+        }
+        let bat_offset = be64(&header, 16);
+        let entries = be32(&header, 28) as usize;
+        let block_size = be32(&header, 32) as u64;
+        if block_size < 512 || !block_size.is_power_of_two(){
+            return Err(invalid("Invalid VHD block size"));
+        }
+        let bat = read_table(&file, bat_offset, entries*4)?.chunks_exact(4).map(|e| be32(e, 0)).collect();
+        let bitmap_size = (block_size / 512 / 8).div_ceil(512) * 512;
+        Ok(Vhd{file, size, block_size, bitmap_size, bat})
+    }
+    fn read_at(self: &Self, buf: &mut [u8], offset: u64) -> io::Result<usize>{
+        read_in_blocks(buf, offset, self.size, self.block_size, |block, in_block, piece|{
+            match self.bat.get(block as usize){
+                Some(&sector) if sector != u32::MAX => {
+                    self.file.read_exact_at(piece, sector as u64*512 + self.bitmap_size + in_block)
+                }
+                _ => {piece.fill(0); Ok(())}
+            }
+        })
+    }
+}
+pub struct Vhdx{
+    file: fs::File,
+    size: u64,
+    block_size: u64,
+    /// Every chunk_ratio data blocks the BAT has an entry for a sector bitmap
+    chunk_ratio: u64,
+    bat: Vec<u64>,
+}
+impl Vhdx{
+    fn open(file: fs::File) -> io::Result<Vhdx>{
+        // Both copies of the region table should be the same, the first one is used
+        let regions = read_vec(&file, 192*1024, 64*1024)?;
+        if &regions[0..4] != b"regi"{
+            return Err(invalid("Broken VHDX region table"));
+        }
+        let mut bat_region = None;
+        let mut metadata_region = None;
+        for i in 0..(u32_at(&regions, 8) as usize).min(2047){
+            let e = &regions[16 + i*32..48 + i*32];
+            let region = (u64_at(e, 16), u32_at(e, 24) as usize);
+            if e[0..16] == VHDX_BAT_GUID{
+                bat_region = Some(region);
+            }else if e[0..16] == VHDX_METADATA_GUID{
+                metadata_region = Some(region);
+            }
+        }
+        let (Some(bat_region), Some(metadata_region)) = (bat_region, metadata_region) else{
+            return Err(invalid("VHDX without BAT or metadata"));
+        };
+        let metadata = read_table(&file, metadata_region.0, metadata_region.1)?;
+        let item = |at: usize, len: usize| metadata.get(at..at + len).ok_or_else(|| invalid("Broken VHDX metadata"));
+        let mut block_size = 0;
+        let mut size = 0;
+        let mut sector_size = 512;
+        for i in 0..u16_at(item(0, 12)?, 10) as usize{
+            let e = item(32 + i*32, 32)?;
+            let at = u32_at(e, 16) as usize;
+            if e[0..16] == VHDX_FILE_PARAMETERS_GUID{
+                let parameters = item(at, 8)?;
+                block_size = u32_at(parameters, 0) as u64;
+                // Bit 1: has a parent (differencing disk)
+                if u32_at(parameters, 4) & 2 != 0{
+                    return Err(unsupported("Differencing VHDXs are not supported"));
+                }
+            }else if e[0..16] == VHDX_VIRTUAL_DISK_SIZE_GUID{
+                size = u64_at(item(at, 8)?, 0);
+            }else if e[0..16] == VHDX_LOGICAL_SECTOR_SIZE_GUID{
+                sector_size = u32_at(item(at, 4)?, 0) as u64;
+            }
+        }
+        if block_size == 0{
+            return Err(invalid("VHDX without file parameters"));
+        }
+        // Blocks are a power of two from 1 MiB to 256 MiB, sectors are 512 or 4096 bytes
+        if !block_size.is_power_of_two() || !(1 << 20..=1 << 28).contains(&block_size) || !matches!(sector_size, 512 | 4096){
+            return Err(invalid("Invalid VHDX block or sector size"));
+        }
+        let chunk_ratio = (1 << 23) * sector_size / block_size;
+        let bat = read_table(&file, bat_region.0, bat_region.1)?.chunks_exact(8).map(|e| u64_at(e, 0)).collect();
+        Ok(Vhdx{file, size, block_size, chunk_ratio, bat})
+    }
+    fn read_at(self: &Self, buf: &mut [u8], offset: u64) -> io::Result<usize>{
+        read_in_blocks(buf, offset, self.size, self.block_size, |block, in_block, piece|{
+            match self.bat.get((block + block / self.chunk_ratio) as usize){
+                Some(&entry) if entry & 7 == VHDX_FULLY_PRESENT => {
+                    // The offset is in MiB
+                    self.file.read_exact_at(piece, (entry >> 20 << 20) + in_block)
+                }
+                // Not present, zero and unmapped blocks all read as zeros
+                _ => {piece.fill(0); Ok(())}
+            }
+        })
+    }
+}
+pub struct Qcow2{
+    file: fs::File,
+    size: u64,
+    cluster_bits: u32,
+    /// Bytes per L2 table entry (16 with extended L2 entries)
+    l2_entry_size: u64,
+    l1: Vec<u64>,
+    /// Unallocated clusters come from here
+    backing: Option<Box<BlockDevice>>,
+    /// Last compressed cluster that was decompressed (host offset, data)
+    last_compressed: RefCell<(u64, Vec<u8>)>,
+}
+impl Qcow2{
+    /// `depth` is how many images have this one as their backing file
+    fn open(file: fs::File, path: &str, depth: u32) -> io::Result<Qcow2>{
+        let header = read_vec(&file, 0, 112)?;
+        let version = be32(&header, 4);
+        let cluster_bits = be32(&header, 20);
+        // Clusters go from 512 bytes to 2 MiB
+        if !(9..=21).contains(&cluster_bits){
+            return Err(invalid("Invalid qcow2 cluster size"));
+        }
+        if be32(&header, 32) != 0{
+            return Err(unsupported("Encrypted qcow2 images are not supported"));
+        }
+        let incompatible = if version >= 3{be64(&header, 72)}else{0};
+        if incompatible & QCOW2_INCOMPAT_EXTERNAL_DATA != 0{
+            return Err(unsupported("qcow2 images with an external data file are not supported"));
+        }
+        // Only zlib compression, zstd is compression type 1
+        if version >= 3 && be32(&header, 100) > 104 && header[104] != 0{
+            return Err(unsupported("zstd compressed qcow2 images are not supported"));
+        }
+        let l1 = read_table(&file, be64(&header, 40), be32(&header, 36) as usize*8)?.chunks_exact(8).map(|e| be64(e, 0)).collect();
+        let backing_offset = be64(&header, 8);
+        let backing = if backing_offset != 0{
+            if depth >= QCOW2_MAX_BACKING_CHAIN{
+                return Err(invalid("The chain of qcow2 backing files loops"));
+            }
+            let name = read_table(&file, backing_offset, be32(&header, 16) as usize)?;
+            let name = String::from_utf8_lossy(&name).to_string();
+            // Relative backing files are relative to the image
+            let backing_path = Path::new(path).parent().unwrap_or(Path::new("")).join(&name);
+            Some(Box::new(open_image(&backing_path.to_string_lossy(), depth + 1)?))
+        }else{
+            None
+        };
+        Ok(Qcow2{
+            file,
+            size: be64(&header, 24),
+            cluster_bits,
+            l2_entry_size: if incompatible & QCOW2_INCOMPAT_EXTENDED_L2 != 0{16}else{8},
+            l1,
+            backing,
+            last_compressed: RefCell::new((0, Vec::new())),
+        })
+    }
+    fn read_compressed(self: &Self, entry: u64, in_cluster: u64, piece: &mut [u8]) -> io::Result<()>{
+        // The host offset uses the low bits, the number of additional 512 bytes sectors the rest
+        let offset_bits = 62 - (self.cluster_bits - 8);
+        let host = entry & ((1 << offset_bits) - 1);
+        let sectors = ((entry >> offset_bits) & ((1 << (self.cluster_bits - 8)) - 1)) + 1;
+        let mut cache = self.last_compressed.borrow_mut();
+        if cache.0 != host || cache.1.is_empty(){
+            let compressed_size = sectors*512 - (host & 511);
+            let mut compressed = vec![0u8; compressed_size as usize];
+            // The last compressed cluster can end before the sectors it claims
+            let n = self.file.read_at(&mut compressed, host)?;
+            compressed.truncate(n);
+            let mut data = Vec::with_capacity(1 << self.cluster_bits);
+            if inflate(&compressed, &mut data, 1 << self.cluster_bits).is_none(){
+                return Err(invalid("Broken compressed qcow2 cluster"));
+            }
+            data.resize(1 << self.cluster_bits, 0);
+            *cache = (host, data);
+        }
+        piece.copy_from_slice(&cache.1[in_cluster as usize..in_cluster as usize + piece.len()]);
+        Ok(())
+    }
+    fn read_at(self: &Self, buf: &mut [u8], offset: u64) -> io::Result<usize>{
+        let l2_bits = self.cluster_bits - self.l2_entry_size.trailing_zeros();
+        read_in_blocks(buf, offset, self.size, 1 << self.cluster_bits, |cluster, in_cluster, piece|{
+            let l2_table = self.l1.get((cluster >> l2_bits) as usize).map(|e| e & QCOW2_OFFSET_MASK).unwrap_or(0);
+            let entry = if l2_table != 0{
+                let b = read_vec(&self.file, l2_table + (cluster & ((1 << l2_bits) - 1))*self.l2_entry_size, 8)?;
+                be64(&b, 0)
+            }else{
+                0
+            };
+            if entry & QCOW2_COMPRESSED != 0{
+                return self.read_compressed(entry, in_cluster, piece);
+            }
+            if entry & QCOW2_ZERO != 0{
+                piece.fill(0);
+                return Ok(());
+            }
+            let host = entry & QCOW2_OFFSET_MASK;
+            if host != 0{
+                return self.file.read_exact_at(piece, host + in_cluster);
+            }
+            piece.fill(0);
+            if let Some(backing) = &self.backing{
+                // The backing file may be smaller than this image, what is past its end stays zero
+                backing.read_at(piece, (cluster << self.cluster_bits) + in_cluster)?;
+            }
+            Ok(())
+        })
+    }
+}
+/// What a drive (or disk image) is read through
+pub enum BlockDevice{
+    Raw(fs::File),
+    Vhd(Vhd),
+    Vhdx(Vhdx),
+    Qcow2(Qcow2),
+}
+impl BlockDevice{
+    pub fn read_at(self: &Self, buf: &mut [u8], offset: u64) -> io::Result<usize>{
+        match self{
+            BlockDevice::Raw(file) => {file.read_at(buf, offset)}
+            BlockDevice::Vhd(vhd) => {vhd.read_at(buf, offset)}
+            BlockDevice::Vhdx(vhdx) => {vhdx.read_at(buf, offset)}
+            BlockDevice::Qcow2(qcow2) => {qcow2.read_at(buf, offset)}
+        }
+    }
+    /// Size of the disk in bytes (of the virtual disk for images)
+    pub fn size(self: &Self) -> io::Result<u64>{
+        match self{
+            // Seeking works on block devices too, their metadata says 0 bytes
+            BlockDevice::Raw(file) => {(&*file).seek(io::SeekFrom::End(0))}
+            BlockDevice::Vhd(vhd) => {Ok(vhd.size)}
+            BlockDevice::Vhdx(vhdx) => {Ok(vhdx.size)}
+            BlockDevice::Qcow2(qcow2) => {Ok(qcow2.size)}
+        }
+    }
+}
+impl std::fmt::Debug for BlockDevice{
+    fn fmt(self: &Self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        match self{
+            BlockDevice::Raw(_) => {write!(f, "Raw")}
+            BlockDevice::Vhd(_) => {write!(f, "Vhd")}
+            BlockDevice::Vhdx(_) => {write!(f, "Vhdx")}
+            BlockDevice::Qcow2(_) => {write!(f, "Qcow2")}
+        }
+    }
+}
+/// Opens a drive or an image, the kind of image is recognized by its signature
+pub fn open(path: &str) -> io::Result<BlockDevice>{
+    open_image(path, 0)
+}
+/// Opens a drive or an image that is the backing file of `depth` qcow2 images
+fn open_image(path: &str, depth: u32) -> io::Result<BlockDevice>{
+    let file = fs::File::open(path)?;
+    let mut start = [0u8; 512];
+    // Drives that can't be read (or empty files) are left to whoever reads them
+    if file.read_exact_at(&mut start, 0).is_err(){
+        return Ok(BlockDevice::Raw(file));
+    }
+    if &start[0..8] == b"vhdxfile"{
+        return Ok(BlockDevice::Vhdx(Vhdx::open(file)?));
+    }
+    if &start[0..4] == b"QFI\xfb"{
+        return Ok(BlockDevice::Qcow2(Qcow2::open(file, path, depth)?));
+    }
+    // Dynamic VHDs have a copy of the footer at the start
+    if &start[0..8] == b"conectix"{
+        return Ok(BlockDevice::Vhd(Vhd::open(file, &start)?));
+    }
+    Ok(BlockDevice::Raw(file))
+}
@@ -0,0 +1,436 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at, u64_at};
+
+// The following code decodes btrfs by walking its B-trees following the on-disk format documentation
+// https://btrfs.readthedocs.io/en/latest/dev/On-disk-format.html
+// Only the stripes stored on this device are read, so filesystems spanning multiple devices
+// are only partially indexed
+
+const SUPERBLOCK_OFFSET: u64 = 0x10000;
+const HEADER_SIZE: usize = 0x65;
+const FS_TREE_OBJECTID: u64 = 5;
+/// Subvolumes have ids from 256 up to (u64::MAX - 255), the trees above that are internal (relocation, log...)
+const FIRST_FREE_OBJECTID: u64 = 256;
+const LAST_FREE_OBJECTID: u64 = u64::MAX - 255;
+const INODE_ITEM: u8 = 1;
+const DIR_INDEX: u8 = 96;
+const ROOT_ITEM: u8 = 132;
+const CHUNK_ITEM: u8 = 228;
+/// Directory entry type for directories
+const FT_DIR: u8 = 2;
+
+/// Key of an item inside of a tree
+#[derive(Debug, Default, Clone, Copy)]
+struct Key{
+    objectid: u64,
+    item_type: u8,
+    offset: u64,
+}
+fn key_at(b: &[u8], i: usize) -> Key{
+    Key{objectid: u64_at(b, i), item_type: b[i+8], offset: u64_at(b, i+9)}
+}
+/// Maps a range of logical addresses to where it is on this device
+#[derive(Debug, Default, Clone)]
+struct Chunk{
+    logical: u64,
+    length: u64,
+    physical: u64,
+}
+#[derive(Debug, Default, Clone)]
+struct InodeItem{
+    size: u64,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+#[derive(Debug, Default, Clone)]
+struct DirEntry{
+    /// Inode number, or the id of a subvolume tree if `is_subvolume`
+    location: u64,
+    is_subvolume: bool,
+    is_dir: bool,
+    name: String,
+}
+/// The inodes and directory entries of one subvolume (every subvolume has its own FS tree)
+#[derive(Debug, Default)]
+struct FsTree{
+    inodes: HashMap<u64, InodeItem>,
+    /// Directory inode -> entries sorted by their DIR_INDEX (creation order)
+    entries: HashMap<u64, Vec<(u64, DirEntry)>>,
+    id: u64,
+    root_dirid: u64,
+}
+struct BtrfsDrive{
+    file: PartitionFile,
+    device: String,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    nodesize: u64,
+    devid: u64,
+    chunks: Vec<Chunk>,
+    /// Tree id -> (logical address of its root node, root directory inode, how many references it has)
+    roots: HashMap<u64, (u64, u64, u32)>,
+    /// (tree, directory) already indexed, a damaged entry pointing back to a parent would loop forever
+    visited: HashSet<(u64, u64)>,
+    /// Root directory of the subvolumes already indexed, for the timestamps of the other entries pointing to them
+    indexed_subvolumes: HashMap<u64, InodeItem>,
+    /// Subvolumes found in the directory being indexed with the path they were found at, each one becomes
+    /// a top level directory once that directory is done
+    pending_subvolumes: Vec<(FsTree, String)>,
+    files: Vec<BtrfsFile>,
+    ignored_dirs: Vec<String>
+}
+impl BtrfsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut sb = vec![0u8; 4096];
+        file.read_at(&mut sb, SUPERBLOCK_OFFSET).map_err(Error::io(SUPERBLOCK_OFFSET))?;
+        if &sb[0x40..0x48] != b"_BHRfS_M"{
+            return Err(Error::WrongFilesystem("btrfs"));
+        }
+
+        let root = u64_at(&sb, 0x50);
+        let chunk_root = u64_at(&sb, 0x58);
+        let nodesize = u32_at(&sb, 0x94) as u64;
+        if !nodesize.is_power_of_two() || !(4096..=65536).contains(&nodesize){
+            return Err(Error::corrupt(SUPERBLOCK_OFFSET + 0x94, format!("invalid node size {nodesize}")));
+        }
+        let sys_chunk_array_size = u32_at(&sb, 0xA0) as usize;
+        // dev_item.devid, to know which stripes are on this device
+        let devid = u64_at(&sb, 0xC9);
+
+        let mut drive = BtrfsDrive{file, device, directories: Vec::new(), mounted_at, nodesize, devid, chunks: Vec::new(),
+            roots: HashMap::new(), visited: HashSet::new(), indexed_subvolumes: HashMap::new(), pending_subvolumes: Vec::new(), files: Vec::new(), ignored_dirs};
+        // The superblock contains the chunks needed to read the chunk tree
+        let array = &sb[0x32B..0x32B+sys_chunk_array_size.min(2048)];
+        let mut i = 0;
+        while i + 17 + 48 <= array.len(){
+            let key = key_at(array, i);
+            let num_stripes = u16_at(array, i+17+44) as usize;
+            drive.add_chunk(key.offset, &array[i+17..]);
+            i += 17 + 48 + num_stripes*32;
+        }
+        // Then the chunk tree has every chunk
+        let mut chunks = Vec::new();
+        drive.walk_tree(chunk_root, &mut |key, data| {
+            if key.item_type == CHUNK_ITEM{
+                chunks.push((key.offset, data.to_vec()));
+            }
+        }, u8::MAX)?;
+        for (logical, data) in chunks{
+            drive.add_chunk(logical, &data);
+        }
+        // The root tree points to the FS tree and every subvolume
+        let mut roots = HashMap::new();
+        drive.walk_tree(root, &mut |key, data| {
+            if key.item_type == ROOT_ITEM && data.len() >= 239{
+                roots.insert(key.objectid, (u64_at(data, 176), u64_at(data, 168), u32_at(data, 216)));
+            }
+        }, u8::MAX)?;
+        drive.roots = roots;
+        Ok(drive)
+    }
+    fn add_chunk(self: &mut Self, logical: u64, item: &[u8]){
+        let length = u64_at(item, 0);
+        let num_stripes = u16_at(item, 44) as usize;
+        for s in 0..num_stripes{
+            let stripe = 48 + s*32;
+            if stripe + 16 > item.len(){
+                break;
+            }
+            // With DUP/RAID1 every stripe is a full copy, the first one on this device is enough
+            if u64_at(item, stripe) == self.devid{
+                if !self.chunks.iter().any(|c| c.logical == logical){
+                    self.chunks.push(Chunk{logical, length, physical: u64_at(item, stripe+8)});
+                }
+                return;
+            }
+        }
+    }
+    fn logical_to_physical(self: &Self, logical: u64) -> Option<u64>{
+        for c in &self.chunks{
+            if logical >= c.logical && logical < c.logical + c.length{
+                return Some(c.physical + logical - c.logical);
+            }
+        }
+        None
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    /// Calls `f` for every item in every leaf of the tree, the level of a node must be below `parent_level`
+    fn walk_tree(self: &Self, logical: u64, f: &mut dyn FnMut(Key, &[u8]), parent_level: u8) -> Result<(), Error>{
+        let Some(physical) = self.logical_to_physical(logical) else{
+            return Ok(());
+        };
+        let node = self.read_bytes(physical, self.nodesize)?;
+        // A node that doesn't know where it is (stale pointer or garbage) is skipped
+        if u64_at(&node, 0x30) != logical{
+            return Ok(());
+        }
+        let nritems = u32_at(&node, 0x60) as usize;
+        let level = node[0x64];
+        // A child pointing back up the tree would be walked again and again
+        if level >= parent_level{
+            return Ok(());
+        }
+        for i in 0..nritems{
+            if level == 0{
+                let item = HEADER_SIZE + i*25;
+                if item + 25 > node.len(){
+                    break;
+                }
+                let key = key_at(&node, item);
+                let offset = HEADER_SIZE + u32_at(&node, item+17) as usize;
+                let size = u32_at(&node, item+21) as usize;
+                if offset + size <= node.len(){
+                    f(key, &node[offset..offset+size]);
+                }
+            }else{
+                let ptr = HEADER_SIZE + i*33;
+                if ptr + 33 > node.len(){
+                    break;
+                }
+                self.walk_tree(u64_at(&node, ptr+17), f, level)?;
+            }
+        }
+        Ok(())
+    }
+    fn read_fs_tree(self: &Self, tree_id: u64) -> Result<FsTree, Error>{
+        let mut tree = FsTree::default();
+        let Some(&(bytenr, root_dirid, _)) = self.roots.get(&tree_id) else{
+            return Ok(tree);
+        };
+        tree.id = tree_id;
+        tree.root_dirid = root_dirid;
+        self.walk_tree(bytenr, &mut |key, data| {
+            match key.item_type{
+                INODE_ITEM if data.len() >= 160 => {
+                    tree.inodes.insert(key.objectid, InodeItem{
+                        size: u64_at(data, 16),
+                        // otime is the creation time
+                        create_timestamp: u64_at(data, 148) as i64,
+                        last_modified_timestamp: u64_at(data, 136) as i64,
+                    });
+                }
+                DIR_INDEX if data.len() >= 30 => {
+                    let location = key_at(data, 0);
+                    let name_len = u16_at(data, 27) as usize;
+                    if 30 + name_len <= data.len(){
+                        let name = String::from_utf8_lossy(&data[30..30+name_len]).to_string();
+                        tree.entries.entry(key.objectid).or_default().push((key.offset, DirEntry{
+                            location: location.objectid,
+                            is_subvolume: location.item_type == ROOT_ITEM,
+                            is_dir: data[29] == FT_DIR,
+                            name
+                        }));
+                    }
+                }
+                _ => {}
+            }
+        }, u8::MAX)?;
+        for entries in tree.entries.values_mut(){
+            entries.sort_by_key(|e| e.0);
+        }
+        Ok(tree)
+    }
+    /// Where the subvolumes of this drive are mounted, (path inside of the filesystem, mountpoint)
+    /// (for example Ubuntu mounts the subvolume "@" at "/" and "@home" at "/home").
+    /// `mounted_at` comes first and defaults to the top of the filesystem when it isn't mounted (disk images)
+    fn mounts(self: &Self) -> Vec<(String, String)>{
+        let mounted_at = self.mounted_at.trim_end_matches('/').to_string() + "/";
+        let mut main = String::from("/");
+        let mut others = Vec::new();
+        let device = fs::canonicalize(&self.device).unwrap_or_default();
+        if let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo"){
+            for line in mountinfo.lines(){
+                // Spaces inside of paths are escaped as \040
+                let fields: Vec<String> = line.split(' ').map(|f| f.replace("\\040", " ")).collect();
+                if fields.len() <= 4{
+                    continue;
+                }
+                let mountpoint = fields[4].trim_end_matches('/').to_string() + "/";
+                if mountpoint == mounted_at{
+                    main = fields[3].clone();
+                    continue;
+                }
+                // The optional fields end with "-", followed by the filesystem type and the device
+                let Some(separator) = fields.iter().position(|f| f == "-") else{
+                    continue;
+                };
+                if let Some(source) = fields.get(separator + 2) && fields[separator + 1] == "btrfs"
+                    && fs::canonicalize(source).is_ok_and(|source| source == device){
+                    others.push((fields[3].clone(), mountpoint));
+                }
+            }
+        }
+        let mut mounts = vec![(main, mounted_at)];
+        mounts.append(&mut others);
+        mounts
+    }
+    /// Finds the subvolume and directory at `path`, starting from the top of the filesystem
+    fn find_subvolume(self: &Self, path: &str) -> Result<(FsTree, u64), Error>{
+        let mut tree = self.read_fs_tree(FS_TREE_OBJECTID)?;
+        let mut dir = tree.root_dirid;
+        for component in path.split('/'){
+            if component.is_empty(){
+                continue;
+            }
+            let Some(entry) = tree.entries.get(&dir).and_then(|e| e.iter().find(|(_, e)| e.name == component)) else{
+                break;
+            };
+            let entry = entry.1.clone();
+            if entry.is_subvolume{
+                tree = self.read_fs_tree(entry.location)?;
+                dir = tree.root_dirid;
+            }else{
+                dir = entry.location;
+            }
+        }
+        Ok((tree, dir))
+    }
+    /// Indexes `dir` of `tree` as a new top level directory named `name`, unless it was already indexed
+    fn index_top_level(self: &mut Self, tree: &FsTree, dir: u64, name: String) -> Result<(), Error>{
+        if self.ignored_dirs.iter().any(|i| name.starts_with(i)) || !self.visited.insert((tree.id, dir)){
+            return Ok(());
+        }
+        if dir == tree.root_dirid{
+            self.indexed_subvolumes.insert(tree.id, tree.inodes.get(&dir).cloned().unwrap_or_default());
+        }
+        self.directories.push(Directory{name});
+        let idx = self.directories.len() as u32 - 1;
+        self.index(tree, dir, idx)?;
+        for (subvolume, name) in std::mem::take(&mut self.pending_subvolumes){
+            let root_dirid = subvolume.root_dirid;
+            self.index_top_level(&subvolume, root_dirid, name)?;
+        }
+        Ok(())
+    }
+    /// The subvolume at `mounted_at` comes first, then the other subvolumes of the drive that are mounted and then the
+    /// top of the filesystem as "device:/" (which has every subvolume that isn't mounted, like snapshots or "@home"
+    /// on a disk image). Subvolumes that no directory points to anymore come last as "device:subvolume id/".
+    /// Every subvolume is its own top level directory, named after the path it was found at
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        for (path, mountpoint) in self.mounts(){
+            let (tree, dir) = self.find_subvolume(&path)?;
+            self.index_top_level(&tree, dir, mountpoint)?;
+        }
+        let top = self.read_fs_tree(FS_TREE_OBJECTID)?;
+        let root_dirid = top.root_dirid;
+        self.index_top_level(&top, root_dirid, self.device.clone() + ":/")?;
+        let mut ids: Vec<u64> = self.roots.iter()
+            .filter(|&(&id, &(_, _, refs))| (FIRST_FREE_OBJECTID..=LAST_FREE_OBJECTID).contains(&id) && refs > 0)
+            .map(|(&id, _)| id).collect();
+        ids.sort();
+        for id in ids{
+            if self.indexed_subvolumes.contains_key(&id){
+                continue;
+            }
+            let tree = self.read_fs_tree(id)?;
+            let root_dirid = tree.root_dirid;
+            self.index_top_level(&tree, root_dirid, format!("{}:subvolume {id}/", self.device))?;
+        }
+        Ok(self)
+    }
+    fn index(self: &mut Self, tree: &FsTree, directory: u64, parent: u32) -> Result<(), Error>{
+        let Some(entries) = tree.entries.get(&directory) else{
+            return Ok(());
+        };
+        let mut new_dirs = Vec::new();
+        for (_, entry) in entries{
+            let mut full_name = self.directories[parent as usize].name.clone() + &entry.name;
+            let is_dir = entry.is_dir || entry.is_subvolume;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            // Every subvolume is its own tree, its root directory holds its timestamps.
+            // A subvolume that was already indexed (like the one mounted somewhere else) isn't read again
+            let indexed = if entry.is_subvolume{self.indexed_subvolumes.get(&entry.location).cloned()}else{None};
+            let subvolume = if entry.is_subvolume && indexed.is_none(){Some(self.read_fs_tree(entry.location)?)}else{None};
+            let inode = match (&subvolume, indexed){
+                (Some(subvolume), _) => {subvolume.inodes.get(&subvolume.root_dirid).cloned().unwrap_or_default()}
+                (None, Some(inode)) => {inode}
+                (None, None) => {tree.inodes.get(&entry.location).cloned().unwrap_or_default()}
+            };
+            self.files.push(BtrfsFile{
+                name: entry.name.clone(),
+                parent,
+                size: inode.size,
+                is_dir,
+                create_timestamp: inode.create_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if entry.is_subvolume{
+                // The subvolume is indexed on its own once the tree it is in is done
+                if let Some(subvolume) = subvolume{
+                    self.indexed_subvolumes.insert(subvolume.id, inode);
+                    self.pending_subvolumes.push((subvolume, full_name));
+                }
+                continue;
+            }
+            if is_dir && self.visited.insert((tree.id, entry.location)){
+                new_dirs.push((entry.location, full_name));
+            }
+        }
+        for (location, name) in new_dirs{
+            self.directories.push(Directory{name});
+            let idx = self.directories.len() as u32 - 1;
+            self.index(tree, location, idx)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct BtrfsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_btrfs_files_to_files(f: &BtrfsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+pub struct BtrfsIndexer;
+impl FilesystemIndexer for BtrfsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Btrfs"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[0x10040..0x10048] == b"_BHRfS_M"
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = BtrfsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_btrfs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
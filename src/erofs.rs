@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at, u64_at};
+
+// The following code decodes EROFS following the kernel documentation
+// https://docs.kernel.org/filesystems/erofs.html and fs/erofs/erofs_fs.h
+// Directories are stored uncompressed (only file data is compressed), so no decompressor is needed
+
+const SUPER_OFFSET: u64 = 1024;
+const EROFS_MAGIC: u32 = 0xE0F5_E1E2;
+/// Inodes are addressed in 32 bytes slots from the start of the metadata area
+const INODE_SLOT_SIZE: u64 = 32;
+const COMPACT_INODE_SIZE: u64 = 32;
+const EXTENDED_INODE_SIZE: u64 = 64;
+const LAYOUT_FLAT_PLAIN: u16 = 0;
+const LAYOUT_FLAT_INLINE: u16 = 2;
+const DIRENT_SIZE: usize = 12;
+
+/// The part of an inode that matters to us
+#[derive(Debug, Default, Clone)]
+struct Inode{
+    mode: u16,
+    size: u64,
+    layout: u16,
+    last_modified_timestamp: i64,
+    raw_blkaddr: u64,
+    /// Where the tail of a FLAT_INLINE inode is stored (right after the inode and its xattrs)
+    inline_at: u64,
+}
+impl Inode{
+    fn is_dir(self: &Self) -> bool{
+        self.mode & 0xF000 == 0x4000
+    }
+}
+struct ErofsDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    /// Byte offset of the metadata area (nid 0)
+    meta_start: u64,
+    root_nid: u64,
+    /// Compact inodes store their modification time relative to this
+    build_time: i64,
+    /// Nids of the directories already indexed, so that a directory listing one of its parents isn't walked forever
+    visited: HashSet<u64>,
+    files: Vec<ErofsFile>,
+    ignored_dirs: Vec<String>
+}
+impl ErofsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut sb = vec![0u8; 128];
+        file.read_at(&mut sb, SUPER_OFFSET).map_err(Error::io(SUPER_OFFSET))?;
+        if u32_at(&sb, 0) != EROFS_MAGIC{
+            return Err(Error::WrongFilesystem("EROFS"));
+        }
+        if !(9..=16).contains(&sb[12]){
+            return Err(Error::corrupt(SUPER_OFFSET + 12, format!("invalid block size bits {}", sb[12])));
+        }
+        let block_size = 1u64 << sb[12];
+        Ok(ErofsDrive {file, directories: Vec::new(), mounted_at, block_size, meta_start: u32_at(&sb, 40) as u64*block_size,
+            root_nid: u16_at(&sb, 14) as u64, build_time: u64_at(&sb, 24) as i64, visited: HashSet::new(), files: Vec::new(), ignored_dirs})
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    fn read_inode(self: &Self, nid: u64) -> Result<Inode, Error>{
+        let at = self.meta_start + nid*INODE_SLOT_SIZE;
+        let b = self.read_bytes(at, EXTENDED_INODE_SIZE)?;
+        let format = u16_at(&b, 0);
+        let layout = (format >> 1) & 0x7;
+        let xattr_count = u16_at(&b, 2) as u64;
+        let xattr_size = if xattr_count == 0{0}else{12 + (xattr_count - 1)*4};
+        let mut inode = Inode{mode: u16_at(&b, 4), layout, raw_blkaddr: u32_at(&b, 16) as u64, ..Default::default()};
+        let inode_size = if format & 1 == 0{
+            inode.size = u32_at(&b, 8) as u64;
+            inode.last_modified_timestamp = self.build_time + u32_at(&b, 12) as i64;
+            COMPACT_INODE_SIZE
+        }else{
+            inode.size = u64_at(&b, 8);
+            inode.last_modified_timestamp = u64_at(&b, 32) as i64;
+            EXTENDED_INODE_SIZE
+        };
+        inode.inline_at = at + inode_size + xattr_size;
+        Ok(inode)
+    }
+    /// Returns (nid, name) for every entry in a directory (except "." and "..")
+    fn read_dir(self: &Self, inode: &Inode) -> Result<Vec<(u64, String)>, Error>{
+        let mut entries = Vec::new();
+        // Compressed and chunk based directories aren't made by mkfs.erofs
+        if inode.layout != LAYOUT_FLAT_PLAIN && inode.layout != LAYOUT_FLAT_INLINE{
+            return Ok(entries);
+        }
+        let blocks = inode.size.div_ceil(self.block_size);
+        for i in 0..blocks{
+            let len = (inode.size - i*self.block_size).min(self.block_size);
+            let b = if inode.layout == LAYOUT_FLAT_INLINE && i == blocks - 1 && !inode.size.is_multiple_of(self.block_size){
+                self.read_bytes(inode.inline_at, len)?
+            }else{
+                self.read_bytes((inode.raw_blkaddr + i)*self.block_size, len)?
+            };
+            if b.len() < DIRENT_SIZE{
+                continue;
+            }
+            // The names follow the entries, so the name of the first entry tells how many entries there are.
+            // Names end where the next one starts, the last one ends at a NUL or at the end of the block
+            let count = u16_at(&b, 8) as usize / DIRENT_SIZE;
+            for j in 0..count{
+                let e = j*DIRENT_SIZE;
+                if e + DIRENT_SIZE > b.len(){
+                    break;
+                }
+                let start = u16_at(&b, e + 8) as usize;
+                let end = if j + 1 < count{u16_at(&b, e + DIRENT_SIZE + 8) as usize}else{b.len()};
+                let Some(name) = b.get(start..end) else{
+                    continue;
+                };
+                let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+                let name = String::from_utf8_lossy(name).to_string();
+                if name != "." && name != ".."{
+                    entries.push((u64_at(&b, e), name));
+                }
+            }
+        }
+        Ok(entries)
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let root = self.read_inode(self.root_nid)?;
+        self.visited.insert(self.root_nid);
+        self.index(&root, 0)?;
+        Ok(self)
+    }
+    fn index(self: &mut Self, directory: &Inode, parent: u32) -> Result<(), Error>{
+        let mut new_dirs = Vec::new();
+        for (nid, name) in self.read_dir(directory)?{
+            let inode = self.read_inode(nid)?;
+            let is_dir = inode.is_dir();
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            // EROFS only stores the modification time
+            self.files.push(ErofsFile{
+                name: name.clone(),
+                parent,
+                size: inode.size,
+                is_dir,
+                create_timestamp: inode.last_modified_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if is_dir && self.visited.insert(nid){
+                new_dirs.push((inode, full_name));
+            }
+        }
+        for (inode, name) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(&inode, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct ErofsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_erofs_files_to_files(f: &ErofsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// Read-only images used by Android and firmware
+pub struct ErofsIndexer;
+impl FilesystemIndexer for ErofsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Erofs"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        u32_at(b, 1024) == EROFS_MAGIC
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = ErofsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_erofs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: false, reads_device: true, deleted_files: false}
+    }
+}
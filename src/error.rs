@@ -0,0 +1,55 @@
+use std::{fmt, io};
+
+/// Why a drive couldn't be indexed
+#[derive(Debug)]
+pub enum Error{
+    /// The drive (or image) can't be opened, indexing needs sudo
+    PermissionDenied(String),
+    /// The drive doesn't hold the filesystem it is indexed as, like a FAT drive set to Exfat
+    WrongFilesystem(&'static str),
+    /// Metadata (a directory entry, an inode, a B-tree node...) that makes no sense, offset is in bytes from the
+    /// beginning of the drive
+    Corrupt{offset: u64, reason: String},
+    /// Reading the drive failed, offset is in bytes from the beginning of the drive
+    Io{offset: u64, source: io::Error},
+    /// Something the backend can't read, like encrypted or lzo compressed metadata
+    Unsupported(String),
+    /// The backend panicked (a bug), with the message of the panic
+    Panicked(String),
+}
+impl Error{
+    /// partitions::open failed on `drive`
+    pub fn open(drive: &str, source: io::Error) -> Self{
+        match source.kind(){
+            io::ErrorKind::PermissionDenied => {Error::PermissionDenied(drive.to_string())}
+            _ => {Error::Io{offset: 0, source}}
+        }
+    }
+    /// For `map_err` after reading at `offset`
+    pub fn io(offset: u64) -> impl FnOnce(io::Error) -> Self{
+        move |source| Error::Io{offset, source}
+    }
+    pub fn corrupt(offset: u64, reason: impl Into<String>) -> Self{
+        Error::Corrupt{offset, reason: reason.into()}
+    }
+}
+impl fmt::Display for Error{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Error::PermissionDenied(drive) => {write!(f, "permission denied on {drive}, run Anything with sudo to index it")}
+            Error::WrongFilesystem(fs) => {write!(f, "no {fs} filesystem found, check the filesystem type in the Drive Settings")}
+            Error::Corrupt{offset, reason} => {write!(f, "corrupt metadata at byte {offset}: {reason}")}
+            Error::Io{offset, source} => {write!(f, "I/O error at byte {offset}: {source}")}
+            Error::Unsupported(what) => {write!(f, "unsupported: {what}")}
+            Error::Panicked(message) => {write!(f, "the backend crashed ({message}), please report it")}
+        }
+    }
+}
+impl std::error::Error for Error{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self{
+            Error::Io{source, ..} => {Some(source)}
+            _ => {None}
+        }
+    }
+}
@@ -1,22 +1,26 @@
-use std::fs;
-use std::os::unix::fs::FileExt;
-use std::io::{Read};
-use crate::{Directory, File};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, DriveReport, FilesystemIndexer, Space};
+use crate::{Attributes, Deleted, Directory, Drive, Error, File};
 
 // The following code decodes the exFAT filesystem following the exfat spec
 // https://learn.microsoft.com/en-us/windows/win32/fileio/exfat-specification
 use chrono::{FixedOffset, NaiveDate,TimeZone};
-fn to_epoch(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32, ms: u32, offset_secs: i32) -> i64 {
-    let offset = FixedOffset::east_opt(offset_secs).expect("Invalid offset");
-    let naive_date = NaiveDate::from_ymd_opt(year, month, day).expect("Invalid date");
-    let naive_dt = naive_date.and_hms_milli_opt(hour, minute, second, ms).expect("Invalid time");
+/// None if the date doesn't exist (like the 30th of February)
+#[allow(clippy::too_many_arguments, reason = "one argument per field of an exFAT timestamp")]
+fn to_epoch(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32, ms: u32, offset_secs: i32) -> Option<i64> {
+    let offset = FixedOffset::east_opt(offset_secs)?;
+    let naive_date = NaiveDate::from_ymd_opt(year, month, day)?;
+    let naive_dt = naive_date.and_hms_milli_opt(hour, minute, second, ms)?;
     let local_result = offset.from_local_datetime(&naive_dt);
-    let dt = local_result.unwrap();  // Or use .single().unwrap() for safety
-    dt.timestamp()  // Now works on DateTime<FixedOffset>
+    let dt = local_result.single()?;
+    Some(dt.timestamp())  // Now works on DateTime<FixedOffset>
 }
 
 
-fn bytes_to_time(b1: u8, b2: u8, b3: u8, b4: u8, b_ms: u8, b_tz: u8)->i64{
+pub fn bytes_to_time(b1: u8, b2: u8, b3: u8, b4: u8, b_ms: u8, b_tz: u8)->i64{
     let mut year = 1980;
     let mut month = 0;
     let mut day = 0;
@@ -54,15 +58,15 @@ fn bytes_to_time(b1: u8, b2: u8, b3: u8, b4: u8, b_ms: u8, b_tz: u8)->i64{
     if second > 60{return 0}
     if minute > 59{return 0}
     if hour > 23{return 0}
-    if day < 1 || day > 31{return 0}
-    if month < 1 || month > 12{return 0}
-    if year < 1980 || year > 2107{return 0}
+    if !(1..=31).contains(&day){return 0}
+    if !(1..=12).contains(&month){return 0}
+    if !(1980..=2107).contains(&year){return 0}
 
     to_epoch(
         year as i32, month as u32, day as u32,
         hour as u32, minute as u32, second as u32,
-        ms as u32, offset_secs
-    )
+        ms, offset_secs
+    ).unwrap_or(0)
 
     // to_epoch(year, month, day, hour, minute, second, ms, offset_secs)
 
@@ -71,7 +75,7 @@ fn bytes_to_time(b1: u8, b2: u8, b3: u8, b4: u8, b_ms: u8, b_tz: u8)->i64{
 struct ExFATDrive{
     /// This index refers to how many directories are already inside the Index of items
     idx: u32,
-    file: fs::File,
+    file: PartitionFile,
     directories: Vec<Directory>,
     volume_label: String,
     mounted_at: String,
@@ -81,18 +85,38 @@ struct ExFATDrive{
     cluster_byte_heap_offset: u64,
     root_dir_cluster: u64,
     files: Vec<ExFatFile>,
-    fat_table: Vec<u32>,
-    ignored_dirs: Vec<String>
+    fat: Fat,
+    ignored_dirs: Vec<String>,
+    /// Entry sets and directories that were skipped because they are corrupt
+    warnings: Vec<String>,
+    /// The up-case table of the volume uncompressed, code units past its end are their own upper case.
+    /// Empty if the volume has none (or a corrupt one), names are then not checked against their NameHash
+    upcase: Vec<u16>,
+    /// Whether deleted entry sets (0x05) are read too
+    include_deleted: bool,
+    /// The allocation bitmap of the volume, for the free space, to check cluster chains and to find out if deleted
+    /// files are recoverable. None if it can't be read
+    bitmap: Option<Bitmap>,
+    /// Clusters in the cluster heap
+    cluster_count: u32,
 }
 impl ExFATDrive{
-    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>, idx: u32)-> Self{
-        let mut file = fs::File::open(device).unwrap();
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>, include_deleted: bool, idx: u32)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
         let mut buffer = vec![0u8; 512];
-        let _bytes_read = file.read(&mut buffer).unwrap();
-        assert_eq!(vec![69,88,70,65,84,32,32,32],buffer[3..11]); //ExFat flag
-        assert_eq!([0x55, 0xAA], [buffer[510], buffer[511]], "Invalid boot signature"); //BootSignature flag
+        let _bytes_read = file.read_at(&mut buffer, 0).map_err(Error::io(0))?;
+        if buffer[3..11] != [69,88,70,65,84,32,32,32]{ //ExFat flag
+            return Err(Error::WrongFilesystem("exFAT"));
+        }
+        if [buffer[510], buffer[511]] != [0x55, 0xAA]{ //BootSignature flag
+            return Err(Error::corrupt(510, "invalid boot signature"));
+        }
         // Bytes per sector
         let bytes_per_sector_shift = buffer[108] as u64;  // 2^(this number) = sector size
+        // The spec allows 512B to 4KiB sectors and clusters up to 32MiB
+        if !(9..=12).contains(&bytes_per_sector_shift) || bytes_per_sector_shift + buffer[109] as u64 > 25{
+            return Err(Error::corrupt(108, "invalid sector or cluster size"));
+        }
         let bytes_per_sector = 1u64 << bytes_per_sector_shift;
         // Sector per cluster
         let sectors_per_cluster_shift = buffer[109] as u64;
@@ -100,6 +124,7 @@ impl ExFATDrive{
         // Where files start
         let cluster_heap_offset_sectors = u32::from_le_bytes([buffer[88], buffer[89], buffer[90], buffer[91]]) as u64;
         let cluster_byte_heap_offset = cluster_heap_offset_sectors*bytes_per_sector;
+        let cluster_count = u32::from_le_bytes([buffer[92], buffer[93], buffer[94], buffer[95]]);
         // Root dir location
         let root_dir_cluster = u32::from_le_bytes([buffer[96], buffer[97], buffer[98], buffer[99]]) as u64;
 
@@ -108,37 +133,281 @@ impl ExFATDrive{
         //Fat table
         let fat_table_length = u32::from_le_bytes([buffer[84], buffer[85], buffer[86], buffer[87]]);
         let fat_table_offset = u32::from_le_bytes([buffer[80], buffer[81], buffer[82], buffer[83]]);
-        let mut fat_table = Vec::new();
-        let mut b = vec![0_u8; fat_table_length as usize * bytes_per_sector as usize];
-        file.read_at(&mut b, fat_table_offset as u64*bytes_per_sector as u64).unwrap();
-        for i in 0..fat_table_length*bytes_per_sector as u32/4{
-            fat_table.push(u32::from_le_bytes([b[(i*4) as usize], b[(i*4)as usize+1],
-                                            b[(i*4)as usize+2], b[(i*4)as usize+3]]));
-        }
+        let fat = Fat{byte_offset: fat_table_offset as u64*bytes_per_sector,
+            len: fat_table_length as u64*bytes_per_sector/4, blocks: BlockCache::default()};
         let directories = Vec::new();
-        ExFATDrive {idx, directories, ignored_dirs,mounted_at,fat_table,file, volume_label: String::new(), bytes_per_sector, sectors_per_cluster, cluster_size, cluster_byte_heap_offset, root_dir_cluster, files: Vec::new()}
+        Ok(ExFATDrive {idx, directories, ignored_dirs,mounted_at,fat,file, volume_label: String::new(), bytes_per_sector, sectors_per_cluster, cluster_size, cluster_byte_heap_offset, root_dir_cluster, files: Vec::new(), warnings: Vec::new(), upcase: Vec::new(), include_deleted, bitmap: None, cluster_count})
     }
     fn cluster_to_byte(self: &Self, cluster: u64)->u64{
         (cluster-2)*self.bytes_per_sector*self.sectors_per_cluster+self.cluster_byte_heap_offset
     }
-    fn read_bytes(self: &Self, from: u64, size: u64) -> Vec<u8>{
-        let mut b = vec![0_u8; size as usize];
-        self.file.read_at(&mut b, from).unwrap();
-        return b;
+    /// Reads the clusters of a directory into `bytes` (whatever it held is dropped but its allocation is reused),
+    /// they come from `chain` so they are inside the cluster heap. Clusters that follow each other on the drive
+    /// are read at once, which is most of them unless the directory is fragmented
+    fn read_clusters(self: &Self, clusters: &[u32], bytes: &mut Vec<u8>) -> Result<(), Error>{
+        let cluster_size = self.cluster_size as usize;
+        bytes.clear();
+        bytes.resize(clusters.len()*cluster_size, 0);
+        let mut start = 0;
+        while start < clusters.len(){
+            let mut end = start + 1;
+            while end < clusters.len() && Some(clusters[end]) == clusters[end-1].checked_add(1){
+                end += 1;
+            }
+            let from = self.cluster_to_byte(clusters[start] as u64);
+            self.file.read_at(&mut bytes[start*cluster_size..end*cluster_size], from).map_err(Error::io(from))?;
+            start = end;
+        }
+        Ok(())
+    }
+    /// The clusters of a file starting at `first_cluster`: the next ones on the drive if it is `contiguous`
+    /// (NoFatChain), otherwise the ones the FAT leads to. `cluster_n` of them, or up to the end of the chain if None
+    fn chain(self: &Self, first_cluster: u32, contiguous: bool, cluster_n: Option<u64>) -> ClusterChain<'_>{
+        ClusterChain{fat: &self.fat, file: &self.file, cluster_count: self.cluster_count,
+            next: Some(first_cluster), previous: None, contiguous, left: cluster_n, visited: HashSet::new()}
+    }
+    /// Reads `length` bytes (rounded up to clusters) starting at `first_cluster` and following the FAT
+    fn read_chain(self: &Self, first_cluster: u32, length: u64) -> Result<Vec<u8>, Error>{
+        let clusters = self.chain(first_cluster, false, Some(length.div_ceil(self.cluster_size))).collect::<Result<Vec<u32>, Error>>()?;
+        let mut bytes = Vec::new();
+        self.read_clusters(&clusters, &mut bytes)?;
+        Ok(bytes)
+    }
+    /// Reads the allocation bitmap described by the directory entry at `i` of the root directory
+    fn load_bitmap(self: &mut Self, bytes: &[u8], i: usize) -> Result<(), Error>{
+        let first_cluster = u32::from_le_bytes([bytes[i+20], bytes[i+21], bytes[i+22], bytes[i+23]]);
+        let length = u64::from_le_bytes([bytes[i+24], bytes[i+25], bytes[i+26], bytes[i+27],
+                                            bytes[i+28], bytes[i+29], bytes[i+30], bytes[i+31]]);
+        if length < (self.cluster_count as u64).div_ceil(8) || length > self.fat.len.div_ceil(8){
+            return Err(Error::corrupt(self.cluster_byte_heap_offset, format!("the allocation bitmap has an invalid size of {length} bytes")));
+        }
+        let clusters = self.chain(first_cluster, false, Some(length.div_ceil(self.cluster_size))).collect::<Result<Vec<u32>, Error>>()?;
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for c in clusters{
+            let from = self.cluster_to_byte(c as u64);
+            match runs.last_mut(){
+                Some((offset, run_length)) if *offset + *run_length == from => {*run_length += self.cluster_size;}
+                _ => {runs.push((from, self.cluster_size));}
+            }
+        }
+        let mut bitmap = Bitmap{runs, len: length, used: 0, blocks: BlockCache::default()};
+        // Only the bits of the clusters of the heap count, the ones after the last cluster can be anything
+        let bytes_n = (self.cluster_count as u64).div_ceil(8);
+        let mut block = vec![0_u8; FAT_BLOCK_SIZE as usize];
+        let mut pos = 0;
+        while pos < bytes_n{
+            let n = FAT_BLOCK_SIZE.min(bytes_n - pos) as usize;
+            bitmap.read(&self.file, pos, &mut block[..n])?;
+            pos += n as u64;
+            if pos == bytes_n && !self.cluster_count.is_multiple_of(8){
+                block[n - 1] &= (1 << (self.cluster_count % 8)) - 1;
+            }
+            bitmap.used += block[..n].iter().map(|b| b.count_ones() as u64).sum::<u64>();
+        }
+        self.bitmap = Some(bitmap);
+        Ok(())
+    }
+    /// Whether the allocation bitmap says that the cluster isn't used by anything.
+    /// False if the bitmap couldn't be read (nothing can be said about the cluster then)
+    fn is_free(self: &Self, cluster: u32) -> bool{
+        let (Some(bitmap), Some(bit)) = (&self.bitmap, (cluster as u64).checked_sub(2)) else {return false;};
+        matches!(bitmap.get(&self.file, bit), Ok(Some(false)))
+    }
+    /// A cluster chain that goes through free clusters is broken, where it leads to can hold anything.
+    /// Nothing is checked if the bitmap couldn't be read
+    fn check_allocated(self: &Self, clusters: &[u32], name: &str) -> Result<(), Error>{
+        match clusters.iter().find(|c| self.is_free(**c)){
+            Some(c) => {Err(Error::corrupt(self.cluster_to_byte(*c as u64), format!("cluster {c} of {name} is free in the allocation bitmap")))}
+            None => {Ok(())}
+        }
+    }
+    /// Used and total bytes of the cluster heap according to the allocation bitmap
+    fn space(self: &Self) -> Option<Space>{
+        let bitmap = self.bitmap.as_ref()?;
+        Some(Space{used: bitmap.used * self.cluster_size, total: self.cluster_count as u64 * self.cluster_size})
+    }
+    /// Whether the clusters of a deleted file are all still free. Deleting a file leaves its FAT chain as it was,
+    /// so fragmented files can be followed too (unless the FAT entries were reused)
+    fn deleted_state(self: &Self, file: &ExFatFile) -> Deleted{
+        let mut chain = self.chain(file.first_cluster, file.contigous, Some(file.size.div_ceil(self.cluster_size)));
+        if chain.all(|cluster| cluster.is_ok_and(|c| self.is_free(c))){
+            Deleted::Recoverable
+        }else{
+            Deleted::Overwritten
+        }
+    }
+    /// Reads the up-case table described by the directory entry at `i` of the root directory. A table whose
+    /// TableChecksum doesn't match is left out with a warning
+    fn load_upcase(self: &mut Self, bytes: &[u8], i: usize) -> Result<(), Error>{
+        let checksum = u32::from_le_bytes([bytes[i+4], bytes[i+5], bytes[i+6], bytes[i+7]]);
+        let first_cluster = u32::from_le_bytes([bytes[i+20], bytes[i+21], bytes[i+22], bytes[i+23]]);
+        let length = u64::from_le_bytes([bytes[i+24], bytes[i+25], bytes[i+26], bytes[i+27],
+                                            bytes[i+28], bytes[i+29], bytes[i+30], bytes[i+31]]);
+        // 65536 code units and a few compressed runs, anything bigger isn't an up-case table
+        if length == 0 || length > 2*65536 + 2*4096{
+            self.warnings.push(format!("the up-case table has an invalid size of {length} bytes, names are not checked against their NameHash"));
+            return Ok(());
+        }
+        let table = self.read_chain(first_cluster, length)?;
+        let table = &table[..length as usize];
+        let mut sum = 0_u32;
+        for b in table{
+            sum = sum.rotate_right(1).wrapping_add(*b as u32);
+        }
+        if sum != checksum{
+            self.warnings.push(String::from("the up-case table has a wrong TableChecksum, names are not checked against their NameHash"));
+            return Ok(());
+        }
+        // 0xFFFF followed by n means that the next n code units are their own upper case
+        let mut units = table.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
+        let mut upcase = Vec::new();
+        while let Some(unit) = units.next(){
+            if unit == 0xFFFF{
+                let n = units.next().unwrap_or(0);
+                for _ in 0..n{
+                    upcase.push(upcase.len() as u16);
+                }
+            }else{
+                upcase.push(unit);
+            }
+            if upcase.len() >= 65536{
+                break;
+            }
+        }
+        upcase.truncate(65536);
+        self.upcase = upcase;
+        Ok(())
+    }
+    /// NameHash of a file name as in the stream extension entry, None if there is no up-case table to compute it
+    fn name_hash(self: &Self, name: &[u16]) -> Option<u16>{
+        if self.upcase.is_empty(){
+            return None;
+        }
+        let mut hash = 0_u16;
+        for unit in name{
+            let upper = self.upcase.get(*unit as usize).copied().unwrap_or(*unit);
+            hash = hash.rotate_right(1).wrapping_add(upper & 0xFF);
+            hash = hash.rotate_right(1).wrapping_add(upper >> 8);
+        }
+        Some(hash)
+    }
+    /// Reads the file directory entry at `i` and its secondary entries, which are told apart by their type:
+    /// the stream extension (0xC0) and the file name entries (0xC1) are needed, benign ones (like the vendor
+    /// extension 0xE0 and vendor allocation 0xE1 entries) are skipped wherever they are. The set is rejected if
+    /// the SetChecksum or the NameHash don't match or if it has a critical entry that isn't known.
+    /// A `deleted` set (0x05) has the InUse bit clear in all of its entries, the rest is the same
+    fn read_entry_set(self: &Self, bytes: &[u8], i: usize, parent: u32, deleted: bool) -> Result<ExFatFile, &'static str>{
+        let secondary_count = bytes[i+1] as usize;
+        if secondary_count < 2{
+            return Err("invalid secondary count");
+        }
+        if i + 32*(secondary_count+1) > bytes.len(){
+            return Err("the entry set goes past the end of the directory");
+        }
+        // Bytes 2 and 3 are the checksum itself, it was computed while the entries were in use
+        let mut checksum = 0_u16;
+        for (j, b) in bytes[i..i+32*(secondary_count+1)].iter().enumerate(){
+            if j == 2 || j == 3{
+                continue;
+            }
+            let b = if j % 32 == 0{b | 0x80}else{*b};
+            checksum = checksum.rotate_right(1).wrapping_add(b as u16);
+        }
+        if checksum != u16::from_le_bytes([bytes[i+2], bytes[i+3]]){
+            return Err("wrong SetChecksum");
+        }
+
+        let mut stream = None;
+        let mut name = Vec::new();
+        for k in 1..=secondary_count{
+            let entry = &bytes[i+32*k..i+32*(k+1)];
+            if (entry[0] & 0x80 == 0) != deleted{
+                return Err(if deleted{"secondary entry of a deleted file that is in use"}else{"secondary entry that isn't in use"});
+            }
+            match entry[0] | 0x80{
+                0xC0 => {
+                    if stream.is_some(){
+                        return Err("more than one stream extension entry");
+                    }
+                    stream = Some(entry);
+                }
+                0xC1 => {
+                    name.extend(entry[2..32].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])));
+                }
+                t if t & 0x20 != 0 => {}
+                _ => {
+                    return Err("unknown critical secondary entry");
+                }
+            }
+        }
+        let Some(stream) = stream else {return Err("no stream extension entry");};
+        let name_length = stream[3] as usize;
+        if name_length == 0 || name_length > name.len(){
+            return Err("the name doesn't fit in its file name entries");
+        }
+        name.truncate(name_length);
+        if self.name_hash(&name).is_some_and(|h| h != u16::from_le_bytes([stream[4], stream[5]])){
+            return Err("wrong NameHash");
+        }
+
+        let attributes = u16::from_le_bytes([bytes[i+4], bytes[i+5]]);
+        let is_dir = attributes & 0b00010000 != 0;
+        let created_t = bytes_to_time(bytes[i+8], bytes[i+9], bytes[i+10],
+            bytes[i+11], bytes[i+20], bytes[i+22]);
+        let modified_t = bytes_to_time(bytes[i+12], bytes[i+13], bytes[i+14],
+            bytes[i+15],bytes[i+21], bytes[i+23]);
+        let first_cluster = u32::from_le_bytes([stream[20], stream[21], stream[22], stream[23]]);
+        let size = u64::from_le_bytes([stream[24], stream[25], stream[26], stream[27],
+                                            stream[28], stream[29], stream[30], stream[31]]);
+        // Unpaired surrogates are allowed in exFAT names
+        let name = String::from_utf16_lossy(&name);
+        let secondary_flags = stream[1];
+        let contigous = secondary_flags & 0b00000010 == 2;
+        Ok(ExFatFile{parent, contigous, first_cluster, is_dir, name, size, create_timestamp: created_t, last_modified_timestamp: modified_t, deleted: None, attributes})
+    }
+    /// Whether a file of the directory `dir` (its full path) is in one of the ignored directories
+    fn is_ignored(self: &Self, dir: &str, file: &ExFatFile) -> bool{
+        let mut full_name = dir.to_string() + &file.name;
+        if file.is_dir{
+            full_name += "/";
+        }
+        self.ignored_dirs.iter().any(|d| full_name.starts_with(d))
+    }
+    /// Remembers that the entry set at byte `i` of a directory read with read_clusters was skipped
+    fn skip_entry_set(self: &mut Self, clusters: &[u32], i: usize, dir: &str, reason: &str){
+        let cluster_size = self.cluster_size as usize;
+        self.warnings.push(format!("corrupt entry set at cluster {} offset {} in {dir}: {reason}",
+            clusters[i / cluster_size], i % cluster_size));
     }
-    fn index_from_root(mut self: Self) -> Self{
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
         self.directories.push(Directory { name: self.mounted_at.clone() + "/" });
         // dbg!(self.cluster_to_byte(self.root_dir_cluster));
-        let mut clusters = vec![self.root_dir_cluster];
-        let mut next_cluster = self.root_dir_cluster.clone();
-        while self.find_next_in_fat(next_cluster as u32) != 0 && self.find_next_in_fat(next_cluster as u32) != u32::MAX{
-            clusters.push(next_cluster);
-            next_cluster = self.find_next_in_fat(next_cluster as u32) as u64;
-        }
+        let clusters = self.chain(self.root_dir_cluster as u32, false, None).collect::<Result<Vec<u32>, Error>>()?;
         let mut bytes = Vec::new();
-        for c in 0..clusters.len(){
-            bytes.append(&mut self.read_bytes(self.cluster_to_byte(clusters[c] as u64), self.cluster_size));
+        self.read_clusters(&clusters, &mut bytes)?;
+        // The up-case table is needed to check the names, it is usually one of the first entries but not always
+        if let Some(i) = (0..bytes.len()).step_by(32).take_while(|i| bytes[*i] != 0x00).find(|i| bytes[*i] == 0x82){
+            match self.load_upcase(&bytes, i){
+                Err(Error::Corrupt{reason, ..}) => {
+                    self.warnings.push(format!("the up-case table can't be read ({reason}), names are not checked against their NameHash"));
+                }
+                result => {result?}
+            }
+        }
+        // TexFAT volumes have a second bitmap (BitmapFlags 1) for the second FAT, the first one is enough
+        let bitmap = (0..bytes.len()).step_by(32).take_while(|i| bytes[*i] != 0x00).find(|i| bytes[*i] == 0x81 && bytes[*i+1] & 1 == 0);
+        let result = match bitmap{
+            Some(i) => {self.load_bitmap(&bytes, i)}
+            None => {Err(Error::corrupt(self.cluster_to_byte(self.root_dir_cluster), "no allocation bitmap in the root directory"))}
+        };
+        match result{
+            Err(Error::Corrupt{reason, ..}) => {
+                self.warnings.push(format!("the allocation bitmap can't be read ({reason}), free space is unknown, \
+                    cluster chains aren't checked and deleted files are shown as overwritten"));
+            }
+            result => {result?}
         }
+        self.check_allocated(&clusters, "the root directory")?;
         let mut found_eod = false; //end_of_directory
         let mut i = 0;
         let size = self.cluster_size*clusters.len() as u64;
@@ -147,150 +416,105 @@ impl ExFATDrive{
             match bytes[i as usize]{
                 0x00 => {found_eod = true}
                 0x83 => {
-                    let size = bytes[(i+1) as usize];
+                    let size = bytes[(i+1) as usize].min(11);
                     let mut volume_label = Vec::new();
                     for j in 0..size{
                         volume_label.push(u16::from_le_bytes([bytes[(i+(j*2) as u64+2) as usize],bytes[(i+(j*2) as u64+3) as usize]]))
                     }
-                    self.volume_label = String::from_utf16(&volume_label).unwrap();
+                    self.volume_label = String::from_utf16_lossy(&volume_label);
                 }
                 0x85 => {
-                    let i = i.clone() as usize;
-                    let _secondary_count = bytes[i+1];
-                    let attr_1 = bytes[i+4];
-                    let is_a_dir = attr_1 & 0b00010000;
-                    let created_t = bytes_to_time(bytes[i+8], bytes[i+9], bytes[i+10],
-                        bytes[i+11], bytes[i+20], bytes[i+23]);
-                    let modified_t = bytes_to_time(bytes[i+12], bytes[i+13], bytes[i+14],
-                        bytes[i+15],bytes[i+21], bytes[i+24]);
-                    let name_length = bytes[i+35];
-                    let first_cluster = u32::from_le_bytes([bytes[i+52],bytes[i+53],bytes[i+54],bytes[i+55]]);
-                    let size = u64::from_le_bytes([bytes[i+56],bytes[i+57],bytes[i+58],bytes[i+59],
-                                                        bytes[i+60],bytes[i+61],bytes[i+62],bytes[i+63]]);
-
-                    let mut name = Vec::new();
-                    let mut k = 64_usize+i;
-                    for _ in 0..name_length{
-                        if k % 32 == 0{
-                            k += 2;
+                    let dir = self.mounted_at.clone() + "/";
+                    match self.read_entry_set(&bytes, i as usize, self.idx, false){
+                        Ok(file) => {
+                            if !self.is_ignored(&dir, &file){
+                                self.files.push(file);
+                            }
                         }
-                        name.push(u16::from_le_bytes([bytes[k], bytes[k+1]]));
-                        k+=2
-                    }
-                    let name = String::from_utf16(&name).unwrap();
-                    let mut full_name = self.mounted_at.clone() + "/" + &name;
-                    let mut is_dir = false;
-                    if is_a_dir == 0b10000{
-                        is_dir = true;
-                        full_name += "/";
-                    }
-                    let secondary_flags = bytes[i+33];
-                    let contigous = secondary_flags & 0b00000010;
-                    let contigous = if contigous == 2{true}else{false};
-                    let mut to_ignore = false;
-                    for i in self.ignored_dirs.clone(){
-                        if full_name.starts_with(&i){
-                            to_ignore = true;
+                        Err(reason) => {
+                            self.skip_entry_set(&clusters, i as usize, &dir, reason);
                         }
                     }
-                    if !to_ignore{
-                        self.files.push(
-                            ExFatFile{
-                                parent: self.idx,
-                                contigous,
-                                first_cluster,
-                                is_dir,
-                                name,
-                                size,
-                                create_timestamp:created_t,
-                                last_modified_timestamp:modified_t
-                            }
-                        );
+                }
+                0x05 if self.include_deleted => {
+                    let dir = self.mounted_at.clone() + "/";
+                    // Entry sets of deleted files get partly overwritten by new ones, those are just left out
+                    if let Ok(mut file) = self.read_entry_set(&bytes, i as usize, self.idx, true) && !self.is_ignored(&dir, &file){
+                        file.deleted = Some(self.deleted_state(&file));
+                        self.files.push(file);
                     }
-
                 }
                 _ => {}
             }
-
-            if i+32>=size && !found_eod{
-
+            if found_eod{
+                break;
             }
             i += 32;
         }
-        self
+        Ok(self)
     }
-    fn index(self: &mut Self, directory: &ExFatFile, parent: u32){
-        let cluster_n = directory.size / self.cluster_size;
-        let mut clusters = Vec::new();
-        clusters.push(directory.first_cluster);
-        if !directory.contigous{
-            for i in 1..cluster_n{
-                let mut next_cluster = directory.first_cluster;
-                for _ in 0..i{
-                    next_cluster = self.find_next_in_fat(next_cluster);
-                }
-                clusters.push(next_cluster);
-            }
-        }else{
-            for i in 1..cluster_n{
-                clusters.push(directory.first_cluster+i as u32);
+    /// Indexes the directories of the root and everything below them. Instead of going down one directory at a
+    /// time, the directories found on one level are read in the order they are on the drive, which saves seeking
+    /// (exFAT puts the clusters of a new directory wherever there is space, not near its parent)
+    fn index_subdirectories(self: &mut Self) -> Result<(), Error>{
+        let mut pending = Vec::new();
+        for i in 0..self.files.len(){
+            if self.files[i].is_dir && self.files[i].deleted.is_none(){
+                let name = self.directories[self.files[i].parent as usize].name.clone() + &self.files[i].name + "/";
+                self.directories.push(Directory{name});
+                pending.push((self.files[i].clone(), self.directories.len() as u32 - 1));
             }
         }
         let mut bytes = Vec::new();
-        for c in 0..clusters.len(){
-            bytes.append(&mut self.read_bytes(self.cluster_to_byte(clusters[c] as u64), self.cluster_size));
+        while !pending.is_empty(){
+            pending.sort_by_key(|(directory, _): &(ExFatFile, u32)| directory.first_cluster);
+            for (directory, parent) in std::mem::take(&mut pending){
+                self.index_or_skip(&directory, parent, &mut bytes, &mut pending)?;
+            }
         }
-        assert_eq!(bytes.len(), directory.size as usize);
-        let mut new_files = Vec::new();
+        Ok(())
+    }
+    /// Indexes the files of a directory, its subdirectories are added to `pending` to be indexed later.
+    /// `bytes` is only there so that its allocation is reused from one directory to the next
+    fn index(self: &mut Self, directory: &ExFatFile, parent: u32, bytes: &mut Vec<u8>, pending: &mut Vec<(ExFatFile, u32)>) -> Result<(), Error>{
+        let cluster_n = directory.size / self.cluster_size;
+        if !directory.size.is_multiple_of(self.cluster_size) || cluster_n == 0 || cluster_n > self.cluster_count as u64{
+            return Err(Error::corrupt(self.cluster_byte_heap_offset,
+                format!("directory {} has a size of {} bytes, which isn't a number of clusters", directory.name, directory.size)));
+        }
+        let clusters = self.chain(directory.first_cluster, directory.contigous, Some(cluster_n)).collect::<Result<Vec<u32>, Error>>()?;
+        self.check_allocated(&clusters, &directory.name)?;
+        self.read_clusters(&clusters, bytes)?;
         let mut found_eod = false; //end_of_directory
         let mut i = 0;
         while i<directory.size{
             match bytes[i as usize]{
                 0x00 => {found_eod = true}
                 0x85 => {
-                    let i = i.clone() as usize;
-                    let _secondary_count = bytes[i+1];
-                    let attr_1 = bytes[i+4];
-                    let is_a_dir = attr_1 & 0b00010000;
-                    let created_t = bytes_to_time(bytes[i+8], bytes[i+9], bytes[i+10],
-                        bytes[i+11], bytes[i+20], bytes[i+23]);
-                    let modified_t = bytes_to_time(bytes[i+12], bytes[i+13], bytes[i+14],
-                        bytes[i+15],bytes[i+21], bytes[i+24]);
-                    let name_length = bytes[i+35];
-                    let first_cluster = u32::from_le_bytes([bytes[i+52],bytes[i+53],bytes[i+54],bytes[i+55]]);
-                    let size = u64::from_le_bytes([bytes[i+56],bytes[i+57],bytes[i+58],bytes[i+59],
-                                                        bytes[i+60],bytes[i+61],bytes[i+62],bytes[i+63]]);
-
-                    let mut name2 = Vec::new();
-                    let mut k = 64_usize+i;
-                    for _ in 0..name_length{
-                        if k % 32 == 0{
-                            k += 2;
+                    let dir = self.directories[parent as usize].name.clone();
+                    match self.read_entry_set(bytes, i as usize, parent, false){
+                        Ok(file) => {
+                            if !self.is_ignored(&dir, &file){
+                                if file.is_dir{
+                                    let name = dir.clone() + &file.name + "/";
+                                    self.directories.push(Directory{name});
+                                    pending.push((file.clone(), self.directories.len() as u32 - 1));
+                                }
+                                self.files.push(file);
+                            }
                         }
-                        name2.push(u16::from_le_bytes([bytes[k], bytes[k+1]]));
-                        k+=2
-                    }
-                    let name = String::from_utf16(&name2);
-                    let name = name.unwrap();
-                    let mut full_name = self.directories[directory.parent as usize].name.clone() + &directory.name + &name;
-                    let mut is_dir = false;
-                    if is_a_dir == 0b10000{
-                        is_dir = true;
-                        full_name += "/";
-                    }
-                    let secondary_flags = bytes[i+33];
-                    let contigous = secondary_flags & 0b00000010;
-                    let contigous = if contigous == 2{true}else{false};
-                    let mut to_ignore = false;
-                    for i in self.ignored_dirs.clone(){
-                        if full_name.starts_with(&i){
-                            to_ignore = true;
+                        Err(reason) => {
+                            self.skip_entry_set(&clusters, i as usize, &dir, reason);
                         }
                     }
-                    if to_ignore{break;}
-                    let file = ExFatFile{parent, contigous,first_cluster,is_dir, name,size,create_timestamp:created_t,last_modified_timestamp:modified_t};
-                    self.files.push(file.clone());
-                    new_files.push(file);
+                }
+                0x05 if self.include_deleted => {
+                    let dir = self.directories[parent as usize].name.clone();
+                    // Not added to pending, the clusters of a deleted directory may hold anything by now
+                    if let Ok(mut file) = self.read_entry_set(bytes, i as usize, parent, true) && !self.is_ignored(&dir, &file){
+                        file.deleted = Some(self.deleted_state(&file));
+                        self.files.push(file);
+                    }
                 }
                 _ => {}
 
@@ -298,21 +522,181 @@ impl ExFATDrive{
             if found_eod{
                 break;
             }
-            if i+32>=directory.size && !found_eod{
-            }
             i += 32;
         }
-        for file in new_files{
-            if file.is_dir{
-                let name = self.directories[file.parent as usize].name.clone() + &file.name + "/";
-                self.directories.push(Directory{name});
-                self.index(&file.clone(), self.directories.len() as u32 - 1);
+        Ok(())
+    }
+    /// Like `index` but a directory with corrupt metadata only costs a warning (and whatever was left to read
+    /// in it), I/O errors still fail the drive
+    fn index_or_skip(self: &mut Self, directory: &ExFatFile, parent: u32, bytes: &mut Vec<u8>, pending: &mut Vec<(ExFatFile, u32)>) -> Result<(), Error>{
+        match self.index(directory, parent, bytes, pending){
+            Err(Error::Corrupt{offset, reason}) => {
+                self.warnings.push(format!("stopped reading {} at byte {offset}: {reason}", self.directories[parent as usize].name));
+                Ok(())
             }
+            result => {result}
         }
-
     }
-    fn find_next_in_fat(self: &Self, val: u32) -> u32{
-        return self.fat_table[val as usize];
+}
+/// How many bytes of the FAT (or of the allocation bitmap) are read at once
+const FAT_BLOCK_SIZE: u64 = 64*1024;
+/// How many blocks of the FAT (or of the allocation bitmap) are kept in memory (4MiB), whatever the size of the volume
+const FAT_CACHED_BLOCKS: usize = 64;
+/// The blocks of the FAT or of the allocation bitmap that were read, by their position, the one used last at the end
+#[derive(Default)]
+struct BlockCache<T>(RefCell<Vec<(u64, Vec<T>)>>);
+impl<T: Copy> BlockCache<T>{
+    /// Item `i` of `block`, which is read with `read` if it isn't one of the blocks kept
+    fn get(self: &Self, block: u64, i: usize, read: impl FnOnce() -> Result<Vec<T>, Error>) -> Result<T, Error>{
+        let mut blocks = self.0.borrow_mut();
+        match blocks.iter().position(|(b, _)| *b == block){
+            Some(p) => {
+                let used = blocks.remove(p);
+                blocks.push(used);
+            }
+            None => {
+                let items = read()?;
+                if blocks.len() == FAT_CACHED_BLOCKS{
+                    blocks.remove(0);
+                }
+                blocks.push((block, items));
+            }
+        }
+        Ok(blocks[blocks.len() - 1].1[i])
+    }
+}
+/// The FAT, read a block at a time when one of its entries is needed and keeping the blocks used last.
+/// Contiguous (NoFatChain) files and directories never need it
+struct Fat{
+    /// Where it starts on the drive
+    byte_offset: u64,
+    /// Number of entries
+    len: u64,
+    blocks: BlockCache<u32>,
+}
+impl Fat{
+    /// The entry of `cluster`, None if the FAT is smaller than that
+    fn get(self: &Self, file: &PartitionFile, cluster: u32) -> Result<Option<u32>, Error>{
+        if cluster as u64 >= self.len{
+            return Ok(None);
+        }
+        let entries_per_block = FAT_BLOCK_SIZE/4;
+        let block = cluster as u64/entries_per_block;
+        let entry = self.blocks.get(block, (cluster as u64 % entries_per_block) as usize, || {
+            let from = self.byte_offset + block*FAT_BLOCK_SIZE;
+            let mut b = vec![0_u8; (FAT_BLOCK_SIZE.min(self.len*4 - block*FAT_BLOCK_SIZE)) as usize];
+            file.read_at(&mut b, from).map_err(Error::io(from))?;
+            Ok(b.chunks_exact(4).map(|e| u32::from_le_bytes([e[0], e[1], e[2], e[3]])).collect())
+        })?;
+        Ok(Some(entry))
+    }
+}
+/// The allocation bitmap (one bit per cluster of the heap, set if it is used), read a block at a time like the FAT.
+/// It is stored in clusters like a file, so where each run of clusters is on the drive is kept
+struct Bitmap{
+    /// (byte offset on the drive, length) of the runs of clusters holding the bitmap, in order
+    runs: Vec<(u64, u64)>,
+    /// Size in bytes
+    len: u64,
+    /// How many clusters are used, counted once when the bitmap is loaded
+    used: u64,
+    blocks: BlockCache<u8>,
+}
+impl Bitmap{
+    /// Reads `b.len()` bytes of the bitmap starting `from` bytes into it
+    fn read(self: &Self, file: &PartitionFile, from: u64, b: &mut [u8]) -> Result<(), Error>{
+        let mut run_start = 0;
+        let mut done = 0;
+        for &(offset, length) in &self.runs{
+            let pos = from + done as u64;
+            if done < b.len() && pos < run_start + length{
+                let n = ((run_start + length - pos) as usize).min(b.len() - done);
+                let at = offset + pos - run_start;
+                file.read_at(&mut b[done..done + n], at).map_err(Error::io(at))?;
+                done += n;
+            }
+            run_start += length;
+        }
+        Ok(())
+    }
+    /// Whether the bit of the `bit`th cluster of the heap is set, None if the bitmap is smaller than that
+    fn get(self: &Self, file: &PartitionFile, bit: u64) -> Result<Option<bool>, Error>{
+        if bit / 8 >= self.len{
+            return Ok(None);
+        }
+        let block = bit / 8 / FAT_BLOCK_SIZE;
+        let byte = self.blocks.get(block, (bit / 8 % FAT_BLOCK_SIZE) as usize, || {
+            let mut b = vec![0_u8; FAT_BLOCK_SIZE.min(self.len - block*FAT_BLOCK_SIZE) as usize];
+            self.read(file, block*FAT_BLOCK_SIZE, &mut b)?;
+            Ok(b)
+        })?;
+        Ok(Some(byte & (1 << (bit % 8)) != 0))
+    }
+}
+/// FAT entry of the last cluster of a chain
+const END_OF_CHAIN: u32 = 0xFFFFFFFF;
+/// FAT entry of a cluster that can't be used
+const BAD_CLUSTER: u32 = 0xFFFFFFF7;
+/// Iterates over the clusters of a file, see ExFATDrive::chain. Every cluster of the chain is looked at once,
+/// so it takes linear time. A cluster outside of the cluster heap, a bad cluster, a chain that comes back to
+/// one of its clusters or that ends before `cluster_n` clusters is an Error::Corrupt, after which it stops
+struct ClusterChain<'a>{
+    fat: &'a Fat,
+    file: &'a PartitionFile,
+    cluster_count: u32,
+    next: Option<u32>,
+    /// The cluster whose FAT entry is `next`
+    previous: Option<u32>,
+    contiguous: bool,
+    /// Clusters still to go, None to follow the chain to its end
+    left: Option<u64>,
+    visited: HashSet<u32>,
+}
+impl ClusterChain<'_>{
+    /// Ends the chain with an error about the FAT entry of `entry` (the start of the FAT if None)
+    fn fail(self: &mut Self, entry: Option<u32>, reason: String) -> Option<Result<u32, Error>>{
+        self.next = None;
+        Some(Err(Error::corrupt(self.fat.byte_offset + entry.unwrap_or(0) as u64 * 4, reason)))
+    }
+}
+impl Iterator for ClusterChain<'_>{
+    type Item = Result<u32, Error>;
+    fn next(self: &mut Self) -> Option<Self::Item>{
+        if self.left == Some(0){
+            return None;
+        }
+        let cluster = self.next?;
+        if cluster < 2 || cluster - 2 >= self.cluster_count{
+            return self.fail(self.previous, format!("cluster {cluster} is outside of the cluster heap"));
+        }
+        self.left = self.left.map(|left| left - 1);
+        if self.contiguous{
+            self.next = Some(cluster.wrapping_add(1));
+            return Some(Ok(cluster));
+        }
+        if !self.visited.insert(cluster){
+            return self.fail(self.previous, format!("the cluster chain loops back to cluster {cluster}"));
+        }
+        let entry = match self.fat.get(self.file, cluster){
+            Ok(entry) => {entry}
+            Err(e) => {
+                self.next = None;
+                return Some(Err(e));
+            }
+        };
+        self.next = match entry{
+            None => {return self.fail(self.previous, format!("cluster {cluster} is outside of the FAT"));}
+            Some(BAD_CLUSTER) => {return self.fail(Some(cluster), format!("cluster {cluster} is marked as bad in the FAT"));}
+            Some(END_OF_CHAIN) => {
+                if self.left.is_some_and(|left| left > 0){
+                    return self.fail(Some(cluster), format!("the cluster chain ends early at cluster {cluster}"));
+                }
+                None
+            }
+            Some(next) => {Some(next)}
+        };
+        self.previous = Some(cluster);
+        Some(Ok(cluster))
     }
 }
 /// A file, timestamps use unix epoch
@@ -326,6 +710,9 @@ struct ExFatFile{
     last_modified_timestamp: i64,
     first_cluster: u32,
     contigous: bool,
+    deleted: Option<Deleted>,
+    /// FileAttributes of the file directory entry
+    attributes: u16,
 }
 fn from_exfat_files_to_files(f: &ExFatFile, idx: u32)->File{
     File{
@@ -334,24 +721,135 @@ fn from_exfat_files_to_files(f: &ExFatFile, idx: u32)->File{
         size:f.size,
         is_dir:f.is_dir,
         create_timestamp:f.create_timestamp,
-        last_modified_timestamp: f.last_modified_timestamp
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: f.deleted,
+        attributes: Attributes(f.attributes as u32),
     }
 }
 
-pub fn index(drive: String, mounted_at: String, ignored_dirs: Vec<String>, idx: u32) -> (Vec<File>, Vec<Directory>) {
-    let idx2 = idx;
-    let idx = 0;
-    let mut drive = ExFATDrive::new(drive, mounted_at, ignored_dirs, idx).index_from_root();
-    for i in 0..drive.files.len(){
-        if drive.files[i].is_dir{
-            let name = drive.directories[drive.files[i].parent as usize].name.clone() + &drive.files[i].name + "/";
-            drive.directories.push(Directory{name});
-            drive.index(&drive.files[i].clone(), drive.directories.len() as u32 - 1);
-        }
-    }
-    let mut output = Vec::new();
-    for f in drive.files{
-        output.push(from_exfat_files_to_files(&f, idx2));
-    }
-    (output,drive.directories)
-}
\ No newline at end of file
+pub struct ExfatIndexer;
+impl FilesystemIndexer for ExfatIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Exfat"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[3..11] == b"EXFAT   "
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        self.index_with_report(drive, idx, &mut DriveReport::default())
+    }
+    fn index_with_report(self: &Self, drive: &Drive, idx: u32, report: &mut DriveReport) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let idx2 = idx;
+        let idx = 0;
+        let mut drive = ExFATDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone(), drive.include_deleted, idx)?.index_from_root()?;
+        drive.index_subdirectories()?;
+        report.warnings.append(&mut drive.warnings);
+        report.space = drive.space();
+        if !drive.upcase.is_empty(){
+            report.upcase = Some(Arc::from(std::mem::take(&mut drive.upcase)));
+        }
+        let mut output = Vec::new();
+        for f in drive.files{
+            output.push(from_exfat_files_to_files(&f, idx2));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: true}
+    }
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::partitions::tests::disk;
+
+    /// Where the FAT starts on the test drives
+    const FAT_OFFSET: u64 = 512;
+
+    /// Walks the chain from `first` on a drive whose FAT is `entries`, returning the clusters before the
+    /// error and the offset and reason of the error
+    fn walk(name: &str, entries: &[u32], cluster_count: u32, first: u32, contiguous: bool, cluster_n: Option<u64>) -> (Vec<u32>, Option<(u64, String)>){
+        let mut bytes = vec![0u8; FAT_OFFSET as usize];
+        bytes.extend(entries.iter().flat_map(|e| e.to_le_bytes()));
+        let file = disk(name, &bytes);
+        let fat = Fat{byte_offset: FAT_OFFSET, len: entries.len() as u64, blocks: BlockCache::default()};
+        let mut chain = ClusterChain{fat: &fat, file: &file, cluster_count, next: Some(first), previous: None,
+            contiguous, left: cluster_n, visited: HashSet::new()};
+        let mut clusters = Vec::new();
+        for cluster in chain.by_ref(){
+            match cluster{
+                Ok(cluster) => {clusters.push(cluster)}
+                Err(Error::Corrupt{offset, reason}) => {
+                    // Nothing comes after an error
+                    assert!(chain.next().is_none());
+                    return (clusters, Some((offset, reason)));
+                }
+                Err(e) => {panic!("{e}")}
+            }
+        }
+        (clusters, None)
+    }
+    /// The offset of the FAT entry of `cluster`
+    fn entry(cluster: u64) -> u64{
+        FAT_OFFSET + cluster*4
+    }
+
+    #[test]
+    fn follows_the_fat(){
+        let fat = [0, 0, 5, END_OF_CHAIN, 0, 3, 0, 0];
+        assert_eq!(walk("follows-the-fat", &fat, 6, 2, false, None), (vec![2, 5, 3], None));
+        // It stops after cluster_n clusters even if the chain goes on
+        assert_eq!(walk("cluster-n", &fat, 6, 2, false, Some(2)), (vec![2, 5], None));
+    }
+    #[test]
+    fn contiguous(){
+        // The FAT isn't read, it is all zeroes
+        assert_eq!(walk("contiguous", &[0; 8], 6, 4, true, Some(3)), (vec![4, 5, 6], None));
+        let (clusters, error) = walk("contiguous-past-the-heap", &[0; 8], 6, 6, true, Some(3));
+        assert_eq!(clusters, [6, 7]);
+        assert_eq!(error, Some((entry(0), "cluster 8 is outside of the cluster heap".to_string())));
+    }
+    #[test]
+    fn loop_is_an_error(){
+        let fat = [0, 0, 3, 4, 3, 0];
+        let (clusters, error) = walk("loop", &fat, 4, 2, false, None);
+        assert_eq!(clusters, [2, 3, 4]);
+        assert_eq!(error, Some((entry(4), "the cluster chain loops back to cluster 3".to_string())));
+        // A cluster that points to itself
+        let (clusters, error) = walk("self-loop", &[0, 0, 2, 0], 2, 2, false, None);
+        assert_eq!(clusters, [2]);
+        assert_eq!(error, Some((entry(2), "the cluster chain loops back to cluster 2".to_string())));
+    }
+    #[test]
+    fn out_of_range_is_an_error(){
+        let fat = [0, 0, 3, 100, 0, 0];
+        let (clusters, error) = walk("outside-of-the-heap", &fat, 4, 2, false, None);
+        assert_eq!(clusters, [2, 3]);
+        assert_eq!(error, Some((entry(3), "cluster 100 is outside of the cluster heap".to_string())));
+        // Clusters 0 and 1 don't exist
+        for first in [0, 1]{
+            let (clusters, error) = walk("first-outside-of-the-heap", &fat, 4, first, false, None);
+            assert_eq!(clusters, []);
+            assert_eq!(error, Some((entry(0), format!("cluster {first} is outside of the cluster heap"))));
+        }
+        // The heap is bigger than the FAT
+        let (clusters, error) = walk("outside-of-the-fat", &[0, 0, 3, 6], 10, 2, false, None);
+        assert_eq!(clusters, [2, 3]);
+        assert_eq!(error, Some((entry(3), "cluster 6 is outside of the FAT".to_string())));
+    }
+    #[test]
+    fn bad_cluster_is_an_error(){
+        let fat = [0, 0, 3, BAD_CLUSTER, 0, 0];
+        let (clusters, error) = walk("bad-cluster", &fat, 4, 2, false, None);
+        assert_eq!(clusters, [2]);
+        assert_eq!(error, Some((entry(3), "cluster 3 is marked as bad in the FAT".to_string())));
+    }
+    #[test]
+    fn early_end_is_an_error(){
+        let fat = [0, 0, 3, END_OF_CHAIN, 0, 0];
+        assert_eq!(walk("exact-end", &fat, 4, 2, false, Some(2)), (vec![2, 3], None));
+        let (clusters, error) = walk("early-end", &fat, 4, 2, false, Some(4));
+        assert_eq!(clusters, [2]);
+        assert_eq!(error, Some((entry(3), "the cluster chain ends early at cluster 3".to_string())));
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashSet;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at};
+
+// The following code decodes the ext2/ext3/ext4 filesystem following the kernel documentation
+// https://docs.kernel.org/filesystems/ext4/index.html
+
+const EXT4_ROOT_INODE: u32 = 2;
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_64BIT: u32 = 0x80;
+/// Features that change how the metadata or the names are stored in ways this reader doesn't follow
+const INCOMPAT_UNSUPPORTED: [(u32, &str); 4] = [(0x10, "meta_bg"), (0x8000, "inline_data"), (0x10000, "encrypt"), (0x20000, "casefold")];
+const EXT4_EXTENTS_FL: u32 = 0x80000;
+const EXTENT_MAGIC: u16 = 0xF30A;
+
+/// ext4 stores seconds as a signed 32 bit number, the lower 2 bits of the `_extra` field extend the epoch
+/// (so that dates after 2038 still work)
+fn extended_time(seconds: u32, extra: Option<u32>) -> i64{
+    let mut t = seconds as i32 as i64;
+    if let Some(extra) = extra{
+        t += ((extra & 0b11) as i64) << 32;
+    }
+    t
+}
+/// The part of an inode that matters to us
+#[derive(Debug, Default, Clone)]
+struct Inode{
+    mode: u16,
+    size: u64,
+    flags: u32,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    /// i_block, either the block map or the root of the extent tree
+    block: Vec<u8>,
+}
+impl Inode{
+    fn is_dir(self: &Self) -> bool{
+        self.mode & 0xF000 == 0x4000
+    }
+}
+struct Ext4Drive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    inode_size: u64,
+    inodes_per_group: u32,
+    feature_incompat: u32,
+    /// Byte offset of the inode table of every block group
+    inode_tables: Vec<u64>,
+    /// Directory inodes already indexed, a corrupted entry linking back to a parent would recurse forever
+    visited: HashSet<u32>,
+    files: Vec<Ext4File>,
+    ignored_dirs: Vec<String>
+}
+impl Ext4Drive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        // The superblock is always 1024 bytes into the partition
+        let mut sb = vec![0u8; 1024];
+        file.read_at(&mut sb, 1024).map_err(Error::io(1024))?;
+        if u16_at(&sb, 0x38) != 0xEF53{
+            return Err(Error::WrongFilesystem("ext2/ext3/ext4"));
+        }
+        if u32_at(&sb, 0x18) > 6 || u32_at(&sb, 0x20) == 0 || u32_at(&sb, 0x28) == 0 || u32_at(&sb, 0x14) >= u32_at(&sb, 0x4){
+            return Err(Error::corrupt(1024, "invalid superblock geometry"));
+        }
+
+        let block_size = 1024u64 << u32_at(&sb, 0x18);
+        let first_data_block = u32_at(&sb, 0x14) as u64;
+        let blocks_per_group = u32_at(&sb, 0x20) as u64;
+        let inodes_per_group = u32_at(&sb, 0x28);
+        let rev_level = u32_at(&sb, 0x4C);
+        let inode_size = if rev_level == 0{128}else{u16_at(&sb, 0x58) as u64};
+        if inode_size < 128 || inode_size > block_size || !inode_size.is_power_of_two(){
+            return Err(Error::corrupt(1024 + 0x58, format!("invalid inode size {inode_size}")));
+        }
+        let feature_incompat = u32_at(&sb, 0x60);
+        for (feature, name) in INCOMPAT_UNSUPPORTED{
+            if feature_incompat & feature != 0{
+                return Err(Error::Unsupported(format!("the ext4 feature {name}")));
+            }
+        }
+        let mut blocks_count = u32_at(&sb, 0x4) as u64;
+        let mut desc_size = 32;
+        if feature_incompat & INCOMPAT_64BIT != 0{
+            blocks_count |= (u32_at(&sb, 0x150) as u64) << 32;
+            desc_size = u16_at(&sb, 0xFE) as u64;
+            if desc_size < 32 || desc_size > block_size || !desc_size.is_power_of_two(){
+                return Err(Error::corrupt(1024 + 0xFE, format!("invalid group descriptor size {desc_size}")));
+            }
+        }
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+        // Group numbers are 32 bits
+        if group_count > u32::MAX as u64{
+            return Err(Error::corrupt(1024, format!("{group_count} block groups")));
+        }
+
+        // Group descriptors start in the block right after the superblock. They are read one block at a time
+        // so that a wrong group count runs into the end of the device instead of allocating gigabytes first
+        let gdt_size = group_count*desc_size;
+        let gdt_start = (first_data_block+1)*block_size;
+        let mut gdt = Vec::new();
+        while (gdt.len() as u64) < gdt_size{
+            let at = gdt_start + gdt.len() as u64;
+            let mut b = vec![0u8; block_size.min(gdt_size - gdt.len() as u64) as usize];
+            if file.read_at(&mut b, at).map_err(Error::io(at))? < b.len(){
+                return Err(Error::corrupt(at, "the group descriptors go past the end of the device"));
+            }
+            gdt.append(&mut b);
+        }
+        let mut inode_tables = Vec::new();
+        for g in 0..group_count as usize{
+            let d = g*desc_size as usize;
+            let mut table = u32_at(&gdt, d+0x8) as u64;
+            if desc_size >= 64{
+                table |= (u32_at(&gdt, d+0x28) as u64) << 32;
+            }
+            inode_tables.push(table.saturating_mul(block_size));
+        }
+        let directories = Vec::new();
+        Ok(Ext4Drive {file, directories, mounted_at, block_size, inode_size, inodes_per_group, feature_incompat, inode_tables, visited: HashSet::new(), files: Vec::new(), ignored_dirs})
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    fn read_inode(self: &Self, n: u32) -> Result<Inode, Error>{
+        let group = n.wrapping_sub(1) / self.inodes_per_group;
+        let index = n.wrapping_sub(1) % self.inodes_per_group;
+        let Some(table) = self.inode_tables.get(group as usize) else{
+            return Err(Error::corrupt(1024, format!("inode {n} doesn't exist")));
+        };
+        let b = self.read_bytes(table.saturating_add(index as u64*self.inode_size), self.inode_size)?;
+
+        let mode = u16_at(&b, 0x0);
+        let mut size = u32_at(&b, 0x4) as u64;
+        size |= (u32_at(&b, 0x6C) as u64) << 32;
+        let flags = u32_at(&b, 0x20);
+        // The extra fields only exist on inodes bigger than 128 bytes
+        let extra_isize = if self.inode_size > 128{u16_at(&b, 0x80) as u64}else{0};
+        let has_extra = |end: u64| 128 + extra_isize >= end && end <= self.inode_size;
+        let ctime_extra = if has_extra(0x88){Some(u32_at(&b, 0x84))}else{None};
+        let mtime_extra = if has_extra(0x8C){Some(u32_at(&b, 0x88))}else{None};
+        let last_modified_timestamp = extended_time(u32_at(&b, 0x10), mtime_extra);
+        // ext2/ext3 don't have a creation time, the inode change time is the closest thing
+        let create_timestamp = if has_extra(0x98){
+            extended_time(u32_at(&b, 0x90), Some(u32_at(&b, 0x94)))
+        }else{
+            extended_time(u32_at(&b, 0xC), ctime_extra)
+        };
+        Ok(Inode{mode, size, flags, create_timestamp, last_modified_timestamp, block: b[0x28..0x28+60].to_vec()})
+    }
+    /// Returns the physical blocks of a file in logical order (holes are skipped)
+    fn data_blocks(self: &Self, inode: &Inode) -> Result<Vec<u64>, Error>{
+        let mut blocks = Vec::new();
+        let block_count = inode.size.div_ceil(self.block_size);
+        if inode.flags & EXT4_EXTENTS_FL != 0{
+            let mut extents = Vec::new();
+            self.walk_extent_tree(&inode.block, &mut extents)?;
+            extents.sort();
+            for (logical, physical, len) in extents{
+                for b in 0..len{
+                    if logical + b < block_count{
+                        blocks.push(physical + b);
+                    }
+                }
+            }
+        }else{
+            // Old style block map: 12 direct blocks then single, double and triple indirect blocks
+            for i in 0..15{
+                let ptr = u32_at(&inode.block, i*4) as u64;
+                let depth = if i < 12{0}else{i-11};
+                self.walk_block_map(ptr, depth, block_count, &mut blocks)?;
+            }
+        }
+        Ok(blocks)
+    }
+    /// Pushes (logical block, physical block, length) for every leaf of the extent tree
+    fn walk_extent_tree(self: &Self, node: &[u8], extents: &mut Vec<(u64, u64, u64)>) -> Result<(), Error>{
+        if u16_at(node, 0) != EXTENT_MAGIC{
+            return Ok(());
+        }
+        let entries = u16_at(node, 2) as usize;
+        let depth = u16_at(node, 6);
+        for e in 0..entries{
+            let i = 12 + e*12;
+            if i + 12 > node.len(){
+                break;
+            }
+            let logical = u32_at(node, i) as u64;
+            if depth == 0{
+                let mut len = u16_at(node, i+4) as u64;
+                // Uninitialized extents (preallocated space) have the high bit set
+                if len > 32768{
+                    len -= 32768;
+                }
+                let physical = ((u16_at(node, i+6) as u64) << 32) | u32_at(node, i+8) as u64;
+                extents.push((logical, physical, len));
+            }else{
+                let leaf = ((u16_at(node, i+8) as u64) << 32) | u32_at(node, i+4) as u64;
+                let child = self.read_bytes(leaf*self.block_size, self.block_size)?;
+                // Every level is one less deep, a child that isn't would make this loop forever
+                if u16_at(&child, 6) >= depth{
+                    return Err(Error::corrupt(leaf*self.block_size, "extent tree node deeper than its parent"));
+                }
+                self.walk_extent_tree(&child, extents)?;
+            }
+        }
+        Ok(())
+    }
+    fn walk_block_map(self: &Self, ptr: u64, depth: usize, block_count: u64, blocks: &mut Vec<u64>) -> Result<(), Error>{
+        if ptr == 0 || blocks.len() as u64 >= block_count{
+            return Ok(());
+        }
+        if depth == 0{
+            blocks.push(ptr);
+            return Ok(());
+        }
+        let b = self.read_bytes(ptr*self.block_size, self.block_size)?;
+        for i in 0..(self.block_size/4) as usize{
+            self.walk_block_map(u32_at(&b, i*4) as u64, depth-1, block_count, blocks)?;
+        }
+        Ok(())
+    }
+    /// Returns (inode, name) for every entry in a directory (except "." and "..")
+    fn read_dir(self: &Self, inode: &Inode) -> Result<Vec<(u32, String)>, Error>{
+        let mut bytes = Vec::new();
+        for b in self.data_blocks(inode)?{
+            bytes.append(&mut self.read_bytes(b*self.block_size, self.block_size)?);
+        }
+        // Hash tree (htree) directories keep a linear layout: the tree nodes are hidden inside
+        // entries with inode 0 that span the whole block, so reading every block in order finds every entry
+        let mut entries = Vec::new();
+        let mut i = 0;
+        while i + 8 <= bytes.len(){
+            let entry_inode = u32_at(&bytes, i);
+            let rec_len = u16_at(&bytes, i+4) as usize;
+            let name_len = if self.feature_incompat & INCOMPAT_FILETYPE != 0{
+                bytes[i+6] as usize
+            }else{
+                u16_at(&bytes, i+6) as usize
+            };
+            if rec_len < 8{
+                // Corrupted entry, skip to the next block
+                i = (i / self.block_size as usize + 1) * self.block_size as usize;
+                continue;
+            }
+            if entry_inode != 0 && i + 8 + name_len <= bytes.len(){
+                let name = String::from_utf8_lossy(&bytes[i+8..i+8+name_len]).to_string();
+                if name != "." && name != ".."{
+                    entries.push((entry_inode, name));
+                }
+            }
+            i += rec_len;
+        }
+        Ok(entries)
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let root = self.read_inode(EXT4_ROOT_INODE)?;
+        self.visited.insert(EXT4_ROOT_INODE);
+        self.index(&root, 0)?;
+        Ok(self)
+    }
+    fn index(self: &mut Self, directory: &Inode, parent: u32) -> Result<(), Error>{
+        let mut new_dirs = Vec::new();
+        for (n, name) in self.read_dir(directory)?{
+            let inode = self.read_inode(n)?;
+            let is_dir = inode.is_dir();
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(Ext4File{
+                name: name.clone(),
+                parent,
+                size: inode.size,
+                is_dir,
+                create_timestamp: inode.create_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if is_dir && self.visited.insert(n){
+                new_dirs.push((inode, full_name));
+            }
+        }
+        for (inode, name) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(&inode, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct Ext4File{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_ext4_files_to_files(f: &Ext4File, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// Also reads ext2 and ext3
+pub struct Ext4Indexer;
+impl FilesystemIndexer for Ext4Indexer{
+    fn name(self: &Self) -> &'static str{
+        "Ext4"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        u16_at(b, 1024 + 0x38) == 0xEF53
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = Ext4Drive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_ext4_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at, u64_at};
+
+// The following code decodes F2FS following the kernel headers (include/linux/f2fs_fs.h)
+// and documentation https://docs.kernel.org/filesystems/f2fs.html
+// Every node (inode or block of pointers) is found through the Node Address Table (NAT),
+// the valid copy of every NAT block is chosen by the bitmap in the newest checkpoint
+
+const SUPER_OFFSET: u64 = 1024;
+const F2FS_MAGIC: u32 = 0xF2F5_2010;
+const BLOCK_SIZE: u64 = 4096;
+/// 9 bytes entries: version, inode number, block address
+const NAT_ENTRY_PER_BLOCK: u32 = 4096 / 9;
+const NAT_JOURNAL_ENTRY_SIZE: usize = 13;
+/// The summary of the hot data segment starts with 512 7 bytes entries, then the NAT journal
+const SUM_JOURNAL_OFFSET: usize = 512 * 7;
+const CP_COMPACT_SUM_FLAG: u32 = 0x4;
+const CP_LARGE_NAT_BITMAP_FLAG: u32 = 0x400;
+const FEATURE_FLEXIBLE_INLINE_XATTR: u32 = 0x40;
+const FEATURE_INODE_CRTIME: u32 = 0x100;
+const INLINE_XATTR: u8 = 0x1;
+const INLINE_DENTRY: u8 = 0x4;
+const EXTRA_ATTR: u8 = 0x20;
+const DEFAULT_INLINE_XATTR_ADDRS: usize = 50;
+/// Number of block addresses in an inode (without extra attributes), followed by 5 node ids
+const DEF_ADDRS_PER_INODE: usize = 923;
+const I_ADDR_OFFSET: usize = 360;
+const I_NID_OFFSET: usize = I_ADDR_OFFSET + DEF_ADDRS_PER_INODE*4;
+/// Addresses in a direct node and node ids in an indirect node
+const ADDRS_PER_BLOCK: usize = 1018;
+/// Not yet written (NEW_ADDR) and compressed cluster (COMPRESS_ADDR) markers aren't real blocks
+const NEW_ADDR: u32 = 0xFFFF_FFFF;
+const COMPRESS_ADDR: u32 = 0xFFFF_FFFE;
+/// A dentry block: bitmap, reserved bytes, 214 entries of 11 bytes, 214 name slots of 8 bytes
+const DENTRY_SLOTS: usize = 214;
+const DENTRY_BITMAP_SIZE: usize = 27;
+const DENTRY_RESERVED_SIZE: usize = 3;
+const DIR_ENTRY_SIZE: usize = 11;
+const SLOT_LEN: usize = 8;
+
+/// The part of an inode that matters to us
+#[derive(Debug, Default, Clone)]
+struct Inode{
+    mode: u16,
+    size: u64,
+    inline: u8,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    /// Where the block addresses (or inline data) start and how many there are
+    addrs_start: usize,
+    addrs_count: usize,
+    /// The whole node block
+    node: Vec<u8>,
+}
+impl Inode{
+    fn is_dir(self: &Self) -> bool{
+        self.mode & 0xF000 == 0x4000
+    }
+}
+struct F2fsDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    features: u32,
+    root_ino: u32,
+    nat_blkaddr: u64,
+    log_blocks_per_seg: u32,
+    /// Tells which of the two copies of every NAT block is valid
+    nat_bitmap: Vec<u8>,
+    /// Recent NAT updates that live in the checkpoint instead of the NAT blocks, node id -> block address
+    nat_journal: HashMap<u32, u32>,
+    nat_blocks: HashMap<u64, Vec<u8>>,
+    /// Directory inodes already indexed (directories can't be hard linked, seeing one twice means a loop)
+    visited: HashSet<u32>,
+    files: Vec<F2fsFile>,
+    ignored_dirs: Vec<String>
+}
+impl F2fsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut sb = vec![0u8; 3072];
+        file.read_at(&mut sb, SUPER_OFFSET).map_err(Error::io(SUPER_OFFSET))?;
+        // There is a second copy of the superblock in the next block
+        if u32_at(&sb, 0) != F2FS_MAGIC{
+            file.read_at(&mut sb, BLOCK_SIZE + SUPER_OFFSET).map_err(Error::io(BLOCK_SIZE + SUPER_OFFSET))?;
+        }
+        if u32_at(&sb, 0) != F2FS_MAGIC{
+            return Err(Error::WrongFilesystem("F2FS"));
+        }
+        if 1u64.checked_shl(u32_at(&sb, 16)) != Some(BLOCK_SIZE){
+            return Err(Error::Unsupported(format!("F2FS block size 2^{}", u32_at(&sb, 16))));
+        }
+        let log_blocks_per_seg = u32_at(&sb, 20);
+        if log_blocks_per_seg > 16{
+            return Err(Error::corrupt(SUPER_OFFSET + 20, format!("invalid blocks per segment 2^{log_blocks_per_seg}")));
+        }
+        let cp_blkaddr = u32_at(&sb, 76) as u64;
+        let mut drive = F2fsDrive {file, directories: Vec::new(), mounted_at, features: u32_at(&sb, 2180), root_ino: u32_at(&sb, 96),
+            nat_blkaddr: u32_at(&sb, 84) as u64, log_blocks_per_seg, nat_bitmap: Vec::new(), nat_journal: HashMap::new(),
+            nat_blocks: HashMap::new(), visited: HashSet::new(), files: Vec::new(), ignored_dirs};
+
+        // There are two checkpoint packs (one per segment), the valid one with the highest version is used.
+        // A pack is valid if its first and last block have the same version (it was completely written)
+        let mut checkpoint: Option<(u64, Vec<u8>)> = None;
+        for pack in [cp_blkaddr, cp_blkaddr + (1 << log_blocks_per_seg)]{
+            let cp = drive.read_block(pack)?;
+            let total = u32_at(&cp, 136) as u64;
+            if total == 0 || total > 1 << log_blocks_per_seg{
+                continue;
+            }
+            let version = u64_at(&cp, 0);
+            if u64_at(&drive.read_block(pack + total - 1)?, 0) != version{
+                continue;
+            }
+            if checkpoint.as_ref().is_none_or(|c| version > u64_at(&c.1, 0)){
+                checkpoint = Some((pack, cp));
+            }
+        }
+        let Some((pack, cp)) = checkpoint else{
+            return Err(Error::corrupt(cp_blkaddr*BLOCK_SIZE, "no valid checkpoint"));
+        };
+        let flags = u32_at(&cp, 132);
+        let sit_bitmap_size = u32_at(&cp, 156) as usize;
+        let nat_bitmap_size = u32_at(&cp, 160) as usize;
+        let nat_bitmap_start = if flags & CP_LARGE_NAT_BITMAP_FLAG != 0{
+            192 + 4
+        }else if u32_at(&sb, 1664) > 0{
+            // With cp_payload the SIT bitmap moves to the blocks after the checkpoint
+            192
+        }else{
+            192 + sit_bitmap_size
+        };
+        let Some(nat_bitmap) = cp.get(nat_bitmap_start..(nat_bitmap_start + nat_bitmap_size).min(cp.len())) else{
+            return Err(Error::corrupt(pack*BLOCK_SIZE + 156, "NAT bitmap outside of the checkpoint"));
+        };
+        drive.nat_bitmap = nat_bitmap.to_vec();
+
+        let summary = drive.read_block(pack + u32_at(&cp, 140) as u64)?;
+        // Compacted summaries start with the NAT journal
+        let journal = if flags & CP_COMPACT_SUM_FLAG != 0{0}else{SUM_JOURNAL_OFFSET};
+        let count = u16_at(&summary, journal) as usize;
+        for i in 0..count{
+            let e = journal + 2 + i*NAT_JOURNAL_ENTRY_SIZE;
+            if e + NAT_JOURNAL_ENTRY_SIZE > summary.len(){
+                break;
+            }
+            drive.nat_journal.insert(u32_at(&summary, e), u32_at(&summary, e + 9));
+        }
+        Ok(drive)
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    fn read_block(self: &Self, address: u64) -> Result<Vec<u8>, Error>{
+        self.read_bytes(address*BLOCK_SIZE, BLOCK_SIZE)
+    }
+    /// Block address of a node, None if the node doesn't exist
+    fn node_address(self: &mut Self, nid: u32) -> Result<Option<u64>, Error>{
+        let address = match self.nat_journal.get(&nid){
+            Some(&address) => {address}
+            None => {
+                let block = (nid / NAT_ENTRY_PER_BLOCK) as u64;
+                if !self.nat_blocks.contains_key(&block){
+                    // The two copies of the NAT are interleaved one segment at a time
+                    let blocks_per_seg = 1u64 << self.log_blocks_per_seg;
+                    let mut address = self.nat_blkaddr + ((block >> self.log_blocks_per_seg) << self.log_blocks_per_seg << 1) + (block & (blocks_per_seg - 1));
+                    let Some(&byte) = self.nat_bitmap.get((block / 8) as usize) else{
+                        return Ok(None);
+                    };
+                    if byte & (0x80 >> (block % 8)) != 0{
+                        address += blocks_per_seg;
+                    }
+                    let b = self.read_block(address)?;
+                    self.nat_blocks.insert(block, b);
+                }
+                let e = (nid % NAT_ENTRY_PER_BLOCK) as usize * 9;
+                u32_at(&self.nat_blocks[&block], e + 5)
+            }
+        };
+        if address == 0 || address == NEW_ADDR{
+            return Ok(None);
+        }
+        Ok(Some(address as u64))
+    }
+    fn read_node(self: &mut Self, nid: u32) -> Result<Option<Vec<u8>>, Error>{
+        let Some(address) = self.node_address(nid)? else{
+            return Ok(None);
+        };
+        let b = self.read_block(address)?;
+        // The footer at the end of the block repeats the node id, a stale NAT entry would point to another node
+        if u32_at(&b, BLOCK_SIZE as usize - 24) != nid{
+            return Ok(None);
+        }
+        Ok(Some(b))
+    }
+    fn read_inode(self: &mut Self, ino: u32) -> Result<Option<Inode>, Error>{
+        let Some(b) = self.read_node(ino)? else{
+            return Ok(None);
+        };
+        let inline = b[3];
+        let mut extra_isize = 0;
+        let mut create_timestamp = u64_at(&b, 40) as i64;
+        if inline & EXTRA_ATTR != 0{
+            extra_isize = u16_at(&b, I_ADDR_OFFSET) as usize;
+            if extra_isize % 4 != 0 || extra_isize > DEF_ADDRS_PER_INODE*4{
+                return Ok(None);
+            }
+            // Without the creation time, the inode change time is the closest thing (like ext2/ext3)
+            if self.features & FEATURE_INODE_CRTIME != 0 && extra_isize >= 20{
+                create_timestamp = u64_at(&b, I_ADDR_OFFSET + 12) as i64;
+            }
+        }
+        let inline_xattr_addrs = if inline & INLINE_XATTR == 0{
+            0
+        }else if self.features & FEATURE_FLEXIBLE_INLINE_XATTR != 0 && inline & EXTRA_ATTR != 0{
+            u16_at(&b, I_ADDR_OFFSET + 2) as usize
+        }else{
+            DEFAULT_INLINE_XATTR_ADDRS
+        };
+        let addrs_count = DEF_ADDRS_PER_INODE.saturating_sub(extra_isize / 4 + inline_xattr_addrs);
+        Ok(Some(Inode{
+            mode: u16_at(&b, 0),
+            size: u64_at(&b, 16),
+            inline,
+            create_timestamp,
+            last_modified_timestamp: u64_at(&b, 48) as i64,
+            addrs_start: I_ADDR_OFFSET + extra_isize,
+            addrs_count,
+            node: b,
+        }))
+    }
+    /// Returns the data blocks of a file in logical order (holes are skipped)
+    fn data_blocks(self: &mut Self, inode: &Inode) -> Result<Vec<u64>, Error>{
+        let block_count = inode.size.div_ceil(BLOCK_SIZE);
+        let mut blocks = Vec::new();
+        for i in 0..inode.addrs_count{
+            push_address(&mut blocks, u32_at(&inode.node, inode.addrs_start + i*4));
+        }
+        // Two direct nodes, two indirect nodes and a double indirect node
+        for (i, depth) in [0, 0, 1, 1, 2].into_iter().enumerate(){
+            if blocks.len() as u64 >= block_count{
+                break;
+            }
+            self.walk_node(u32_at(&inode.node, I_NID_OFFSET + i*4), depth, block_count, &mut blocks)?;
+        }
+        blocks.truncate(block_count as usize);
+        Ok(blocks)
+    }
+    fn walk_node(self: &mut Self, nid: u32, depth: u32, block_count: u64, blocks: &mut Vec<u64>) -> Result<(), Error>{
+        if nid == 0 || blocks.len() as u64 >= block_count{
+            return Ok(());
+        }
+        let Some(b) = self.read_node(nid)? else{
+            return Ok(());
+        };
+        for i in 0..ADDRS_PER_BLOCK{
+            if depth == 0{
+                push_address(blocks, u32_at(&b, i*4));
+            }else{
+                self.walk_node(u32_at(&b, i*4), depth - 1, block_count, blocks)?;
+            }
+        }
+        Ok(())
+    }
+    /// Returns (inode, name) for every entry in a directory (except "." and "..")
+    fn read_dir(self: &mut Self, inode: &Inode) -> Result<Vec<(u32, String)>, Error>{
+        let mut entries = Vec::new();
+        if inode.inline & INLINE_DENTRY != 0{
+            // Inline dentries use the space of the block addresses (the first one is reserved),
+            // the number of slots is the most that fits with one bitmap bit for every slot
+            let size = 4 * inode.addrs_count.saturating_sub(1);
+            let slots = size*8 / ((DIR_ENTRY_SIZE + SLOT_LEN)*8 + 1);
+            let bitmap_size = slots.div_ceil(8);
+            let reserved = size - ((DIR_ENTRY_SIZE + SLOT_LEN)*slots + bitmap_size);
+            let start = inode.addrs_start + 4;
+            read_dentries(&inode.node[start..start + size], slots, bitmap_size + reserved, &mut entries);
+        }else{
+            for b in self.data_blocks(inode)?{
+                let block = self.read_block(b)?;
+                read_dentries(&block, DENTRY_SLOTS, DENTRY_BITMAP_SIZE + DENTRY_RESERVED_SIZE, &mut entries);
+            }
+        }
+        Ok(entries)
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let Some(root) = self.read_inode(self.root_ino)? else{
+            return Err(Error::corrupt(self.nat_blkaddr*BLOCK_SIZE, format!("root inode {} not found", self.root_ino)));
+        };
+        self.visited.insert(self.root_ino);
+        self.index(&root, 0)?;
+        Ok(self)
+    }
+    fn index(self: &mut Self, directory: &Inode, parent: u32) -> Result<(), Error>{
+        let mut new_dirs = Vec::new();
+        for (n, name) in self.read_dir(directory)?{
+            let Some(inode) = self.read_inode(n)? else{
+                continue;
+            };
+            let is_dir = inode.is_dir();
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(F2fsFile{
+                name: name.clone(),
+                parent,
+                size: inode.size,
+                is_dir,
+                create_timestamp: inode.create_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if is_dir && self.visited.insert(n){
+                new_dirs.push((inode, full_name));
+            }
+        }
+        for (inode, name) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(&inode, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+fn push_address(blocks: &mut Vec<u64>, address: u32){
+    if address != 0 && address != NEW_ADDR && address != COMPRESS_ADDR{
+        blocks.push(address as u64);
+    }
+}
+/// Reads the entries of a dentry block (or inline dentries): a bitmap of used slots, then the entries,
+/// then the names in 8 bytes slots (long names take more than one slot)
+fn read_dentries(b: &[u8], slots: usize, entries_start: usize, entries: &mut Vec<(u32, String)>){
+    let names_start = entries_start + slots*DIR_ENTRY_SIZE;
+    let mut i = 0;
+    while i < slots{
+        if b[i / 8] & (1 << (i % 8)) == 0{
+            i += 1;
+            continue;
+        }
+        let e = entries_start + i*DIR_ENTRY_SIZE;
+        let ino = u32_at(b, e + 4);
+        let name_len = u16_at(b, e + 8) as usize;
+        let name_start = names_start + i*SLOT_LEN;
+        if name_start + name_len <= b.len(){
+            let name = String::from_utf8_lossy(&b[name_start..name_start + name_len]).to_string();
+            if name != "." && name != ".."{
+                entries.push((ino, name));
+            }
+        }
+        i += name_len.div_ceil(SLOT_LEN).max(1);
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct F2fsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_f2fs_files_to_files(f: &F2fsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// SD cards and Android devices
+pub struct F2fsIndexer;
+impl FilesystemIndexer for F2fsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "F2fs"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        u32_at(b, 1024) == 0xF2F5_2010
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = F2fsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_f2fs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
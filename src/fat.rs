@@ -0,0 +1,332 @@
+use std::collections::HashSet;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at};
+use crate::exfat::bytes_to_time;
+
+// The following code decodes FAT12, FAT16 and FAT32 (with VFAT long file names) following the
+// Microsoft FAT specification (fatgen103)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FatType{
+    Fat12,
+    Fat16,
+    Fat32,
+}
+struct FatDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    volume_label: String,
+    mounted_at: String,
+    fat_type: FatType,
+    cluster_size: u64,
+    /// Byte offset of cluster 2
+    data_offset: u64,
+    /// FAT12/FAT16 keep the root directory in a fixed region before the data (offset, size in bytes)
+    fixed_root: (u64, u64),
+    root_dir_cluster: u32,
+    cluster_count: u32,
+    /// First clusters of the directories already indexed, a damaged entry pointing back to a parent would loop forever
+    visited: HashSet<u32>,
+    files: Vec<FatFile>,
+    fat_table: Vec<u32>,
+    ignored_dirs: Vec<String>
+}
+impl FatDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut buffer = vec![0u8; 512];
+        file.read_at(&mut buffer, 0).map_err(Error::io(0))?;
+        if [buffer[510], buffer[511]] != [0x55, 0xAA]{ //BootSignature flag
+            return Err(Error::WrongFilesystem("FAT"));
+        }
+
+        let bytes_per_sector = u16_at(&buffer, 11) as u64;
+        let sectors_per_cluster = buffer[13] as u64;
+        if bytes_per_sector < 512 || sectors_per_cluster == 0{
+            return Err(Error::WrongFilesystem("FAT"));
+        }
+        let reserved_sectors = u16_at(&buffer, 14) as u64;
+        let number_of_fats = buffer[16] as u64;
+        let root_entries = u16_at(&buffer, 17) as u64;
+        let mut total_sectors = u16_at(&buffer, 19) as u64;
+        if total_sectors == 0{
+            total_sectors = u32_at(&buffer, 32) as u64;
+        }
+        let mut fat_size = u16_at(&buffer, 22) as u64;
+        if fat_size == 0{
+            fat_size = u32_at(&buffer, 36) as u64;
+        }
+        let root_dir_sectors = (root_entries*32).div_ceil(bytes_per_sector);
+        let first_data_sector = reserved_sectors + number_of_fats*fat_size + root_dir_sectors;
+        if first_data_sector >= total_sectors{
+            return Err(Error::corrupt(19, "the data region starts after the end of the volume"));
+        }
+        let cluster_count = ((total_sectors - first_data_sector) / sectors_per_cluster) as u32;
+        // The type of FAT is determined only by the number of clusters
+        let fat_type = if cluster_count < 4085{
+            FatType::Fat12
+        }else if cluster_count < 65525{
+            FatType::Fat16
+        }else{
+            FatType::Fat32
+        };
+        let root_dir_cluster = if fat_type == FatType::Fat32{u32_at(&buffer, 44)}else{0};
+        let fixed_root = (
+            (reserved_sectors + number_of_fats*fat_size)*bytes_per_sector,
+            root_dir_sectors*bytes_per_sector
+        );
+
+        //Fat table (only the first copy is used)
+        let mut b = vec![0_u8; (fat_size*bytes_per_sector) as usize];
+        file.read_at(&mut b, reserved_sectors*bytes_per_sector).map_err(Error::io(reserved_sectors*bytes_per_sector))?;
+        let entries = (cluster_count + 2) as usize;
+        let mut fat_table = Vec::with_capacity(entries);
+        for i in 0..entries{
+            let val = match fat_type{
+                FatType::Fat12 => {
+                    // Every entry is 12 bits, two entries share 3 bytes
+                    let o = i + i/2;
+                    if o + 1 >= b.len(){break;}
+                    let v = u16_at(&b, o) as u32;
+                    if i % 2 == 0{v & 0xFFF}else{v >> 4}
+                }
+                FatType::Fat16 => {
+                    if i*2 + 1 >= b.len(){break;}
+                    u16_at(&b, i*2) as u32
+                }
+                FatType::Fat32 => {
+                    if i*4 + 3 >= b.len(){break;}
+                    u32_at(&b, i*4) & 0x0FFFFFFF
+                }
+            };
+            fat_table.push(val);
+        }
+        let directories = Vec::new();
+        Ok(FatDrive {file, directories, volume_label: String::new(), mounted_at, fat_type,
+            cluster_size: bytes_per_sector*sectors_per_cluster, data_offset: first_data_sector*bytes_per_sector,
+            fixed_root, root_dir_cluster, cluster_count, visited: HashSet::new(), files: Vec::new(), fat_table, ignored_dirs})
+    }
+    fn cluster_to_byte(self: &Self, cluster: u32)->u64{
+        (cluster as u64-2)*self.cluster_size+self.data_offset
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    /// Returns None at the end of the chain (or if the chain is broken)
+    fn find_next_in_fat(self: &Self, val: u32) -> Option<u32>{
+        let next = *self.fat_table.get(val as usize)?;
+        let end_of_chain = match self.fat_type{
+            FatType::Fat12 => {0xFF7}
+            FatType::Fat16 => {0xFFF7}
+            FatType::Fat32 => {0x0FFFFFF7}
+        };
+        // 0 and 1 are free/reserved, end_of_chain is a bad cluster and anything above it is the end
+        if next < 2 || next >= end_of_chain || next >= self.cluster_count + 2{
+            return None;
+        }
+        Some(next)
+    }
+    fn read_chain(self: &Self, first_cluster: u32) -> Result<Vec<u8>, Error>{
+        let mut bytes = Vec::new();
+        if first_cluster < 2{
+            return Ok(bytes);
+        }
+        let mut cluster = Some(first_cluster);
+        let mut visited = 0;
+        while let Some(c) = cluster{
+            bytes.append(&mut self.read_bytes(self.cluster_to_byte(c), self.cluster_size)?);
+            // A chain can't be longer than the volume, stop if it loops
+            visited += 1;
+            if visited > self.cluster_count{
+                break;
+            }
+            cluster = self.find_next_in_fat(c);
+        }
+        Ok(bytes)
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let bytes = if self.fat_type == FatType::Fat32{
+            self.visited.insert(self.root_dir_cluster);
+            self.read_chain(self.root_dir_cluster)?
+        }else{
+            self.read_bytes(self.fixed_root.0, self.fixed_root.1)?
+        };
+        self.index(&bytes, 0)?;
+        Ok(self)
+    }
+    fn index(self: &mut Self, bytes: &[u8], parent: u32) -> Result<(), Error>{
+        let mut new_dirs = Vec::new();
+        // Long file name parts are stored in reverse order right before the short entry
+        let mut lfn: Vec<u16> = Vec::new();
+        let mut lfn_checksum = 0;
+        let mut i = 0;
+        while i + 32 <= bytes.len(){
+            let e = &bytes[i..i+32];
+            i += 32;
+            match e[0]{
+                0x00 => {break;} // End of directory
+                0xE5 => {lfn.clear(); continue;} // Deleted entry
+                _ => {}
+            }
+            let attr = e[11];
+            if attr & 0x3F == 0x0F{
+                // VFAT long file name entry
+                let order = e[0];
+                if order & 0x40 != 0{
+                    lfn.clear();
+                    lfn_checksum = e[13];
+                }
+                let mut part = Vec::new();
+                for k in (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2)){
+                    part.push(u16_at(e, k));
+                }
+                part.append(&mut lfn);
+                lfn = part;
+                continue;
+            }
+            if attr & 0x08 != 0{
+                // Volume label
+                if parent == 0{
+                    self.volume_label = short_name(e, 0).trim().to_string();
+                }
+                lfn.clear();
+                continue;
+            }
+            let mut name = short_name(e, e[12]);
+            if !lfn.is_empty() && lfn_checksum == short_name_checksum(&e[0..11]){
+                let end = lfn.iter().position(|&c| c == 0x0000 || c == 0xFFFF).unwrap_or(lfn.len());
+                name = String::from_utf16_lossy(&lfn[..end]);
+            }
+            lfn.clear();
+            if name == "." || name == ".."{
+                continue;
+            }
+            let is_dir = attr & 0x10 != 0;
+            let mut first_cluster = u16_at(e, 26) as u32;
+            if self.fat_type == FatType::Fat32{
+                first_cluster |= (u16_at(e, 20) as u32) << 16;
+            }
+            let created_t = bytes_to_time(e[14], e[15], e[16], e[17], e[13], 0);
+            let modified_t = bytes_to_time(e[22], e[23], e[24], e[25], 0, 0);
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            if is_dir{
+                // Directories have a size of 0 on FAT, use what they take on disk instead
+                let dir_bytes = self.read_chain(first_cluster)?;
+                self.files.push(FatFile{name, parent, size: dir_bytes.len() as u64, is_dir, create_timestamp: created_t, last_modified_timestamp: modified_t, attributes: attr});
+                if self.visited.insert(first_cluster){
+                    new_dirs.push((dir_bytes, full_name));
+                }
+            }else{
+                let size = u32_at(e, 28) as u64;
+                self.files.push(FatFile{name, parent, size, is_dir, create_timestamp: created_t, last_modified_timestamp: modified_t, attributes: attr});
+            }
+        }
+        for (dir_bytes, name) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(&dir_bytes, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// Decodes a 8.3 name, `case` is the NT byte that says if the base name and the extension are lowercase
+fn short_name(e: &[u8], case: u8) -> String{
+    let mut base = e[0..8].to_vec();
+    // 0xE5 is a valid first character in some codepages, it is stored as 0x05
+    if base[0] == 0x05{
+        base[0] = 0xE5;
+    }
+    // Bytes outside of ASCII depend on the OEM codepage, Latin-1 is the best guess without knowing it
+    let mut base: String = base.iter().map(|&c| c as char).collect::<String>().trim_end().to_string();
+    let mut ext: String = e[8..11].iter().map(|&c| c as char).collect::<String>().trim_end().to_string();
+    if case & 0x08 != 0{
+        base = base.to_lowercase();
+    }
+    if case & 0x10 != 0{
+        ext = ext.to_lowercase();
+    }
+    if ext.is_empty(){
+        base
+    }else{
+        base + "." + &ext
+    }
+}
+fn short_name_checksum(name: &[u8]) -> u8{
+    let mut sum: u8 = 0;
+    for &c in name{
+        sum = (if sum & 1 != 0{0x80}else{0}) + (sum >> 1);
+        sum = sum.wrapping_add(c);
+    }
+    sum
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct FatFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    /// The attribute byte of the directory entry
+    attributes: u8,
+}
+fn from_fat_files_to_files(f: &FatFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes(f.attributes as u32),
+    }
+}
+
+fn is_fat(b: &[u8]) -> bool{
+    // The filesystem type string is only informative (and some formatters leave it empty),
+    // the BIOS parameter block has to make sense and either the string or the media descriptor must be there
+    let has_label = &b[54..59] == b"FAT12" || &b[54..59] == b"FAT16" || &b[54..62] == b"FAT     " || &b[82..87] == b"FAT32";
+    let bytes_per_sector = u16_at(b, 11);
+    let sectors_per_cluster = b[13];
+    let valid_bpb = matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+        && sectors_per_cluster != 0 && sectors_per_cluster.is_power_of_two()
+        && u16_at(b, 14) != 0 && b[16] != 0;
+    let valid_media = b[21] == 0xF0 || b[21] >= 0xF8;
+    valid_bpb && (has_label || valid_media)
+}
+/// FAT12, FAT16 and FAT32
+pub struct FatIndexer;
+impl FilesystemIndexer for FatIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Fat"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        b[510] == 0x55 && b[511] == 0xAA && is_fat(b)
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = FatDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_fat_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
@@ -1,11 +1,10 @@
 use eframe::egui::{self, FontId, TextWrapMode};
-use eframe;
 use std::thread;
-use crate::{self as main, SupportedFilesystems, save_cache, save_drives, save_settings};
+use anything::{self as main, Index, Query, SearchResult, save_drives, save_settings};
 
 #[derive(Debug, Default)]
 struct Anything{
-    items: (Vec<main::File>, Vec<main::Directory>),
+    index: Index,
     settings: main::Settings,
     drives: Vec<main::Drive>,
     searching_for: String,
@@ -16,14 +15,23 @@ struct Anything{
     no_disk_popup: bool,
     info_popup: bool,
     temp: String,
+    /// Text boxes of the archive settings
+    temp_archive_size: String,
+    temp_archive_depth: String,
     temp_drives: Vec<main::Drive>,
+    /// Path typed in the Drive Settings window to add a disk image
+    image_path: String,
     indexed: bool,
-    indexing_handle_thread: Option<std::thread::JoinHandle<(Vec<main::File>,Vec<main::Directory>)>>,
+    indexing_handle_thread: Option<std::thread::JoinHandle<(Index, main::IndexReport)>>,
+    /// What went wrong the last time the drives were indexed, failed drives are shown in the status bar
+    /// and everything is listed in the Index Report window
+    report: main::IndexReport,
+    report_window: bool,
     finished_indexing: bool,
     time_last_index: Option<std::time::Instant>,
     time_last_change: Option<std::time::Instant>,
-    search_thread: Option<std::thread::JoinHandle<Vec<main::File>>>,
-    search_results: Vec<main::File>,
+    search_thread: Option<std::thread::JoinHandle<Vec<SearchResult>>>,
+    search_results: Vec<SearchResult>,
     cancel_search: Option<std::sync::mpsc::Sender<u8>>,
     times_it_has_indexed: u32,
     not_first_frame: bool,
@@ -31,68 +39,30 @@ struct Anything{
 
 impl Anything{
     fn new(_cc: &eframe::CreationContext<'_>) -> Self{
-        let mut app = Anything::default();
-        app.settings = main::load_settings();
-        app.drives = main::load_drives();
-        if app.drives.len() == 0{
+        let mut app = Anything{
+            settings: main::load_settings(),
+            drives: main::load_drives(),
+            ..Default::default()
+        };
+        if app.drives.is_empty(){
             app.no_disk_popup = true;
         }
-        if app.settings.columns.len() == 0{
-            app.settings.columns = vec![200, 950, 100, 150, 150]
+        if app.settings.columns.is_empty(){
+            app.settings.columns = vec![200, 950, 100, 150, 150, 100]
+        }
+        // Settings saved before the Attributes column only have the first five
+        if app.settings.columns.len() < 6{
+            app.settings.columns.resize(6, 100);
         }
-        app.items = main::load_cache();
+        app.index = Index::load();
         app.temp = app.settings.index_every_minutes.to_string();
+        app.temp_archive_size = app.settings.archive_max_size_mb.to_string();
+        app.temp_archive_depth = app.settings.archive_max_depth.to_string();
         app
     }
     fn sort_items(&mut self){
-        match self.settings.sort_in_use{
-            main::Sort::DateCreatedAscending => {
-                self.items.0.sort_by(|a,b| a.create_timestamp.cmp(&b.create_timestamp));
-                self.search_results.sort_by(|a,b| a.create_timestamp.cmp(&b.create_timestamp));
-
-            },
-            main::Sort::DateCreatedDescending => {
-                self.items.0.sort_by(|b,a| a.create_timestamp.cmp(&b.create_timestamp));
-                self.search_results.sort_by(|b,a| a.create_timestamp.cmp(&b.create_timestamp));
-            },
-            main::Sort::DateModifiedAscending => {
-                self.items.0.sort_by(|a,b| a.last_modified_timestamp.cmp(&b.last_modified_timestamp));
-                self.search_results.sort_by(|a,b| a.last_modified_timestamp.cmp(&b.last_modified_timestamp));
-            },
-            main::Sort::DateModifiedDescending => {
-                self.items.0.sort_by(|b,a| a.last_modified_timestamp.cmp(&b.last_modified_timestamp));
-                self.search_results.sort_by(|b,a| a.last_modified_timestamp.cmp(&b.last_modified_timestamp));
-            },
-            main::Sort::SizeAscending => {
-                self.items.0.sort_by(|a,b| a.size.cmp(&b.size));
-                self.search_results.sort_by(|a,b| a.size.cmp(&b.size));
-            },
-            main::Sort::SizeDescending => {
-                self.items.0.sort_by(|b,a| a.size.cmp(&b.size));
-                self.search_results.sort_by(|b,a| a.size.cmp(&b.size));
-            },
-            main::Sort::PathAscending => {
-                self.items.0.sort_by(|a,b|
-                    self.items.1[a.parent as usize].name.cmp(&self.items.1[b.parent as usize].name));
-                self.search_results.sort_by(|a,b|
-                    self.items.1[a.parent as usize].name.cmp(&self.items.1[b.parent as usize].name));
-
-            },
-            main::Sort::PathDescending => {
-                self.items.0.sort_by(|b,a|
-                    self.items.1[a.parent as usize].name.cmp(&self.items.1[b.parent as usize].name));
-                self.search_results.sort_by(|b,a|
-                    self.items.1[a.parent as usize].name.cmp(&self.items.1[b.parent as usize].name));
-            },
-            main::Sort::FileAscending => {
-                self.items.0.sort_by(|a,b| a.name.cmp(&b.name));
-                self.search_results.sort_by(|a,b| a.name.cmp(&b.name));
-            },
-            main::Sort::FileDescending => {
-                self.items.0.sort_by(|b,a| a.name.cmp(&b.name));
-                self.search_results.sort_by(|b,a| a.name.cmp(&b.name));
-            },
-        }
+        self.index.sort(&self.settings.sort_in_use);
+        self.index.sort_results(&mut self.search_results, &self.settings.sort_in_use);
     }
     fn render_table(&mut self, ui: &mut egui::Ui) {
 
@@ -118,6 +88,7 @@ impl Anything{
             .column(Column::initial(self.settings.columns[2] as f32).resizable(true))
             .column(Column::initial(self.settings.columns[3] as f32).resizable(true))
             .column(Column::initial(self.settings.columns[4] as f32).resizable(true))
+            .column(Column::initial(self.settings.columns[5] as f32).resizable(true))
             .striped(true)
             .animate_scrolling(false)
             .scroll_bar_visibility(egui::scroll_area::ScrollBarVisibility::AlwaysVisible)
@@ -187,25 +158,37 @@ impl Anything{
                         };
                     });
                 });
+                header.col(|ui| {
+                    ui.add_sized(ui.available_size(), egui::Label::new("Attributes"));
+                });
             })
             .body(| body| {
                 body.rows(24.0, self.search_results.len()+5, |mut row| {
                     let row_index = row.index();
                     if row_index < self.search_results.len(){
+                        let file = &self.search_results[row_index].file;
+                        row.col(|ui| {
+                            let name = ui.label(file_text(ui, file.name.clone(), file));
+                            match file.deleted{
+                                Some(main::Deleted::Recoverable) => {name.on_hover_text("Deleted, its content is probably still on the drive");}
+                                Some(main::Deleted::Overwritten) => {name.on_hover_text("Deleted, other files were written over it");}
+                                None => {}
+                            }
+                        });
                         row.col(|ui| {
-                            ui.label(&self.search_results[row_index].name);
+                            ui.label(file_text(ui, self.search_results[row_index].path(&self.index), file));
                         });
                         row.col(|ui| {
-                            ui.label(self.items.1[self.search_results[row_index].parent as usize].name.clone()+&self.search_results[row_index].name);
+                            ui.label(file_text(ui, main::size_to_pretty_string(file.size), file));
                         });
                         row.col(|ui| {
-                            ui.label(main::size_to_pretty_string(self.search_results[row_index].size));
+                            ui.label(file_text(ui, main::timestamp_to_string(file.create_timestamp), file));
                         });
                         row.col(|ui| {
-                            ui.label(main::timestamp_to_string(self.search_results[row_index].create_timestamp));
+                            ui.label(file_text(ui, main::timestamp_to_string(file.last_modified_timestamp), file));
                         });
                         row.col(|ui| {
-                            ui.label(main::timestamp_to_string(self.search_results[row_index].last_modified_timestamp));
+                            ui.label(file_text(ui, file.attributes.to_letters(), file));
                         });
                     }else{
                         row.col(|_ui|{});
@@ -213,6 +196,7 @@ impl Anything{
                         row.col(|_ui|{});
                         row.col(|_ui|{});
                         row.col(|_ui|{});
+                        row.col(|_ui|{});
                     }
                 });
             });
@@ -220,321 +204,21 @@ impl Anything{
 
 
 }
-/// Vec< negation, starts_with, ends_with
-fn convert_string_to_predicates(searching_for: String)->Vec<(bool,bool,bool,String)>{
-    let mut output = Vec::new();
-    if searching_for.contains("\\"){
-        let parts: Vec<&str> = searching_for.split("\\").collect();
-        for p in parts{
-            let mut negation = false;
-            let mut starts_with = false;
-            let mut ends_with = false;
-            let string;
-
-            if !p.is_empty(){
-                if p.starts_with("!"){
-                    negation = true;
-                    if p.starts_with("!_*"){
-                        starts_with = true;
-                    } else if p.starts_with("!*_"){
-                        ends_with = true;
-                    }
-                }
-                if p.starts_with("_*"){
-                    starts_with = true;
-                } else if p.starts_with("*_"){
-                    ends_with = true;
-                }
-                if p.starts_with(" "){}
-                if negation && (starts_with || ends_with){
-                    string = p[3..].to_string();
-                }else if !negation && (starts_with || ends_with){
-                    string = p[2..].to_string();
-                }else{
-                    string = p[1..].to_string();
-                }
-                output.push((negation,starts_with,ends_with,string));
-            }
-        }
-        output
-    }else{
-        vec![(false,false,false,searching_for)]
-    }
-}
-fn search(items: Vec<main::File>, directories: Vec<main::Directory>, settings: main::Settings, searching_for: String,cancel_flag: std::sync::mpsc::Receiver<u8>)->Vec<main::File>{
-    let mut output: Vec<main::File> = Vec::new();
-    let pred = convert_string_to_predicates(searching_for.clone());
-    let contains_slash = if searching_for.contains(&"/"){true}else{false};
-    // dbg!(&pred);
-
-    let mut cache_dir = vec![false; directories.len()];
-    if settings.search_full_path && !contains_slash{
-        for i in 0..pred.len(){
-            if i == 0{
-                for j in 0..directories.len(){
-                    match cancel_flag.try_recv(){
-                        Ok(1) => {return output;}
-                        _=>{}
-                    }
-                    let p = pred[i].clone();
-                    let n = if settings.ignore_case{directories[j].name.clone().to_lowercase()}else{directories[j].name.clone()};
-                    let m = if settings.ignore_case{p.3.clone().to_lowercase()}else{p.3.clone()};
-                    // Negate
-                    if p.0{
-                        // Not Starts With
-                        if p.1{
-                            if !n.starts_with(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                        // Not ends with
-                        else if p.2{
-                            cache_dir[j] = false;
-                        }
-                        // Not contains
-                        else{
-                            if !n.contains(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                    // Normal
-                    }else{
-                        // Starts With
-                        if p.1{
-                            if n.starts_with(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                        // Ends with
-                        else if p.2{
-                            cache_dir[j] = false;
-                        }
-                        // contains
-                        else{
-                            if n.contains(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                    }
-                }
-            }else{
-                for j in 0..directories.len(){
-                    match cancel_flag.try_recv(){
-                        Ok(1) => {return output;}
-                        _=>{}
-                    }
-                    let p = pred[i].clone();
-                    let n = if settings.ignore_case{directories[j].name.clone().to_lowercase()}else{directories[j].name.clone()};
-                    let m = if settings.ignore_case{p.3.clone().to_lowercase()}else{p.3.clone()};
-                    // Negate
-                    if p.0{
-                        // Not Starts With
-                        if p.1{
-                            if !n.starts_with(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                        // Not ends with
-                        else if p.2{
-                            cache_dir[j] = false;
-                        }
-                        // Not contains
-                        else{
-                            if !n.contains(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                    // Normal
-                    }else{
-                        // Starts With
-                        if p.1{
-                            if n.starts_with(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                        // Ends with
-                        else if p.2{
-                            cache_dir[j] = false;
-                        }
-                        // contains
-                        else{
-                            if n.contains(&m){
-                                if cache_dir[j]{}
-                            }else{
-                                cache_dir[j] = false;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    for i in 0..pred.len(){
-        if i == 0{
-            //Initial pred build all the results
-            let p = pred[i].clone();
-            for item in 0..items.len(){
-                match cancel_flag.try_recv(){
-                    Ok(1) => {return output;}
-                    _=>{}
-                }
-                let f: main::File = items[item].clone();
-                if cache_dir[f.parent as usize]{
-                    output.push(f);
-                }else{
-                        let mut n;
-                        let mut m = p.3.clone();
-                        if settings.search_full_path && contains_slash {
-                            n = directories[f.parent as usize].name.clone() + &f.name;
-                        }else{
-                            n = f.name.clone();
-                        }
-                        if settings.ignore_case{
-                            n = n.to_lowercase();
-                            m = m.to_lowercase();
-                        }
-                        // Negate
-                        if p.0{
-                            // Not Starts With
-                            if p.1{
-                                if !n.starts_with(&m){
-                                    output.push(f);
-                                }
-                            }
-                            // Not Ends With
-                            else if p.2{
-                                if !n.ends_with(&m){
-                                    output.push(f);
-                                }
-                            }
-                            // Not contains
-                            else{
-                                if !n.contains(&m){
-                                    output.push(f);
-                                }
-                            }
-                        // Normal
-                        }else{
-                            // Starts With
-                            if p.1{
-                                if n.starts_with(&m){
-                                    output.push(f);
-                                }
-                            }
-                            // Ends With
-                            else if p.2{
-                                if n.ends_with(&m){
-                                    output.push(f);
-                                }
-                            }
-                            // contains
-                            else{
-                                if n.contains(&m){
-                                    output.push(f);
-                                }
-                            }
-                        }
-                    }
-                }
-        } else {
-            //Later predicates only use from the previous results
-            let mut temp = Vec::new();
-            let p = pred[i].clone();
-            for o in 0..output.len(){
-                match cancel_flag.try_recv(){
-                    Ok(1) => {return temp;}
-                    _=>{}
-                }
-                let f: main::File = output[o].clone();
-                if cache_dir[f.parent as usize]{
-                    temp.push(f);
-                }else{
-                    let mut n;
-                    let mut m = p.3.clone();
-                    if settings.search_full_path && contains_slash {
-                        n = directories[f.parent as usize].name.clone() + &f.name;
-                    }else{
-                        n = f.name.clone();
-                    }
-                    if settings.ignore_case{
-                        n = n.to_lowercase();
-                        m = m.to_lowercase();
-                    }
-                    // Negate
-                    if p.0{
-                        // Not Starts With
-                        if p.1{
-                            if !n.starts_with(&m){
-                                temp.push(f);
-                            }
-                        }
-                        // Not Ends With
-                        else if p.2{
-                            if !n.ends_with(&m){
-                                temp.push(f);
-                            }
-                        }
-                        // Not contains
-                        else{
-                            if !n.contains(&m){
-                                temp.push(f);
-                            }
-                        }
-                    // Normal
-                    }else{
-                        // Starts With
-                        if p.1{
-                            if n.starts_with(&m){
-                                temp.push(f);
-                            }
-                        }
-                        // Ends With
-                        else if p.2{
-                            if n.ends_with(&m){
-                                temp.push(f);
-                            }
-                        }
-                        // contains
-                        else{
-                            if n.contains(&m){
-                                temp.push(f);
-                            }
-                        }
-                    }
-                }
-            }
-            output = temp;
-        }
+/// Deleted files are struck through, in the warning color if they can still be recovered
+/// and in the error color if they were overwritten
+fn file_text(ui: &egui::Ui, text: String, file: &main::File) -> egui::RichText{
+    let text = egui::RichText::new(text);
+    match file.deleted{
+        Some(main::Deleted::Recoverable) => {text.strikethrough().color(ui.visuals().warn_fg_color)}
+        Some(main::Deleted::Overwritten) => {text.strikethrough().color(ui.visuals().error_fg_color)}
+        None => {text}
     }
-    output
 }
-fn index_drives(drives: Vec<main::Drive>)->(Vec<main::File>, Vec<main::Directory>){
-    let mut items = (Vec::new(), Vec::new());
-    for d in drives.clone(){
-        match d.fs{
-            SupportedFilesystems::Exfat => {
-                let idx = items.1.len() as u32;
-                let (mut files, mut dir) = main::exfat::index(d.drive, d.mounted_at, d.ignored_dirs, idx);
-                items.0.append(&mut files);
-                items.1.append(&mut dir);
-            }
-        }
+fn detected_fs_to_string(detected_fs: Option<&'static str>) -> String{
+    match detected_fs{
+        Some(fs) => {format!("(detected: {})    ", fs)}
+        None => {String::from("(detected: unreadable)    ")}
     }
-    items
 }
 impl eframe::App for Anything {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
@@ -547,41 +231,33 @@ impl eframe::App for Anything {
             }
         }
         if self.time_last_change.is_some(){
-            if self.cancel_search.is_some(){
-                let _ = self.cancel_search.as_ref().unwrap().send(1);
+            if let Some(cancel_search) = &self.cancel_search{
+                let _ = cancel_search.send(1);
             }
             if self.time_last_change.unwrap().elapsed() > std::time::Duration::from_millis(300){
                 let (s, r) = std::sync::mpsc::channel::<u8>();
                 self.cancel_search = Some(s);
                 self.time_last_change = None;
 
-                let ptr = self.items.0.as_ptr();
-                let len = self.items.0.len();
-                let slice = unsafe { std::slice::from_raw_parts(ptr, len) };
-                let ptr = self.items.1.as_ptr();
-                let len = self.items.1.len();
-                let slice_2 = unsafe { std::slice::from_raw_parts(ptr, len) };
-                let settings_clone = self.settings.clone();
-                let searching_for = self.searching_for.clone();
+                let index = self.index.clone();
+                let query = Query::with_settings(&self.searching_for, &self.settings);
                 let cancel_flag = r;
-                self.search_thread = Some(thread::spawn(move ||search(slice.to_vec(),slice_2.to_vec(), settings_clone, searching_for, cancel_flag)));
+                self.search_thread = Some(thread::spawn(move ||index.search_with_cancel(&query, &cancel_flag)));
 
                 self.status = String::from("Searching...");
             }
         }
 
-        if let Some(handle) = &self.search_thread{
-            if handle.is_finished()  {
-                if let Some(completed_handle) = self.search_thread.take() {
-                    match completed_handle.join() {
-                        Ok(res) => {
-                            self.status = format!("{} Files/Directories found",res.len());
-                            self.search_results = res;
-                            self.search_thread = None;
-                        }
-                        Err(_) => {self.status = String::from("Searching Interrupted or Failed...")}
-                    }
+        if let Some(handle) = &self.search_thread
+            && handle.is_finished()
+            && let Some(completed_handle) = self.search_thread.take() {
+            match completed_handle.join() {
+                Ok(res) => {
+                    self.status = format!("{} Files/Directories found",res.len());
+                    self.search_results = res;
+                    self.search_thread = None;
                 }
+                Err(_) => {self.status = String::from("Searching Interrupted or Failed...")}
             }
         }
 
@@ -589,47 +265,46 @@ impl eframe::App for Anything {
             self.indexed = true
         }
 
-        if !self.indexed{
-            if self.status != String::from("Searching..."){
-                self.indexed = true;
-                let d_clone = self.drives.clone();
-                self.indexing_handle_thread = Some(thread::spawn(||index_drives(d_clone)));
-                self.finished_indexing = false;
-                self.time_last_index = Some(std::time::Instant::now());
-            }
+        if !self.indexed && self.status != "Searching..."{
+            self.indexed = true;
+            let d_clone = self.drives.clone();
+            let settings_clone = self.settings.clone();
+            self.indexing_handle_thread = Some(thread::spawn(move ||Index::build(&d_clone, &settings_clone)));
+            self.finished_indexing = false;
+            self.time_last_index = Some(std::time::Instant::now());
         }
 
         if let Some(handle) = &self.indexing_handle_thread {
                     self.status = String::from("Indexing...");
-                    if handle.is_finished() && !self.finished_indexing {
-                        if let Some(completed_handle) = self.indexing_handle_thread.take() {
-                            match completed_handle.join() {
-                                Ok(items) => {
-                                    self.items = items;
-                                    self.sort_items();
-                                    self.status = format!("Indexing took: {:.3?}, Files found: {}"
-                                        ,self.time_last_index.unwrap().elapsed(),self.items.0.len());
-                                    self.finished_indexing = true;
-                                    self.times_it_has_indexed += 1;
-                                    self.time_last_change = Some(std::time::Instant::now());
+                    if handle.is_finished() && !self.finished_indexing
+                        && let Some(completed_handle) = self.indexing_handle_thread.take() {
+                        match completed_handle.join() {
+                            Ok((index, report)) => {
+                                self.index = index;
+                                self.report = report;
+                                self.sort_items();
+                                self.status = format!("Indexing took: {:.3?}, Files found: {}"
+                                    ,self.time_last_index.unwrap().elapsed(),self.index.files().len());
+                                self.finished_indexing = true;
+                                self.times_it_has_indexed += 1;
+                                self.time_last_change = Some(std::time::Instant::now());
 
-                                }
-                                Err(_) => {
-                                    self.status = String::from("Indexing failed: probably because of lacking permission or a drive didn't exist");
-                                    self.finished_indexing = true;
-                                }
+                            }
+                            Err(_) => {
+                                self.status = String::from("Indexing failed unexpectedly");
+                                self.finished_indexing = true;
                             }
                         }
                     }
                 }
-        if self.time_last_index.is_none(){
-            self.time_last_index = Some(std::time::Instant::now());
-        } else {
+        if let Some(time_last_index) = self.time_last_index{
             let m = self.settings.index_every_minutes as u64;
             if std::time::Duration::from_secs(m * 60) >std::time::Duration::from_secs(1) &&
-            self.time_last_index.unwrap().elapsed() > std::time::Duration::from_secs(m * 60){
+            time_last_index.elapsed() > std::time::Duration::from_secs(m * 60){
                 self.indexed = false;
             }
+        } else {
+            self.time_last_index = Some(std::time::Instant::now());
         }
         // No disk warning
         let mut open_warning = self.no_disk_popup;
@@ -681,6 +356,9 @@ impl eframe::App for Anything {
                     if ui.button("Disks").clicked() {
                         self.disk_window = true;
                     }
+                    if ui.button("Index Report").clicked() {
+                        self.report_window = true;
+                    }
                     ui.checkbox(&mut self.settings.light_mode, "Light mode").changed().then(|| {
                         if self.settings.light_mode {
                             ctx.set_theme(egui::Theme::Light);
@@ -707,7 +385,8 @@ impl eframe::App for Anything {
                 if ui.button("🔄").clicked(){
                     self.indexed = true;
                     let d_clone = self.drives.clone();
-                    self.indexing_handle_thread = Some(thread::spawn(||index_drives(d_clone)));
+                    let settings_clone = self.settings.clone();
+                    self.indexing_handle_thread = Some(thread::spawn(move ||Index::build(&d_clone, &settings_clone)));
                     self.finished_indexing = false;
                     self.time_last_index = Some(std::time::Instant::now());
                 }
@@ -715,10 +394,9 @@ impl eframe::App for Anything {
                     self.time_last_change = Some(std::time::Instant::now());
                 }
                 if ui.add(egui::TextEdit::singleline(&mut self.searching_for)
-                    .desired_width(ui.available_width() * 1.0)).changed(){
-                        if self.settings.instant_search{
-                            self.time_last_change = Some(std::time::Instant::now());
-                        }
+                    .desired_width(ui.available_width() * 1.0)).changed()
+                    && self.settings.instant_search{
+                        self.time_last_change = Some(std::time::Instant::now());
                 };
             });
         });
@@ -740,10 +418,8 @@ impl eframe::App for Anything {
                             ui.label("Index Once every");
                             if ui.text_edit_singleline(&mut temp).changed(){
                                 self.temp = temp.clone();
-                                let try_convert = temp.parse::<u32>();
-                                if try_convert.is_ok(){
-                                    new_settings.index_every_minutes = try_convert.unwrap();
-
+                                if let Ok(minutes) = temp.parse::<u32>(){
+                                    new_settings.index_every_minutes = minutes;
                                 }
                             }
                             ui.label("Minutes");
@@ -760,6 +436,22 @@ impl eframe::App for Anything {
                         ui.horizontal(|ui|{
                             ui.checkbox(&mut new_settings.search_full_path, "Search Full Path");
                         });
+                        ui.horizontal(|ui|{
+                            ui.label("Open archives up to");
+                            if ui.text_edit_singleline(&mut self.temp_archive_size).changed()
+                                && let Ok(size) = self.temp_archive_size.parse::<u64>(){
+                                new_settings.archive_max_size_mb = size;
+                            }
+                            ui.label("MB");
+                        });
+                        ui.horizontal(|ui|{
+                            ui.label("Archives inside archives up to");
+                            if ui.text_edit_singleline(&mut self.temp_archive_depth).changed()
+                                && let Ok(depth) = self.temp_archive_depth.parse::<u32>(){
+                                new_settings.archive_max_depth = depth;
+                            }
+                            ui.label("levels");
+                        });
 
                         ui.horizontal(|ui|{
                             if ui.add_sized(ui.available_size(), egui::Button::new("Ok")).clicked(){
@@ -784,17 +476,32 @@ impl eframe::App for Anything {
                             ui.horizontal(|ui|{
                                 ui.label(drives[i].drive.clone()+"    ");
                                 ui.label(drives[i].mounted_at.clone()+"    ");
+                                let detected = ui.label(detected_fs_to_string(drives[i].detected_fs));
+                                if let Some((_, space)) = self.report.space.iter().find(|(drive, _)| *drive == drives[i].drive){
+                                    ui.label(format!("{} free of {}    ", main::size_to_pretty_string(space.total.saturating_sub(space.used)),
+                                        main::size_to_pretty_string(space.total)));
+                                }
+                                if main::indexer::registry().read().unwrap().get(drives[i].fs).is_some_and(|i| !i.capabilities().creation_time){
+                                    detected.on_hover_text("Date Created shows the modification date on this filesystem");
+                                }
 
                                 // let before = drives[i].fs;
                                 egui::ComboBox::new(drives[i].drive.clone(),"")
-                                    .selected_text(format!("{:?}", drives[i].fs))
+                                    .selected_text(drives[i].fs)
                                     .show_ui(ui, |ui| {
                                         ui.style_mut().override_font_id = Some(FontId{size:24.0,family:egui::FontFamily::Monospace});
-                                        ui.selectable_value(&mut drives[i].fs, SupportedFilesystems::Exfat, "Exfat");
+                                        for indexer in main::indexer::registry().read().unwrap().iter(){
+                                            ui.selectable_value(&mut drives[i].fs, indexer.name(), indexer.name());
+                                        }
 
                                     }
                                 );
                                 // if drives[i].fs != before {}
+                                ui.checkbox(&mut drives[i].index_archives, "Archives");
+                                if main::indexer::registry().read().unwrap().get(drives[i].fs).is_some_and(|i| i.capabilities().deleted_files){
+                                    ui.checkbox(&mut drives[i].include_deleted, "Deleted")
+                                        .on_hover_text("Also index the files that were deleted but still have a directory entry");
+                                }
 
                                 if ui.button("-").clicked(){
                                     drives.remove(i);
@@ -814,6 +521,22 @@ impl eframe::App for Anything {
                                 self.lsblk_window = true;
                             };
                         });
+                        ui.horizontal(|ui|{
+                            ui.label("Image:");
+                            ui.text_edit_singleline(&mut self.image_path);
+                            if ui.button("Add").clicked(){
+                                let found = main::image_drives(&self.image_path);
+                                if found.is_empty(){
+                                    self.status = format!("No supported filesystem found in {}", self.image_path);
+                                }
+                                for d in found{
+                                    if !drives.iter().any(|x| x.drive == d.drive){
+                                        drives.push(d);
+                                    }
+                                }
+                                self.image_path.clear();
+                            }
+                        });
                         ui.horizontal(|ui|{
                             if ui.add_sized(ui.available_size(), egui::Button::new("Ok")).clicked(){
                                 self.disk_window = false;
@@ -845,6 +568,7 @@ impl eframe::App for Anything {
                                         self.drives.push(self.temp_drives[i].clone());
                                     };
                                     ui.label(self.temp_drives[i].mounted_at.clone()+"    ");
+                                    ui.label(detected_fs_to_string(self.temp_drives[i].detected_fs));
                                 });
                             }
                         }
@@ -858,6 +582,29 @@ impl eframe::App for Anything {
 
                     });
 
+        // Index report
+        let mut open_report = self.report_window;
+        egui::Window::new("Index Report")
+            .open(&mut open_report)
+            .title_bar(true)
+            .resizable(true)
+            .default_width(700.0)
+            .show(ctx, |ui| {
+                ui.style_mut().override_font_id = Some(FontId{size:18.0,family:egui::FontFamily::Monospace});
+                if self.report.failed.is_empty() && self.report.warnings.is_empty(){
+                    ui.label("Every drive was indexed without problems");
+                }
+                egui::ScrollArea::vertical().show(ui, |ui|{
+                    for (drive, e) in &self.report.failed{
+                        ui.colored_label(ui.visuals().error_fg_color, format!("{drive} not indexed: {e}"));
+                    }
+                    for (drive, warning) in &self.report.warnings{
+                        ui.colored_label(ui.visuals().warn_fg_color, format!("{drive}: {warning}"));
+                    }
+                });
+            });
+        self.report_window = open_report;
+
         // Main table
         egui::CentralPanel::default().show(ctx, |ui| {
             self.render_table(ui);
@@ -866,7 +613,16 @@ impl eframe::App for Anything {
         // Status bar
         egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
             ui.style_mut().override_font_id = Some(FontId{size:20.0,family:egui::FontFamily::Proportional});
-            ui.label(self.status.clone());
+            ui.horizontal(|ui|{
+                ui.label(self.status.clone());
+                for (drive, e) in &self.report.failed{
+                    ui.colored_label(ui.visuals().error_fg_color, format!("    Not indexed {drive}: {e}"));
+                }
+                if !self.report.warnings.is_empty()
+                    && ui.button(format!("\u{26A0} {} warnings", self.report.warnings.len())).clicked(){
+                    self.report_window = true;
+                }
+            });
         });
 
         ctx.request_repaint_after_secs(0.1);
@@ -875,8 +631,8 @@ impl eframe::App for Anything {
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
         save_settings(self.settings.clone());
         save_drives(self.drives.clone());
-        if self.times_it_has_indexed > 0{
-            save_cache(self.items.0.clone(),self.items.1.clone());
+        if self.times_it_has_indexed > 0 && let Err(e) = self.index.save(){
+            eprintln!("Couldn't save the index: {e}");
         }
         println!("Bye Bye");
     }
@@ -0,0 +1,121 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::time::UNIX_EPOCH;
+use crate::partitions::PartitionFile;
+use crate::indexer::{Capabilities, FilesystemIndexer, GENERIC};
+use crate::{Attributes, Directory, Drive, Error, File};
+
+// The following code doesn't decode anything, it asks the kernel for the directory listing.
+// It is much slower than reading the drive directly but works on any mounted filesystem
+// (btrfs, XFS, tmpfs, NFS, FUSE...)
+
+struct GenericDrive{
+    directories: Vec<Directory>,
+    mounted_at: String,
+    /// Device id of the mountpoint, directories on other devices (other mounts) are not entered
+    dev: u64,
+    files: Vec<GenericFile>,
+    ignored_dirs: Vec<String>
+}
+impl GenericDrive{
+    fn new(mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let dev = fs::symlink_metadata(&mounted_at).map_err(|e| Error::open(&mounted_at, e))?.dev();
+        Ok(GenericDrive {directories: Vec::new(), mounted_at, dev, files: Vec::new(), ignored_dirs})
+    }
+    fn index_from_root(mut self: Self) -> Self{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        self.index(0);
+        self
+    }
+    fn index(self: &mut Self, parent: u32){
+        // Directories that can't be read (permissions, stale NFS handles...) are skipped
+        let entries = match fs::read_dir(&self.directories[parent as usize].name){
+            Ok(entries) => {entries}
+            Err(_) => {return;}
+        };
+        let mut new_dirs = Vec::new();
+        for entry in entries{
+            let Ok(entry) = entry else{continue;};
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Symlinks are listed but never followed
+            let Ok(meta) = fs::symlink_metadata(entry.path()) else{continue;};
+            let is_dir = meta.is_dir();
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            // Not every filesystem keeps a creation time, the inode change time is the closest thing
+            let create_timestamp = match meta.created(){
+                Ok(t) => {t.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(meta.ctime())}
+                Err(_) => {meta.ctime()}
+            };
+            self.files.push(GenericFile{
+                name,
+                parent,
+                size: meta.len(),
+                is_dir,
+                create_timestamp,
+                last_modified_timestamp: meta.mtime()
+            });
+            if is_dir && meta.dev() == self.dev{
+                new_dirs.push(full_name);
+            }
+        }
+        for name in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(self.directories.len() as u32 - 1);
+        }
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct GenericFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_generic_files_to_files(f: &GenericFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// Walks the mountpoint instead of reading the drive, works with any mounted filesystem
+pub struct GenericIndexer;
+impl FilesystemIndexer for GenericIndexer{
+    fn name(self: &Self) -> &'static str{
+        GENERIC
+    }
+    fn probe(self: &Self, _b: &[u8], _file: &PartitionFile) -> bool{
+        // There is nothing to recognize, it is what is left when no other filesystem matches
+        false
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = GenericDrive::new(drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root();
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_generic_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: false, deleted_files: false}
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+use crate::partitions::{self, PartitionFile};
+use crate::unicode::compose;
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File};
+
+// The following code decodes HFS+ (and HFSX) by reading the leaves of the catalog B-tree
+// following Apple's Technical Note TN1150 https://developer.apple.com/library/archive/technotes/tn/tn1150.html
+// Like everything from Apple it is big endian
+
+const VOLUME_HEADER_OFFSET: u64 = 1024;
+/// Seconds between 1904-01-01 (HFS epoch) and 1970-01-01
+const HFS_EPOCH_OFFSET: i64 = 2082844800;
+const CATALOG_FILE_ID: u32 = 4;
+const ROOT_FOLDER_ID: u32 = 2;
+const FOLDER_RECORD: u16 = 1;
+const FILE_RECORD: u16 = 2;
+const LEAF_NODE: i8 = -1;
+/// Hard links are files with this type and creator pointing to a file in the private metadata folder
+const HARD_LINK_TYPE: &[u8] = b"hlnkhfs+";
+
+fn be16(b: &[u8], i: usize) -> u16{
+    u16::from_be_bytes([b[i], b[i+1]])
+}
+fn be32(b: &[u8], i: usize) -> u32{
+    u32::from_be_bytes([b[i], b[i+1], b[i+2], b[i+3]])
+}
+fn be64(b: &[u8], i: usize) -> u64{
+    u64::from_be_bytes([b[i], b[i+1], b[i+2], b[i+3], b[i+4], b[i+5], b[i+6], b[i+7]])
+}
+fn hfs_time_to_epoch(t: u32) -> i64{
+    t as i64 - HFS_EPOCH_OFFSET
+}
+/// (first block, number of blocks)
+fn extents_at(b: &[u8], i: usize) -> Vec<(u32, u32)>{
+    (0..8).map(|e| (be32(b, i + e*8), be32(b, i + e*8 + 4))).filter(|e| e.1 != 0).collect()
+}
+/// A catalog record of a file or folder
+#[derive(Debug, Default, Clone)]
+struct CatalogEntry{
+    parent: u32,
+    name: String,
+    id: u32,
+    is_dir: bool,
+    size: u64,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    /// Number of the iNode file in the private metadata folder, for hard links
+    link: Option<u32>,
+}
+struct HfsPlusDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    catalog_extents: Vec<(u32, u32)>,
+    entries: Vec<CatalogEntry>,
+    /// Folder id -> indexes inside of entries
+    children: HashMap<u32, Vec<usize>>,
+    /// Folder ids already indexed, a folder record that is its own parent (or ancestor) would recurse forever
+    visited: HashSet<u32>,
+    files: Vec<HfsPlusFile>,
+    ignored_dirs: Vec<String>
+}
+impl HfsPlusDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut vh = vec![0u8; 512];
+        file.read_at(&mut vh, VOLUME_HEADER_OFFSET).map_err(Error::io(VOLUME_HEADER_OFFSET))?;
+        if &vh[0..2] != b"H+" && &vh[0..2] != b"HX"{
+            return Err(Error::WrongFilesystem("HFS+"));
+        }
+        let block_size = be32(&vh, 40) as u64;
+        if !block_size.is_power_of_two() || block_size < 512{
+            return Err(Error::corrupt(VOLUME_HEADER_OFFSET + 40, format!("invalid block size {block_size}")));
+        }
+        let mut drive = HfsPlusDrive {file, directories: Vec::new(), mounted_at, block_size,
+            catalog_extents: extents_at(&vh, 272 + 16), entries: Vec::new(), children: HashMap::new(), visited: HashSet::new(), files: Vec::new(), ignored_dirs};
+        // Fragmented catalogs continue in the extents overflow file
+        let overflow = drive.overflow_extents(extents_at(&vh, 192 + 16), CATALOG_FILE_ID)?;
+        drive.catalog_extents.extend(overflow);
+        Ok(drive)
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    /// Reads from a file given its extents
+    fn read_fork(self: &Self, extents: &[(u32, u32)], offset: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = Vec::with_capacity(size as usize);
+        let mut skip = offset;
+        for &(start, count) in extents{
+            let len = count as u64*self.block_size;
+            if skip >= len{
+                skip -= len;
+                continue;
+            }
+            let n = (len - skip).min(size - b.len() as u64);
+            b.append(&mut self.read_bytes(start as u64*self.block_size + skip, n)?);
+            skip = 0;
+            if b.len() as u64 == size{
+                break;
+            }
+        }
+        b.resize(size as usize, 0);
+        Ok(b)
+    }
+    /// Calls `f` with every record of every leaf node of a B-tree
+    fn walk_leaves(self: &Self, extents: &[(u32, u32)], mut f: impl FnMut(&[u8])) -> Result<(), Error>{
+        let header = self.read_fork(extents, 0, 512)?;
+        let node_size = be16(&header, 14 + 18) as u64;
+        if !node_size.is_power_of_two() || node_size < 512{
+            return Err(Error::corrupt(extents.first().map_or(0, |e| e.0 as u64*self.block_size) + 32, format!("invalid B-tree node size {node_size}")));
+        }
+        let total_nodes = be32(&header, 14 + 22);
+        let mut node = be32(&header, 14 + 10);
+        // The leaves are a linked list, a broken one could loop forever
+        let mut visited = 0;
+        while node != 0 && visited < total_nodes{
+            visited += 1;
+            let b = self.read_fork(extents, node as u64*node_size, node_size)?;
+            if b[8] as i8 != LEAF_NODE{
+                break;
+            }
+            // Every record needs an offset at the end of the node, the descriptor takes the first 14 bytes
+            let records = (be16(&b, 10) as usize).min((node_size as usize - 14) / 2 - 1);
+            for r in 0..records{
+                // Offsets of the records are at the end of the node
+                let start = be16(&b, node_size as usize - 2*(r + 1)) as usize;
+                let end = be16(&b, node_size as usize - 2*(r + 2)) as usize;
+                if start < end && end <= b.len(){
+                    f(&b[start..end]);
+                }
+            }
+            node = be32(&b, 0);
+        }
+        Ok(())
+    }
+    fn overflow_extents(self: &Self, extents_file: Vec<(u32, u32)>, file_id: u32) -> Result<Vec<(u32, u32)>, Error>{
+        let mut found: Vec<(u32, Vec<(u32, u32)>)> = Vec::new();
+        if extents_file.is_empty(){
+            return Ok(Vec::new());
+        }
+        self.walk_leaves(&extents_file, |r|{
+            // Key: length, fork type (0 = data), pad, file id, start block
+            if r.len() >= 12 + 64 && r[2] == 0 && be32(r, 4) == file_id{
+                found.push((be32(r, 8), extents_at(r, 12)));
+            }
+        })?;
+        found.sort_by_key(|e| e.0);
+        Ok(found.into_iter().flat_map(|e| e.1).collect())
+    }
+    fn read_catalog(mut self: Self) -> Result<Self, Error>{
+        let mut entries = Vec::new();
+        self.walk_leaves(&self.catalog_extents, |r|{
+            if r.len() < 8{
+                return;
+            }
+            let key_len = be16(r, 0) as usize;
+            if key_len < 6 || r.len() < key_len + 2{
+                return;
+            }
+            let parent = be32(r, 2);
+            let name_len = be16(r, 6) as usize;
+            let units: Vec<u16> = (0..name_len.min((key_len - 6) / 2)).map(|i| be16(r, 8 + i*2)).collect();
+            // The catalog has ":" and "/" swapped compared to the POSIX name (":" separated old Mac OS paths)
+            let name = compose(&String::from_utf16_lossy(&units)).replace('/', ":");
+            // The record starts after the key, aligned to 2 bytes
+            let d = &r[(key_len + 2 + 1) & !1..];
+            if d.len() < 88{
+                return;
+            }
+            let record_type = be16(d, 0);
+            if record_type != FOLDER_RECORD && record_type != FILE_RECORD{
+                return;
+            }
+            let mut entry = CatalogEntry{
+                parent,
+                name,
+                id: be32(d, 8),
+                is_dir: record_type == FOLDER_RECORD,
+                create_timestamp: hfs_time_to_epoch(be32(d, 12)),
+                last_modified_timestamp: hfs_time_to_epoch(be32(d, 16)),
+                ..Default::default()
+            };
+            if record_type == FILE_RECORD && d.len() >= 96{
+                entry.size = be64(d, 88);
+                if &d[48..56] == HARD_LINK_TYPE{
+                    // The iNode number is in the "special" field of the BSD info
+                    entry.link = Some(be32(d, 32 + 12));
+                }
+            }
+            entries.push(entry);
+        })?;
+        for (i, e) in entries.iter().enumerate(){
+            self.children.entry(e.parent).or_default().push(i);
+        }
+        self.entries = entries;
+        Ok(self)
+    }
+    fn index_from_root(mut self: Self) -> Self{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        // Hard link targets are files called iNode<number> in the private metadata folder
+        let mut links = HashMap::new();
+        for &i in self.children.get(&ROOT_FOLDER_ID).unwrap_or(&Vec::new()){
+            if self.entries[i].name == "\0\0\0\0HFS+ Private Data"{
+                for &j in self.children.get(&self.entries[i].id).unwrap_or(&Vec::new()){
+                    if let Some(number) = self.entries[j].name.strip_prefix("iNode").and_then(|n| n.parse::<u32>().ok()){
+                        links.insert(number, j);
+                    }
+                }
+            }
+        }
+        self.visited.insert(ROOT_FOLDER_ID);
+        self.index(ROOT_FOLDER_ID, &links, 0);
+        self
+    }
+    fn index(self: &mut Self, folder_id: u32, links: &HashMap<u32, usize>, parent: u32){
+        let children = self.children.get(&folder_id).cloned().unwrap_or_default();
+        let mut new_dirs = Vec::new();
+        for i in children{
+            let mut entry = self.entries[i].clone();
+            // The private metadata folders are hidden (like macOS and Linux do)
+            if folder_id == ROOT_FOLDER_ID && (entry.name.starts_with("\0\0\0\0") || entry.name == ".HFS+ Private Directory Data\r"){
+                continue;
+            }
+            if let Some(&target) = entry.link.and_then(|l| links.get(&l)){
+                entry.size = self.entries[target].size;
+                entry.create_timestamp = self.entries[target].create_timestamp;
+                entry.last_modified_timestamp = self.entries[target].last_modified_timestamp;
+            }
+            let mut full_name = self.directories[parent as usize].name.clone() + &entry.name;
+            if entry.is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(HfsPlusFile{
+                name: entry.name,
+                parent,
+                size: entry.size,
+                is_dir: entry.is_dir,
+                create_timestamp: entry.create_timestamp,
+                last_modified_timestamp: entry.last_modified_timestamp
+            });
+            if entry.is_dir && self.visited.insert(entry.id){
+                new_dirs.push((full_name, entry.id));
+            }
+        }
+        for (name, id) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(id, links, self.directories.len() as u32 - 1);
+        }
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct HfsPlusFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_hfsplus_files_to_files(f: &HfsPlusFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// Also reads HFSX
+pub struct HfsPlusIndexer;
+impl FilesystemIndexer for HfsPlusIndexer{
+    fn name(self: &Self) -> &'static str{
+        "HfsPlus"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[1024..1026] == b"H+" || &b[1024..1026] == b"HX"
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = HfsPlusDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.read_catalog()?.index_from_root();
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_hfsplus_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
@@ -0,0 +1,322 @@
+use std::io::{self, BufWriter, Write};
+use std::env;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use crate::indexer::{DriveReport, Space};
+use crate::{Attributes, Deleted, Directory, Drive, Error, File, Query, SearchResult, Settings, Sort, archive, indexer, search, u32_at};
+
+/// cache.txt starts with this followed by its version and a new line, the version goes up whenever what is
+/// saved changes. Caches saved before there were versions (version 1) start right away with the number of directories
+const CACHE_HEADER: &[u8] = b"Anything cache ";
+/// 2 added the deleted flag of files, 3 the up-case tables, 4 the attributes of files
+const CACHE_VERSION: u32 = 4;
+
+/// Every file and directory of the indexed drives
+#[derive(Debug, Default, Clone)]
+pub struct Index{
+    files: Vec<File>,
+    directories: Vec<Directory>,
+    /// Up-case tables of the drives that have one, with the range of `directories` that belongs to the drive
+    upcase: Vec<(Range<u32>, Arc<[u16]>)>,
+}
+/// What went wrong while building an index
+#[derive(Debug, Default)]
+pub struct IndexReport{
+    /// Drives that couldn't be indexed at all and why
+    pub failed: Vec<(String, Error)>,
+    /// Drives that were indexed but with metadata that had to be skipped, one message per thing skipped
+    pub warnings: Vec<(String, String)>,
+    /// Used and total space of the drives whose filesystem keeps track of it
+    pub space: Vec<(String, Space)>,
+}
+impl Index{
+    /// Indexes the drives one after the other, drives whose filesystem isn't in the registry are skipped.
+    /// A drive that fails (or whose backend panics) doesn't stop the others, it ends up in the report with the reason
+    pub fn build(drives: &[Drive], settings: &Settings) -> (Self, IndexReport){
+        let mut items = Index::default();
+        let mut report = IndexReport::default();
+        for d in drives{
+            let first = items.files.len();
+            let registry = indexer::registry().read().unwrap();
+            let Some(indexer) = registry.get(d.fs) else {continue;};
+            let idx = items.directories.len() as u32;
+            let mut drive_report = DriveReport::default();
+            // A backend that panics (a bug) only fails its drive, the warnings it reported until then are kept
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let update = if settings.journal{indexer.update(d, idx)}else{None};
+                update.map_or_else(|| indexer.index_with_report(d, idx, &mut drive_report), Ok)
+            })).unwrap_or_else(|payload| {
+                let message = match payload.downcast_ref::<&str>(){
+                    Some(message) => {message.to_string()}
+                    None => {payload.downcast_ref::<String>().cloned().unwrap_or_default()}
+                };
+                Err(Error::Panicked(message))
+            });
+            report.warnings.extend(drive_report.warnings.into_iter().map(|w| (d.drive.clone(), w)));
+            if let Some(space) = drive_report.space{
+                report.space.push((d.drive.clone(), space));
+            }
+            let (mut files, mut dir) = match result{
+                Ok(items) => {items}
+                Err(e) => {
+                    report.failed.push((d.drive.clone(), e));
+                    continue;
+                }
+            };
+            items.files.append(&mut files);
+            items.directories.append(&mut dir);
+            if d.index_archives{
+                archive::index_archives(&mut items.files, &mut items.directories, first,
+                    settings.archive_max_size_mb.saturating_mul(1024*1024), settings.archive_max_depth);
+            }
+            if let Some(upcase) = drive_report.upcase{
+                items.upcase.push((idx..items.directories.len() as u32, upcase));
+            }
+        }
+        (items, report)
+    }
+    /// Every file and directory, `File::parent` is a position in `directories`
+    pub fn files(self: &Self) -> &[File]{
+        &self.files
+    }
+    /// Every directory with its full path, in the order `File::parent` refers to them
+    pub fn directories(self: &Self) -> &[Directory]{
+        &self.directories
+    }
+    /// Full path of a file of this index
+    pub fn path(self: &Self, file: &File) -> String{
+        self.directories[file.parent as usize].name.clone() + &file.name
+    }
+    /// Files matching the query, in the order of the index
+    pub fn search(self: &Self, query: &Query) -> Vec<SearchResult>{
+        let (_sender, receiver) = std::sync::mpsc::channel();
+        self.search_with_cancel(query, &receiver)
+    }
+    /// Like `search` but sending 1 through the channel stops it, what was found until then is returned
+    pub fn search_with_cancel(self: &Self, query: &Query, cancel_flag: &Receiver<u8>) -> Vec<SearchResult>{
+        search::search(&self.files, &self.directories, &self.upcase, query, cancel_flag).into_iter().map(|file| SearchResult{file}).collect()
+    }
+    /// Sorting the index makes the results of every following search come out sorted
+    pub fn sort(self: &mut Self, sort: &Sort){
+        let mut files = std::mem::take(&mut self.files);
+        files.sort_by(|a, b| self.compare(a, b, sort));
+        self.files = files;
+    }
+    /// `results` have to come from this index
+    pub fn sort_results(self: &Self, results: &mut [SearchResult], sort: &Sort){
+        results.sort_by(|a, b| self.compare(&a.file, &b.file, sort));
+    }
+    fn compare(self: &Self, a: &File, b: &File, sort: &Sort) -> std::cmp::Ordering{
+        let path = |f: &File| &self.directories[f.parent as usize].name;
+        match sort{
+            Sort::DateCreatedAscending => {a.create_timestamp.cmp(&b.create_timestamp)}
+            Sort::DateCreatedDescending => {b.create_timestamp.cmp(&a.create_timestamp)}
+            Sort::DateModifiedAscending => {a.last_modified_timestamp.cmp(&b.last_modified_timestamp)}
+            Sort::DateModifiedDescending => {b.last_modified_timestamp.cmp(&a.last_modified_timestamp)}
+            Sort::SizeAscending => {a.size.cmp(&b.size)}
+            Sort::SizeDescending => {b.size.cmp(&a.size)}
+            Sort::PathAscending => {path(a).cmp(path(b))}
+            Sort::PathDescending => {path(b).cmp(path(a))}
+            Sort::FileAscending => {a.name.cmp(&b.name)}
+            Sort::FileDescending => {b.name.cmp(&a.name)}
+        }
+    }
+    /// Writes the index to cache.txt next to the binary (or the AppImage)
+    pub fn save(self: &Self) -> io::Result<()>{
+        let no_parent = || io::Error::new(io::ErrorKind::NotFound, "no directory to put settings/cache.txt in");
+        let binary_path = env::current_exe()?;
+        let parent_dir = binary_path.parent().ok_or_else(no_parent)?;
+        let save_cache_path = parent_dir.join("settings").join("cache.txt");
+        let file = match std::fs::OpenOptions::new().write(true).truncate(true).open(save_cache_path){
+            Ok(a) => {a},
+            Err(e) =>{
+                // If it is an appimage
+                let Ok(appimage_path) = env::var("APPIMAGE") else{
+                    return Err(e);
+                };
+                let appimage_path = Path::new(&appimage_path);
+                let app_dir = appimage_path.parent().ok_or_else(no_parent)?;
+                let settings_dir = app_dir.join("settings");
+                let path = settings_dir.join("cache.txt");
+                std::fs::OpenOptions::new().write(true).truncate(true).open(path)?
+            }
+        };
+        let mut writer = BufWriter::new(file);
+        writer.write_all(CACHE_HEADER)?;
+        writeln!(&mut writer, "{CACHE_VERSION}")?;
+        writer.write_all(&(self.directories.len() as u32).to_le_bytes())?;
+        writeln!(&mut writer)?;
+
+        for d in &self.directories{
+            writeln!(&mut writer, "{}",d.name)?;
+        }
+        // Up-case tables: how many, then the range of directories, the length and the code units of each one
+        writer.write_all(&(self.upcase.len() as u32).to_le_bytes())?;
+        for (range, table) in &self.upcase{
+            writer.write_all(&range.start.to_le_bytes())?;
+            writer.write_all(&range.end.to_le_bytes())?;
+            writer.write_all(&(table.len() as u32).to_le_bytes())?;
+            for unit in table.iter(){
+                writer.write_all(&unit.to_le_bytes())?;
+            }
+        }
+        for f in &self.files{
+            let size = f.size;
+            let t_created = f.create_timestamp;
+            let t_modified = f.last_modified_timestamp;
+            // Directories get a trailing "/" so that load_cache can tell them apart
+            let name = if f.is_dir{f.name.clone() + "/"}else{f.name.clone()};
+            let size_bytes = size.to_le_bytes();
+            let t_created_bytes = t_created.to_le_bytes();
+            let t_modified_bytes = t_modified.to_le_bytes();
+            let parent_idx = f.parent.to_le_bytes();
+            // let s = format!("{size_bytes}{t_created_bytes}{t_modified_bytes}{name}");
+            writer.write_all(&size_bytes)?;
+            writer.write_all(&t_created_bytes)?;
+            writer.write_all(&t_modified_bytes)?;
+            writer.write_all(&parent_idx)?;
+            let deleted: u8 = match f.deleted{
+                None => {0}
+                Some(Deleted::Recoverable) => {1}
+                Some(Deleted::Overwritten) => {2}
+            };
+            writer.write_all(&[deleted])?;
+            writer.write_all(&f.attributes.0.to_le_bytes())?;
+            writeln!(&mut writer, "{}",name)?;
+        }
+        writer.flush()
+    }
+    /// Reads the index saved by `save`, empty if there is none
+    pub fn load()->Self{
+        let Ok(binary_path) = env::current_exe() else{
+            return Index::default();
+        };
+        let Some(parent_dir) = binary_path.parent() else{
+            return Index::default();
+        };
+        let save_cache_path = parent_dir.join("settings").join("cache.txt");
+        let file = match std::fs::read(save_cache_path){
+            Ok(a) => {a},
+            Err(_) =>{
+                // If it is an appimage
+                match env::var("APPIMAGE"){
+                    Err(_) => {return Index::default();}
+                    Ok(s) =>{
+                        let appimage_path = Path::new(&s);
+                        let Some(app_dir) = appimage_path.parent() else{
+                            return Index::default();
+                        };
+                        let settings_dir = app_dir.join("settings");
+                        let path = settings_dir.join("cache.txt");
+                        match std::fs::read(path){
+                            Ok(file) => {file}
+                            Err(_) => {return Index::default();}
+                        }
+                    }
+                }
+            }
+        };
+        if file.is_empty(){
+            return Index::default();
+        }
+        let (version, start) = if file.starts_with(CACHE_HEADER){
+            let end = file.iter().position(|b| *b == b'\n').unwrap_or(file.len());
+            let version = String::from_utf8_lossy(&file[CACHE_HEADER.len()..end]).parse().unwrap_or(0);
+            (version, end + 1)
+        }else{
+            (1, 0)
+        };
+        if version == 0 || version > CACHE_VERSION || file.len() < start + 5{
+            return Index::default();
+        }
+        // Version 1 has no deleted flag after the parent, versions before 4 no attributes after it
+        let record_len = match version{
+            1 => {28}
+            2 | 3 => {29}
+            _ => {33}
+        };
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+        let directories_n = u32::from_le_bytes([file[start],file[start+1],file[start+2],file[start+3]]);
+        let mut i = start + 5;
+        // An empty index has no directories, a truncated one less than it says
+        while (directories.len() as u32) < directories_n && i < file.len(){
+            let mut name_bytes = Vec::new();
+            while i < file.len() && file[i] != b'\n' {
+                name_bytes.push(file[i]);
+                i += 1;
+            }
+            i += 1; //Skip the null terminator
+            let name = String::from_utf8_lossy(&name_bytes).to_string();
+            directories.push(Directory { name });
+        }
+        let mut upcase = Vec::new();
+        if version >= 3 && i + 4 <= file.len(){
+            let tables = u32_at(&file, i);
+            i += 4;
+            for _ in 0..tables{
+                if i + 12 > file.len(){break;}
+                let range = u32_at(&file, i)..u32_at(&file, i+4);
+                let len = u32_at(&file, i+8) as usize;
+                i += 12;
+                if i + 2*len > file.len(){break;}
+                let table: Arc<[u16]> = file[i..i+2*len].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                i += 2*len;
+                upcase.push((range, table));
+            }
+        }
+        let mut p = i;
+        while p + record_len < file.len(){
+            let size =  u64::from_le_bytes([
+                    file[p],file[p+1],file[p+2],file[p+3],
+                    file[p+4],file[p+5],file[p+6],file[p+7]
+            ]);
+            let t_created =  i64::from_le_bytes([
+                    file[p+8],file[p+9],file[p+10],file[p+11],
+                    file[p+12],file[p+13],file[p+14],file[p+15]
+            ]);
+            let t_modified =  i64::from_le_bytes([
+                    file[p+16],file[p+17],file[p+18],file[p+19],
+                    file[p+20],file[p+21],file[p+22],file[p+23]
+            ]);
+            let parent = u32::from_le_bytes([file[p+24],file[p+25],file[p+26],file[p+27]]);
+            let deleted = match file[p+28..p+record_len].first(){
+                Some(1) => {Some(Deleted::Recoverable)}
+                Some(2) => {Some(Deleted::Overwritten)}
+                _ => {None}
+            };
+            let attributes = if record_len >= 33{Some(Attributes(u32_at(&file, p+29)))}else{None};
+            p += record_len;
+            // Read null-terminated UTF-8
+            let mut name_bytes = Vec::new();
+            while p < file.len() && file[p] != b'\n' {
+                name_bytes.push(file[p]);
+                p += 1;
+            }
+            p += 1;  // Skip null terminator
+            // The directory of the file is missing from a damaged cache
+            if parent as usize >= directories.len(){
+                continue;
+            }
+            let mut name = String::from_utf8_lossy(&name_bytes).to_string();
+            let mut is_dir = false;
+            if name.ends_with("/"){
+                is_dir = true;
+                name.pop();
+            }
+            files.push(File{
+                name,
+                parent,
+                size,is_dir,
+                create_timestamp:t_created,
+                last_modified_timestamp: t_modified,
+                deleted,
+                attributes: attributes.unwrap_or(Attributes::only_directory(is_dir)),
+            })
+        }
+        Index{files, directories, upcase}
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::{Arc, LazyLock, RwLock};
+use crate::partitions::PartitionFile;
+use crate::{Directory, Drive, Error, File, apfs, btrfs, erofs, exfat, ext4, f2fs, fat, generic, hfsplus, iso9660, ntfs, squashfs, udf, xfs};
+
+// Every filesystem backend implements FilesystemIndexer and is listed in the registry, which is all the GUI knows
+// about: it probes drives, fills the Drive Settings window and indexes drives through it
+
+/// What a filesystem is called when nothing recognizes it, it is indexed through its mountpoint
+pub const GENERIC: &str = "Generic";
+
+/// What a backend can do
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Capabilities{
+    /// Whether files may have a real creation time, otherwise the modification time is used in its place. Files
+    /// that don't have one still get the closest time there is: the inode change time on ext2/ext3, small ext4
+    /// inodes and in the Generic backend when the OS doesn't report one, the modification time for UDF file
+    /// entries that aren't extended
+    pub creation_time: bool,
+    /// Whether the drive itself is read (which needs sudo), otherwise the mountpoint is walked
+    pub reads_device: bool,
+    /// Whether the backend can find deleted files, which are indexed when Drive::include_deleted is on
+    pub deleted_files: bool,
+}
+/// What a backend found out about a drive while indexing it, besides its files
+#[derive(Debug, Default, Clone)]
+pub struct DriveReport{
+    /// Metadata that was skipped because it makes no sense, the rest of the drive was still indexed
+    pub warnings: Vec<String>,
+    /// Used and total bytes, if the filesystem keeps track of them
+    pub space: Option<Space>,
+    /// The table the filesystem uppercases names with (indexed by UTF-16 code unit, units past its end are
+    /// their own upper case). Searches that ignore case compare the names of the drive through it
+    pub upcase: Option<Arc<[u16]>>,
+}
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Space{
+    pub used: u64,
+    pub total: u64,
+}
+pub trait FilesystemIndexer: Send + Sync{
+    /// Written in drives.txt and shown in the Drive Settings window, it has to be unique
+    fn name(self: &Self) -> &'static str;
+    /// Whether the drive holds this filesystem. `b` is the beginning of the drive (always 68KiB, zero filled
+    /// if the drive is smaller) and `file` can be used to look further
+    fn probe(self: &Self, b: &[u8], file: &PartitionFile) -> bool;
+    /// Indexes the drive from its root, `idx` is added to the parent of every file
+    /// (it is where the directories of the drive start in the list of all directories)
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>;
+    /// Like `index` but the backend may skip metadata it can't make sense of instead of failing the drive,
+    /// saying what it skipped (and whatever else it knows about the drive) in `report`. This is what Index::build calls
+    fn index_with_report(self: &Self, drive: &Drive, idx: u32, _report: &mut DriveReport) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        self.index(drive, idx)
+    }
+    /// Indexes the drive reusing what the backend remembers from the last time (like a position in the journal),
+    /// it is tried first when the Journal setting is on. None means that the drive has to be indexed from scratch
+    fn update(self: &Self, _drive: &Drive, _idx: u32) -> Option<(Vec<File>, Vec<Directory>)>{
+        None
+    }
+    fn capabilities(self: &Self) -> Capabilities;
+}
+pub struct Registry{
+    indexers: Vec<Box<dyn FilesystemIndexer>>,
+}
+impl Registry{
+    /// The built-in backends in the order they are probed:
+    /// exFAT and NTFS have a FAT-like boot sector, they must be checked before FAT.
+    /// Optical media are checked before FAT because hybrid .iso files start with an MBR boot sector.
+    /// UDF wins over ISO9660 because on UDF bridge discs the ISO9660 side may only have a readme
+    fn new() -> Self{
+        Registry{indexers: vec![
+            Box::new(exfat::ExfatIndexer),
+            Box::new(ntfs::NtfsIndexer),
+            Box::new(udf::UdfIndexer),
+            Box::new(iso9660::Iso9660Indexer),
+            Box::new(fat::FatIndexer),
+            Box::new(ext4::Ext4Indexer),
+            Box::new(btrfs::BtrfsIndexer),
+            Box::new(xfs::XfsIndexer),
+            Box::new(hfsplus::HfsPlusIndexer),
+            Box::new(f2fs::F2fsIndexer),
+            Box::new(apfs::ApfsIndexer),
+            Box::new(squashfs::SquashfsIndexer),
+            Box::new(erofs::ErofsIndexer),
+            Box::new(generic::GenericIndexer),
+        ]}
+    }
+    /// Adds a backend, it is probed after the ones already there.
+    /// The backend is given back if one with the same name is already registered
+    pub fn register(self: &mut Self, indexer: Box<dyn FilesystemIndexer>) -> Result<(), Box<dyn FilesystemIndexer>>{
+        if self.get(indexer.name()).is_some(){
+            return Err(indexer);
+        }
+        self.indexers.push(indexer);
+        Ok(())
+    }
+    pub fn get(self: &Self, name: &str) -> Option<&dyn FilesystemIndexer>{
+        self.iter().find(|i| i.name() == name)
+    }
+    pub fn iter(self: &Self) -> impl Iterator<Item = &dyn FilesystemIndexer>{
+        self.indexers.iter().map(|i| i.as_ref())
+    }
+    /// The name of the first backend that recognizes the drive, Generic if none does
+    pub fn probe(self: &Self, b: &[u8], file: &PartitionFile) -> &'static str{
+        self.iter().find(|i| i.probe(b, file)).map(|i| i.name()).unwrap_or(GENERIC)
+    }
+}
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(|| RwLock::new(Registry::new()));
+
+/// Other backends are added with `registry().write().unwrap().register(...)` before the drives are loaded,
+/// registering a name twice fails
+pub fn registry() -> &'static RwLock<Registry>{
+    &REGISTRY
+}
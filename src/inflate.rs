@@ -0,0 +1,303 @@
+// The following code decompresses raw DEFLATE streams following RFC 1951
+// https://www.rfc-editor.org/rfc/rfc1951
+// Huffman codes are decoded the canonical way (one bit at a time, like zlib's puff)
+
+const MAX_BITS: usize = 15;
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which the code lengths of the code lengths code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct BitReader<'a>{
+    data: &'a [u8],
+    pos: usize,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+impl BitReader<'_>{
+    fn bits(self: &mut Self, n: u32) -> Option<u32>{
+        while self.bit_count < n{
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.bit_buffer |= (byte as u64) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let v = (self.bit_buffer & ((1u64 << n) - 1)) as u32;
+        self.bit_buffer >>= n;
+        self.bit_count -= n;
+        Some(v)
+    }
+    /// Stored blocks start at a byte boundary
+    fn align(self: &mut Self){
+        let drop = self.bit_count % 8;
+        self.bit_buffer >>= drop;
+        self.bit_count -= drop;
+    }
+}
+/// A canonical Huffman code: how many codes have each length and the symbols ordered by code
+struct Huffman{
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+impl Huffman{
+    fn new(lengths: &[u8]) -> Huffman{
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &l in lengths{
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS{
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate(){
+            if l != 0{
+                symbols[offsets[l as usize] as usize] = symbol as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Huffman{counts, symbols}
+    }
+    fn decode(self: &Self, r: &mut BitReader) -> Option<u16>{
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS{
+            code |= r.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count{
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+fn fixed_codes() -> (Huffman, Huffman){
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+fn dynamic_codes(r: &mut BitReader) -> Option<(Huffman, Huffman)>{
+    let literals = r.bits(5)? as usize + 257;
+    let distances = r.bits(5)? as usize + 1;
+    let code_lengths = r.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths]{
+        lengths[i] = r.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len(){
+        let symbol = code_length_code.decode(r)?;
+        let (value, repeat) = match symbol{
+            0..=15 => {(symbol as u8, 1)}
+            16 => {(*lengths.get(i.checked_sub(1)?)?, 3 + r.bits(2)? as usize)}
+            17 => {(0, 3 + r.bits(3)? as usize)}
+            _ => {(0, 11 + r.bits(7)? as usize)}
+        };
+        if i + repeat > lengths.len(){
+            return None;
+        }
+        lengths[i..i+repeat].fill(value);
+        i += repeat;
+    }
+    Some((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+/// Decompresses a raw DEFLATE stream, returns None if it is broken or `out` would grow past `max` bytes.
+/// Returns how many bytes of `data` the stream used
+pub fn inflate(data: &[u8], out: &mut Vec<u8>, max: usize) -> Option<usize>{
+    let mut r = BitReader{data, pos: 0, bit_buffer: 0, bit_count: 0};
+    loop{
+        let last = r.bits(1)?;
+        match r.bits(2)?{
+            0 => {
+                r.align();
+                let len = r.bits(16)? as usize;
+                // NLEN is the one's complement of LEN
+                if r.bits(16)? as usize != !len & 0xFFFF{
+                    return None;
+                }
+                if out.len() + len > max{
+                    return None;
+                }
+                for _ in 0..len{
+                    out.push(r.bits(8)? as u8);
+                }
+            }
+            block_type @ (1 | 2) => {
+                let (literal_code, distance_code) = if block_type == 1{fixed_codes()}else{dynamic_codes(&mut r)?};
+                loop{
+                    let symbol = literal_code.decode(&mut r)? as usize;
+                    if symbol < 256{
+                        if out.len() >= max{
+                            return None;
+                        }
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256{
+                        break;
+                    }
+                    let symbol = symbol - 257;
+                    if symbol >= LENGTH_BASE.len(){
+                        return None;
+                    }
+                    let len = LENGTH_BASE[symbol] as usize + r.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+                    let symbol = distance_code.decode(&mut r)? as usize;
+                    if symbol >= DISTANCE_BASE.len(){
+                        return None;
+                    }
+                    let distance = DISTANCE_BASE[symbol] as usize + r.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                    if distance > out.len() || out.len() + len > max{
+                        return None;
+                    }
+                    // The copy may overlap with what it is writing
+                    let start = out.len() - distance;
+                    for i in 0..len{
+                        out.push(out[start + i]);
+                    }
+                }
+            }
+            _ => {return None;}
+        }
+        if last == 1{
+            break;
+        }
+    }
+    // Bytes still in the bit buffer were not used
+    Some(r.pos - (r.bit_count / 8) as usize)
+}
+#[cfg(test)]
+pub(crate) mod tests{
+    use super::*;
+
+    /// "line 0 of the test file\nline 1 of the test file\n..." cut at `n` bytes
+    pub(crate) fn text(n: usize) -> Vec<u8>{
+        let mut text = Vec::new();
+        let mut i = 0;
+        while text.len() < n{
+            text.extend_from_slice(format!("line {i} of the test file\n").as_bytes());
+            i += 1;
+        }
+        text.truncate(n);
+        text
+    }
+    /// Bytes that don't compress, from a linear congruential generator
+    pub(crate) fn noise(n: usize) -> Vec<u8>{
+        let mut x: u64 = 0x5eed;
+        (0..n).map(|_|{
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (x >> 33) as u8
+        }).collect()
+    }
+
+    // Made with Python's zlib (raw DEFLATE)
+    /// noise(100) in a stored block
+    const STORED: &[u8] = &[
+        0x01, 0x64, 0x00, 0x9B, 0xFF, 0xC6, 0x4F, 0x14, 0xAF, 0xD8, 0x4B, 0x9A, 0x2E, 0x62, 0x07, 0x77, 0xCA, 0x7D, 0x4B, 0xFA,
+        0xEB, 0x6D, 0xA1, 0xA0, 0xCB, 0x53, 0x28, 0x15, 0x10, 0x93, 0x8C, 0x23, 0xA5, 0xD3, 0xD9, 0x38, 0xED, 0x5A, 0x85, 0x30,
+        0x9A, 0x08, 0x2D, 0xD6, 0xDE, 0xE3, 0x4E, 0x92, 0xAB, 0xA6, 0x10, 0xC5, 0x6F, 0xE7, 0xA5, 0xA7, 0xEF, 0x55, 0xA7, 0x68,
+        0x5A, 0xB2, 0x80, 0x70, 0xC9, 0x11, 0x62, 0xD3, 0xE2, 0x4A, 0xAE, 0x9D, 0xFC, 0x44, 0x2C, 0x80, 0x1D, 0xB1, 0x1B, 0xED,
+        0xD1, 0xC7, 0x11, 0xD0, 0xF2, 0xC9, 0x23, 0xF9, 0x86, 0xA0, 0xC1, 0x3B, 0x8E, 0xFD, 0x60, 0x12, 0x87, 0xF0, 0x91, 0xA8,
+        0x64, 0x69, 0xAB, 0x8E, 0xDF,
+    ];
+    /// 50 "a" and "hello hello hello" with the fixed codes, the first match overlaps what it copies
+    const FIXED: &[u8] = &[
+        0x4B, 0x4C, 0x24, 0x15, 0x64, 0xA4, 0xE6, 0xE4, 0xE4, 0x2B, 0x20, 0x91, 0x00,
+    ];
+    /// text(800) with dynamic codes, flushed halfway so the second block copies from the first
+    const MULTI_BLOCK: &[u8] = &[
+        0x74, 0xCF, 0xB1, 0x0D, 0x80, 0x30, 0x0C, 0x45, 0xC1, 0x9E, 0x29, 0x3C, 0x02, 0x1F, 0x48, 0x80, 0x81, 0x1C, 0x11, 0xC9,
+        0x82, 0x22, 0xDE, 0x5F, 0x28, 0x03, 0xBC, 0xF6, 0xBA, 0x8B, 0xFE, 0xBA, 0xAD, 0xF6, 0x35, 0xCB, 0xC7, 0x2D, 0x7D, 0xA4,
+        0xB5, 0x1E, 0xBE, 0xC4, 0x74, 0x81, 0x6F, 0xE0, 0x3B, 0xF8, 0x01, 0x5E, 0xC0, 0x2B, 0xF8, 0x09, 0x7E, 0x81, 0xDF, 0xF4,
+        0xC2, 0x30, 0x8D, 0x45, 0x65, 0xD1, 0x59, 0x94, 0x16, 0xAD, 0x35, 0xDB, 0x3F, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x75, 0xD1,
+        0xB1, 0x0D, 0x00, 0x30, 0x08, 0xC0, 0xB0, 0x9B, 0x08, 0xFF, 0xFF, 0x86, 0xBA, 0xD7, 0x73, 0x26, 0x2B, 0xDF, 0x20, 0xF7,
+        0x08, 0x3E, 0x92, 0x27, 0x79, 0x7C, 0x2D, 0x79, 0x92, 0x27, 0x79, 0x92, 0x27, 0x79, 0x92, 0x27, 0x79, 0x92, 0xAF, 0xE4,
+        0x2B, 0xF9, 0x3E, 0xF9, 0x01,
+    ];
+    /// A fixed block starting with a match, there is nothing to copy from yet
+    const DISTANCE_TOO_FAR: &[u8] = &[
+        0x03, 0x02, 0x00,
+    ];
+
+    fn decompress(data: &[u8], max: usize) -> Option<Vec<u8>>{
+        let mut out = Vec::new();
+        let used = inflate(data, &mut out, max)?;
+        assert_eq!(used, data.len());
+        Some(out)
+    }
+    fn fixed_expected() -> Vec<u8>{
+        let mut expected = vec![b'a'; 50];
+        expected.extend_from_slice(b"hello hello hello");
+        expected
+    }
+
+    #[test]
+    fn empty(){
+        // An empty fixed block and an empty stored block
+        assert_eq!(decompress(&[0x03, 0x00], usize::MAX), Some(Vec::new()));
+        assert_eq!(decompress(&[0x01, 0x00, 0x00, 0xFF, 0xFF], usize::MAX), Some(Vec::new()));
+    }
+    #[test]
+    fn stored(){
+        assert_eq!(decompress(STORED, usize::MAX), Some(noise(100)));
+    }
+    #[test]
+    fn fixed(){
+        assert_eq!(decompress(FIXED, usize::MAX), Some(fixed_expected()));
+    }
+    #[test]
+    fn multi_block(){
+        assert_eq!(decompress(MULTI_BLOCK, usize::MAX), Some(text(800)));
+    }
+    #[test]
+    fn stops_at_the_end_of_the_stream(){
+        let mut data = FIXED.to_vec();
+        data.extend_from_slice(b"trailing data");
+        let mut out = Vec::new();
+        assert_eq!(inflate(&data, &mut out, usize::MAX), Some(FIXED.len()));
+        assert_eq!(out, fixed_expected());
+    }
+    #[test]
+    fn max(){
+        for (data, len) in [(STORED, 100), (FIXED, 67), (MULTI_BLOCK, 800)]{
+            assert_eq!(decompress(data, len - 1), None);
+            assert_eq!(decompress(data, len).map(|out| out.len()), Some(len));
+        }
+    }
+    #[test]
+    fn truncated(){
+        for data in [STORED, FIXED, MULTI_BLOCK]{
+            for len in 0..data.len(){
+                assert_eq!(decompress(&data[..len], usize::MAX), None, "cut at {len}");
+            }
+        }
+    }
+    #[test]
+    fn corrupt(){
+        // Reserved block type
+        assert_eq!(decompress(&[0x07, 0x00], usize::MAX), None);
+        // Stored block whose NLEN doesn't match its LEN
+        assert_eq!(decompress(&[0x01, 0x01, 0x00, 0xFF, 0xFF, 0x00], usize::MAX), None);
+        assert_eq!(decompress(DISTANCE_TOO_FAR, usize::MAX), None);
+        // Any flipped bit either fails or gives something, it never panics
+        for data in [STORED, FIXED, MULTI_BLOCK]{
+            for bit in 0..data.len()*8{
+                let mut data = data.to_vec();
+                data[bit / 8] ^= 1 << (bit % 8);
+                inflate(&data, &mut Vec::new(), 1 << 20);
+            }
+        }
+    }
+}
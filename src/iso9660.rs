@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+use chrono::NaiveDate;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at};
+
+// The following code decodes ISO9660 (ECMA-119) with the Joliet and Rock Ridge (SUSP/RRIP) extensions
+// https://ecma-international.org/publications-and-standards/standards/ecma-119/
+// Fields are stored both little and big endian, only the little endian half is read.
+// Like the Linux kernel Rock Ridge names are preferred, then Joliet names, then the plain 8.3 names
+
+/// Volume descriptors start at sector 16 and are always 2048 bytes
+const DESCRIPTOR_SIZE: u64 = 2048;
+const FIRST_DESCRIPTOR: u64 = 16;
+const TYPE_PRIMARY: u8 = 1;
+const TYPE_SUPPLEMENTARY: u8 = 2;
+const TYPE_TERMINATOR: u8 = 255;
+const FLAG_DIRECTORY: u8 = 0x02;
+/// The file continues in the next directory record (files bigger than 4GiB)
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+/// Rock Ridge TF entry flags
+const TF_CREATION: u8 = 0x01;
+const TF_MODIFY: u8 = 0x02;
+const TF_LONG_FORM: u8 = 0x80;
+
+/// Converts a date to unix epoch, invalid dates become 0
+pub fn date_to_epoch(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32, offset_secs: i64) -> i64{
+    match NaiveDate::from_ymd_opt(year, month, day).and_then(|d| d.and_hms_opt(hour, minute, second)){
+        Some(dt) => {dt.and_utc().timestamp() - offset_secs}
+        None => {0}
+    }
+}
+/// 7 bytes date of directory records (years since 1900, offset from GMT in 15 minutes intervals)
+fn record_date_to_epoch(b: &[u8]) -> i64{
+    date_to_epoch(1900 + b[0] as i32, b[1] as u32, b[2] as u32, b[3] as u32, b[4] as u32, b[5] as u32, b[6] as i8 as i64 * 900)
+}
+/// 17 bytes date of volume descriptors (and long form Rock Ridge timestamps), digits in ASCII
+fn long_date_to_epoch(b: &[u8]) -> i64{
+    let digits = |from: usize, to: usize| -> u32{
+        String::from_utf8_lossy(&b[from..to]).parse().unwrap_or(0)
+    };
+    date_to_epoch(digits(0, 4) as i32, digits(4, 6), digits(6, 8), digits(8, 10), digits(10, 12), digits(12, 14), b[16] as i8 as i64 * 900)
+}
+#[derive(Debug, PartialEq)]
+enum Names{
+    Plain,
+    Joliet,
+    RockRidge,
+}
+/// What the Rock Ridge entries of a directory record say
+#[derive(Debug, Default)]
+struct RockRidge{
+    name: Option<String>,
+    create_timestamp: Option<i64>,
+    last_modified_timestamp: Option<i64>,
+    /// RE: the directory was moved here from a deeper level, it is listed at its real place through a CL
+    relocated: bool,
+    /// CL: the real location of a relocated directory
+    child_link: Option<u32>,
+}
+struct IsoDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    names: Names,
+    /// Extent and size of the root directory (of the descriptor that is used)
+    root: (u32, u32),
+    /// Bytes to skip at the start of every System Use area (SUSP SP entry)
+    susp_skip: usize,
+    /// Extents of the directories already indexed, a damaged record pointing back to a parent would loop forever
+    visited: HashSet<u32>,
+    files: Vec<IsoFile>,
+    ignored_dirs: Vec<String>
+}
+impl IsoDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut primary = None;
+        let mut joliet = None;
+        for i in 0..64{
+            let mut b = vec![0u8; DESCRIPTOR_SIZE as usize];
+            file.read_at(&mut b, (FIRST_DESCRIPTOR + i)*DESCRIPTOR_SIZE).map_err(Error::io((FIRST_DESCRIPTOR + i)*DESCRIPTOR_SIZE))?;
+            if &b[1..6] != b"CD001" || b[0] == TYPE_TERMINATOR{
+                break;
+            }
+            // Joliet is a supplementary descriptor with one of the UCS-2 escape sequences
+            if b[0] == TYPE_SUPPLEMENTARY && &b[88..90] == b"%/" && matches!(b[90], b'@' | b'C' | b'E'){
+                joliet = Some(b);
+            }else if b[0] == TYPE_PRIMARY && primary.is_none(){
+                primary = Some(b);
+            }
+        }
+        let Some(primary) = primary else{
+            return Err(Error::WrongFilesystem("ISO 9660"));
+        };
+        let block_size = u16_at(&primary, 128) as u64;
+        if !block_size.is_power_of_two() || !(512..=2048).contains(&block_size){
+            return Err(Error::corrupt(FIRST_DESCRIPTOR*DESCRIPTOR_SIZE + 128, format!("invalid block size {block_size}")));
+        }
+        let root_of = |d: &[u8]| (u32_at(d, 156 + 2), u32_at(d, 156 + 10));
+
+        let mut drive = IsoDrive {file, directories: Vec::new(), mounted_at, block_size, names: Names::Plain,
+            root: root_of(&primary), susp_skip: 0, visited: HashSet::new(), files: Vec::new(), ignored_dirs};
+        // Rock Ridge is there if the "." record of the root directory starts with a SUSP SP entry
+        let root = drive.read_bytes(drive.root.0 as u64*block_size, DESCRIPTOR_SIZE)?;
+        let su = 34;
+        if root[0] as usize >= su + 7 && &root[su..su+2] == b"SP" && root[su+4] == 0xBE && root[su+5] == 0xEF{
+            drive.names = Names::RockRidge;
+            drive.susp_skip = root[su+6] as usize;
+        }else if let Some(j) = joliet{
+            drive.names = Names::Joliet;
+            drive.root = root_of(&j);
+        }
+        Ok(drive)
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let (extent, size) = self.root;
+        self.visited.insert(extent);
+        self.index(extent, size, 0)?;
+        Ok(self)
+    }
+    /// Reads the SUSP entries of a System Use area, continuation areas (CE) are followed
+    fn read_rock_ridge(self: &Self, su: &[u8], rr: &mut RockRidge, depth: u32) -> Result<(), Error>{
+        let mut i = 0;
+        while i + 4 <= su.len(){
+            let len = su[i+2] as usize;
+            if len < 4 || i + len > su.len(){
+                break;
+            }
+            let e = &su[i..i+len];
+            match &e[0..2]{
+                b"NM" => {
+                    // Names that don't fit in one entry are split in more NM entries
+                    let name = rr.name.get_or_insert(String::new());
+                    name.push_str(&String::from_utf8_lossy(&e[5..]));
+                }
+                b"TF" => {
+                    let flags = e[4];
+                    let size = if flags & TF_LONG_FORM != 0{17}else{7};
+                    let mut at = 5;
+                    for bit in 0..7{
+                        if flags & (1 << bit) == 0{
+                            continue;
+                        }
+                        if at + size > e.len(){
+                            break;
+                        }
+                        let t = if size == 17{long_date_to_epoch(&e[at..at+17])}else{record_date_to_epoch(&e[at..at+7])};
+                        if 1 << bit == TF_CREATION{
+                            rr.create_timestamp = Some(t);
+                        }else if 1 << bit == TF_MODIFY{
+                            rr.last_modified_timestamp = Some(t);
+                        }
+                        at += size;
+                    }
+                }
+                b"RE" => {rr.relocated = true;}
+                b"CL" => {rr.child_link = Some(u32_at(e, 4));}
+                b"CE" if depth < 16 => {
+                    let area = self.read_bytes(u32_at(e, 4) as u64*self.block_size + u32_at(e, 12) as u64, u32_at(e, 20) as u64)?;
+                    self.read_rock_ridge(&area, rr, depth + 1)?;
+                }
+                b"ST" => {break;}
+                _ => {}
+            }
+            i += len;
+        }
+        Ok(())
+    }
+    fn name_of(self: &Self, raw: &[u8]) -> String{
+        let name = match self.names{
+            Names::Joliet => {
+                let units: Vec<u16> = raw.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+                String::from_utf16_lossy(&units)
+            }
+            // Plain names are shown lowercase like the Linux kernel does
+            _ => {raw.iter().map(|&c| c as char).collect::<String>().to_lowercase()}
+        };
+        // "NAME.EXT;1" -> "NAME.EXT" and "NAME.;1" -> "NAME"
+        let name = match name.rfind(';'){
+            Some(i) => {name[..i].to_string()}
+            None => {name}
+        };
+        if self.names == Names::Plain{
+            return name.trim_end_matches('.').to_string();
+        }
+        name
+    }
+    fn index(self: &mut Self, extent: u32, size: u32, parent: u32) -> Result<(), Error>{
+        let bytes = self.read_bytes(extent as u64*self.block_size, size as u64)?;
+        let mut new_dirs = Vec::new();
+        let mut multi_extent_size = 0;
+        let mut i = 0;
+        while i < bytes.len(){
+            let len = bytes[i] as usize;
+            // Records never cross a sector, the rest of the sector is zero
+            if len == 0{
+                i = (i / DESCRIPTOR_SIZE as usize + 1) * DESCRIPTOR_SIZE as usize;
+                continue;
+            }
+            if len < 34 || i + len > bytes.len(){
+                break;
+            }
+            let r = &bytes[i..i+len];
+            i += len;
+            let name_len = r[32] as usize;
+            let raw_name = &r[33..(33+name_len).min(len)];
+            // "." and ".."
+            if name_len == 1 && raw_name[0] <= 1{
+                continue;
+            }
+            let flags = r[25];
+            let data_length = u32_at(r, 10);
+            if flags & FLAG_MULTI_EXTENT != 0{
+                multi_extent_size += data_length as u64;
+                continue;
+            }
+            let file_size = multi_extent_size + data_length as u64;
+            multi_extent_size = 0;
+
+            let mut rr = RockRidge::default();
+            if self.names == Names::RockRidge{
+                // The System Use area follows the name (padded to an even length)
+                let su_start = 33 + name_len + (1 - name_len % 2) + self.susp_skip;
+                if su_start < len{
+                    self.read_rock_ridge(&r[su_start..], &mut rr, 0)?;
+                }
+            }
+            if rr.relocated{
+                continue;
+            }
+            let recorded = record_date_to_epoch(&r[18..25]);
+            let name = rr.name.take().unwrap_or_else(|| self.name_of(raw_name));
+            let mut dir_extent = u32_at(r, 2);
+            let mut dir_size = data_length;
+            let is_dir = flags & FLAG_DIRECTORY != 0 || rr.child_link.is_some();
+            if let Some(location) = rr.child_link{
+                // The size of a relocated directory is in its own "." record
+                let dot = self.read_bytes(location as u64*self.block_size, 34)?;
+                dir_extent = location;
+                dir_size = u32_at(&dot, 10);
+            }
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(IsoFile{
+                name,
+                parent,
+                size: if is_dir{dir_size as u64}else{file_size},
+                is_dir,
+                create_timestamp: rr.create_timestamp.unwrap_or(recorded),
+                last_modified_timestamp: rr.last_modified_timestamp.unwrap_or(recorded)
+            });
+            if is_dir && self.visited.insert(dir_extent){
+                new_dirs.push((full_name, dir_extent, dir_size));
+            }
+        }
+        for (name, extent, size) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(extent, size, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct IsoFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_iso_files_to_files(f: &IsoFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// CDs/DVDs and .iso files, with Joliet and Rock Ridge names
+/// `drive` can be an optical drive (/dev/sr0) or an .iso file
+pub struct Iso9660Indexer;
+impl FilesystemIndexer for Iso9660Indexer{
+    fn name(self: &Self) -> &'static str{
+        "Iso9660"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[0x8001..0x8006] == b"CD001"
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = IsoDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_iso_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: false, reads_device: true, deleted_files: false}
+    }
+}
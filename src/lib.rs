@@ -0,0 +1,549 @@
+//! Anything indexes drives by reading their filesystem directly (or by walking a mountpoint) and searches
+//! the result, the GUI is only a consumer of this library.
+//!
+//! ```no_run
+//! use anything::{Index, Query};
+//!
+//! let drives = anything::get_devices();
+//! let (index, report) = Index::build(&drives, &anything::Settings::default());
+//! for (drive, error) in report.failed{
+//!     eprintln!("{drive} was not indexed: {error}");
+//! }
+//! for result in index.search(&Query::new("\\_*IMG\\*_.jpg")){
+//!     println!("{} {}", index.path(&result.file), anything::size_to_pretty_string(result.file.size));
+//! }
+//! ```
+//!
+//! New filesystems are added by implementing [`indexer::FilesystemIndexer`] and registering it in
+//! [`indexer::registry`] before the drives are loaded.
+// Methods are written with an explicit `self: &Self` throughout
+#![allow(clippy::needless_arbitrary_self_type)]
+mod apfs;
+mod archive;
+mod blockdev;
+mod btrfs;
+mod erofs;
+mod error;
+mod exfat;
+mod ext4;
+mod f2fs;
+mod fat;
+mod generic;
+mod hfsplus;
+mod index;
+pub mod indexer;
+mod inflate;
+mod iso9660;
+mod lzma;
+mod ntfs;
+mod partitions;
+mod probe;
+mod search;
+mod squashfs;
+mod udf;
+mod unicode;
+mod xfs;
+mod zstd;
+pub use error::Error;
+pub use index::{Index, IndexReport};
+pub use partitions::PartitionFile;
+pub use search::{Query, SearchResult};
+
+/// Size in bytes as shown in the Size column, like "1.50MiB"
+pub fn size_to_pretty_string(size: u64) -> String{
+    if size < 1024{
+        return size.to_string() + "B";
+    }
+    if size < 1048576{
+        return format!("{:.2}KiB", size as f64 / 1024.0);
+    }
+    if size < 1073741824{
+        return format!("{:.2}MiB", size as f64 / 1048576.0);
+    }
+    if size < 1099511627776{
+        format!("{:.2}GiB", size as f64 / 1073741824.0)
+    }else{
+        format!("{:.2}TiB", size as f64 / 1099511627776.0)
+    }
+}
+pub fn timestamp_to_string(t: i64)-> String{
+    // let secs = (timestamp_ms / 1000); commented it out because apparently the information about ms is not stored inside of the timestamp ???
+    // let nanos = ((timestamp_ms % 1000) * 1_000_000) as u32; // ms → ns
+
+    // Timestamps out of chrono's range (a few hundred thousand years) come from broken metadata
+    match chrono::DateTime::from_timestamp(t, 0){
+        Some(naive) => {naive.format("%d/%m/%Y %H:%M:%S").to_string()}
+        None => {String::from("?")}
+    }
+}
+/// Little endian helpers used by the filesystem decoders
+pub(crate) fn u16_at(b: &[u8], i: usize) -> u16{
+    u16::from_le_bytes([b[i], b[i+1]])
+}
+pub(crate) fn u32_at(b: &[u8], i: usize) -> u32{
+    u32::from_le_bytes([b[i], b[i+1], b[i+2], b[i+3]])
+}
+pub(crate) fn u64_at(b: &[u8], i: usize) -> u64{
+    u64::from_le_bytes([b[i], b[i+1], b[i+2], b[i+3], b[i+4], b[i+5], b[i+6], b[i+7]])
+}
+#[derive(Debug, Default, Clone)]
+pub struct Directory{
+    /// Full path, ending with "/"
+    pub name: String
+}
+#[derive(Debug, Default, Clone)]
+pub struct File{
+    pub name: String,
+    /// Position of the directory that contains it in the list of directories
+    pub parent: u32,
+    pub size: u64,
+    pub is_dir: bool,
+    /// Unix epoch, the modification time on filesystems that don't store a creation time
+    pub create_timestamp: i64,
+    pub last_modified_timestamp: i64,
+    /// Set for files that were deleted but still have a directory entry, see Drive::include_deleted
+    pub deleted: Option<Deleted>,
+    pub attributes: Attributes,
+}
+/// Attributes of a file the way FAT, exFAT and NTFS store them (the bits they share are the same).
+/// Other filesystems have none of them, their directories only get DIRECTORY
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Attributes(pub u32);
+impl Attributes{
+    pub const READ_ONLY: u32 = 0x01;
+    pub const HIDDEN: u32 = 0x02;
+    pub const SYSTEM: u32 = 0x04;
+    pub const DIRECTORY: u32 = 0x10;
+    pub const ARCHIVE: u32 = 0x20;
+    /// The letter of each attribute in the Attributes column and in "attrib:" queries
+    const LETTERS: [(char, u32); 5] = [('R', Self::READ_ONLY), ('H', Self::HIDDEN), ('S', Self::SYSTEM),
+        ('D', Self::DIRECTORY), ('A', Self::ARCHIVE)];
+    /// For filesystems without attributes
+    pub fn only_directory(is_dir: bool) -> Self{
+        if is_dir{Attributes(Self::DIRECTORY)}else{Attributes::default()}
+    }
+    /// Whether all of `bits` are set
+    pub fn contains(self: &Self, bits: u32) -> bool{
+        self.0 & bits == bits
+    }
+    /// Like "HSA"
+    pub fn to_letters(self: &Self) -> String{
+        Self::LETTERS.iter().filter(|(_, bit)| self.contains(*bit)).map(|(letter, _)| letter).collect()
+    }
+    /// The bits of letters like "hs" (the case doesn't matter), None if one of them isn't an attribute
+    pub fn from_letters(letters: &str) -> Option<u32>{
+        let mut bits = 0;
+        for c in letters.chars(){
+            bits |= Self::LETTERS.iter().find(|(letter, _)| letter.eq_ignore_ascii_case(&c))?.1;
+        }
+        Some(bits)
+    }
+}
+/// What is left of a deleted file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deleted{
+    /// None of its clusters were given to other files yet, its content should still be there
+    Recoverable,
+    /// Some of its clusters are used by other files now
+    Overwritten,
+}
+#[derive(Debug, Default, Clone)]
+pub struct Drive{
+    /// Name of the filesystem in the registry
+    pub fs: &'static str,
+    /// Device (/dev/sdc1) or image (image.img#p2) that is read
+    pub drive: String,
+    /// Where its files are shown, usually its mountpoint
+    pub mounted_at: String,
+    /// Full paths of the directories that are skipped
+    pub ignored_dirs: Vec<String>,
+    /// Whether the members of the archives (zip, tar, 7z...) found on the drive are indexed too
+    pub index_archives: bool,
+    /// Whether the directory entries of deleted files are indexed too (only on backends that can, see
+    /// Capabilities::deleted_files)
+    pub include_deleted: bool,
+    /// What probe found on the drive (not saved), None if the drive couldn't be read
+    pub detected_fs: Option<&'static str>
+}
+/// Unknown names (like a backend that isn't there anymore) can still be indexed through the mountpoint
+fn string_to_fs(string: &str) -> &'static str{
+    match indexer::registry().read().unwrap().get(string){
+        Some(indexer) => {indexer.name()}
+        None => {indexer::GENERIC}
+    }
+}
+#[derive(Debug, Default, Clone)]
+pub struct Settings{
+    /// file, path, size, date modified, date created
+    pub columns: Vec<u16>,
+    pub sort_in_use: Sort,
+    pub index_on_startup: bool,
+    pub index_every_minutes: u32,
+    pub instant_search: bool,
+    pub journal: bool,
+    pub ignore_case: bool,
+    pub search_full_path: bool,
+    pub light_mode: bool,
+    /// Archives bigger than this are not opened
+    pub archive_max_size_mb: u64,
+    /// How many levels of archives inside of archives are opened
+    pub archive_max_depth: u32
+}
+fn string_to_sort(string: &str) -> Sort{
+    match string{
+        "DateCreatedAscending" => {Sort::DateCreatedAscending}
+        "DateCreatedDescending" => {Sort::DateCreatedDescending}
+        "DateModifiedAscending" => {Sort::DateModifiedAscending}
+        "DateModifiedDescending" => {Sort::DateModifiedDescending}
+        "SizeAscending" => {Sort::SizeAscending}
+        "SizeDescending" => {Sort::SizeDescending}
+        "PathAscending" => {Sort::PathAscending}
+        "PathDescending" => {Sort::PathDescending}
+        "FileAscending" => {Sort::FileAscending}
+        "FileDescending" => {Sort::FileDescending}
+        _ => {Sort::default()}
+    }
+}
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum Sort{
+    #[default]
+    DateCreatedAscending,
+    DateCreatedDescending,
+    DateModifiedAscending,
+    DateModifiedDescending,
+    SizeAscending,
+    SizeDescending,
+    PathAscending,
+    PathDescending,
+    FileAscending,
+    FileDescending
+}
+/// The mounted partitions of the disks (sd*, sr* and nvme*) listed by lsblk, each one set to the filesystem probe
+/// found on it (Generic if none). Empty if lsblk can't be run
+pub fn get_devices()->Vec<Drive>{
+    let Ok(lsblk) = std::process::Command::new("lsblk")
+        .args(["-l", "-n", "-o", "NAME,MOUNTPOINT"])
+        .output() else{
+        return Vec::new();
+    };
+    let mut drives = Vec::new();
+    let lines = lines_from_bytes(lsblk.stdout);
+    for line in &lines{
+        if line.contains(&b'/') && (line[0] == b's' && (line[1] == b'd' || line[1] == b'r') || line[0] == b'n'){
+            let space = line.iter().position(|&c| c == b' ').unwrap_or(0);
+            let drive = &line[0..space];
+            let slash = line.iter().position(|&c| c == b'/').unwrap_or(0);
+            let mounted_at = &line[slash..line.len()-1];
+            let mut drive = drive.to_vec();
+            let mut dev = b"/dev/".to_vec();
+            dev.append(&mut drive);
+            let drive = String::from_utf8_lossy(&dev).to_string();
+            let mounted_at = String::from_utf8_lossy(mounted_at).to_string();
+            let detected_fs = probe::probe(&drive);
+            let fs = detected_fs.unwrap_or(indexer::GENERIC);
+            drives.push(Drive{fs,drive,mounted_at,ignored_dirs:vec![],index_archives:false,include_deleted:false,detected_fs});
+        }
+    }
+    drives
+}
+/// Turns a disk image (or a whole disk like /dev/sda) into drives, one for each partition with a filesystem
+/// that can be read directly. Images without a partition table are a single drive.
+/// Nothing is mounted so the files are shown under the name of the drive ("image.img#p2/...")
+pub fn image_drives(path: &str) -> Vec<Drive>{
+    let mut drives = Vec::new();
+    let whole = probe::probe(path);
+    let partitions = match blockdev::open(path){
+        Ok(file) => {partitions::read_partition_table(&file)}
+        Err(_) => {Vec::new()}
+    };
+    let mut candidates = vec![path.to_string()];
+    // A filesystem boot sector can look like an MBR, what probe found wins
+    if !partitions.is_empty() && whole == Some(indexer::GENERIC){
+        candidates = partitions.iter().map(|p| format!("{path}#p{}", p.number)).collect();
+    }
+    for drive in candidates{
+        let detected_fs = probe::probe(&drive);
+        // Only what can be read directly, the others need a mountpoint to walk
+        if let Some(fs) = detected_fs && indexer::registry().read().unwrap().get(fs).is_some_and(|i| i.capabilities().reads_device){
+            drives.push(Drive{fs, mounted_at: drive.clone(), drive, ignored_dirs: vec![], index_archives: false, include_deleted: false, detected_fs});
+        }
+    }
+    drives
+}
+/// Splits the output of a command into lines, they keep their "\n"
+pub(crate) fn lines_from_bytes(mut data: Vec<u8>) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+
+    while let Some(pos) = data[0..].iter().position(|&b| b == b'\n') {
+        let end = pos;
+        lines.push(data.drain(0..=end).collect());
+        // No need for start = end + 1; drain adjusts remaining data
+    }
+
+    // Last line
+    if !data.is_empty() {
+        lines.push(std::mem::take(&mut data));
+    }
+
+    lines
+}
+
+use std::io::{BufRead, BufWriter, Write};
+use std::env;
+use std::path::Path;
+/// Characters that separate the fields of drives.txt, paths write them in octal like /etc/fstab does (a space is \040)
+const DRIVES_ESCAPED: [char; 5] = [' ', ',', '[', '\\', ']'];
+fn escape_path(path: &str) -> String{
+    let mut escaped = String::new();
+    for c in path.chars(){
+        if DRIVES_ESCAPED.contains(&c){
+            escaped += &format!("\\{:03o}", c as u32);
+        }else{
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+fn unescape_path(field: &str) -> String{
+    let mut path = String::new();
+    let mut rest = field;
+    while let Some(i) = rest.find('\\'){
+        path += &rest[..i];
+        let code = rest.get(i+1..i+4).filter(|o| o.bytes().all(|b| (b'0'..=b'7').contains(&b))).and_then(|o| u8::from_str_radix(o, 8).ok());
+        match code{
+            Some(c) => {path.push(c as char); rest = &rest[i+4..];}
+            None => {path.push('\\'); rest = &rest[i+1..];}
+        }
+    }
+    path + rest
+}
+/// Writes the drives to drives.txt next to the binary (or the AppImage), one line per drive
+pub fn save_drives(drives: Vec<Drive>){
+    let binary_path = env::current_exe().unwrap();
+    let parent_dir = binary_path.parent().unwrap();
+    let save_drives_path = parent_dir.join("settings").join("drives.txt");
+    let file = match std::fs::OpenOptions::new().write(true).truncate(true).open(save_drives_path){
+        Ok(a) => {a},
+        Err(_) =>{
+            // If it is an appimage
+            match env::var("APPIMAGE"){
+                Err(_) => {return;}
+                Ok(s) =>{
+                    let appimage_path = Path::new(&s);
+                    let app_dir = appimage_path.parent().unwrap();
+                    let settings_dir = app_dir.join("settings");
+                    let path = settings_dir.join("drives.txt");
+                    std::fs::OpenOptions::new().write(true).truncate(true).open(path).unwrap()
+                }
+            }
+        }
+    };
+
+    let mut writer = BufWriter::new(file);
+    // Write new lines, overwriting everything
+    for drive in drives{
+        let mut s = String::from("[");
+        for dir in 0..drive.ignored_dirs.len(){
+            if dir == drive.ignored_dirs.len()-1{
+                s = format!("{s}{}",escape_path(&drive.ignored_dirs[dir]));
+            }else{
+                s = format!("{s}{}, ",escape_path(&drive.ignored_dirs[dir]));
+            }
+        }
+        s = format!("{s}]");
+        let archives = if drive.index_archives{" Archives"}else{""};
+        let deleted = if drive.include_deleted{" Deleted"}else{""};
+        writeln!(writer, "{} {} {}{}{} {}",
+            escape_path(&drive.drive), escape_path(&drive.mounted_at), drive.fs, archives, deleted, s).unwrap();
+
+    }
+    writer.flush().unwrap();
+}
+/// Reads the drives saved by `save_drives` and probes them again, empty if there are none
+pub fn load_drives() -> Vec<Drive>{
+    let mut output = Vec::new();
+    let binary_path = env::current_exe().unwrap();
+    let parent_dir = binary_path.parent().unwrap();
+    let save_drives_path = parent_dir.join("settings").join("drives.txt");
+    let file = match std::fs::File::open(save_drives_path){
+        Ok(a) => {a},
+        Err(_) =>{
+            // If it is an appimage
+            match env::var("APPIMAGE"){
+                Err(_) => {return output;}
+                Ok(s) =>{
+                    let appimage_path = Path::new(&s);
+                    let app_dir = appimage_path.parent().unwrap();
+                    let settings_dir = app_dir.join("settings");
+                    let path = settings_dir.join("drives.txt");
+                    match std::fs::File::open(path){
+                        Ok(file) => {file}
+                        Err(_) => {return output;}
+                    }
+                }
+            }
+        }
+    };
+
+    let reader = std::io::BufReader::new(file);
+    for line in reader.lines(){
+        let line = line.unwrap();
+        let mut drive = String::new();
+        let mut mounted_at = String::new();
+        let mut fs = indexer::GENERIC;
+        let mut ignored_dirs = Vec::new();
+        let mut index_archives = false;
+        let mut include_deleted = false;
+        // The options come after the filesystem and before the ignored directories
+        for (i, attr) in line.split(' ').enumerate(){
+            match i{
+                0=>{drive=unescape_path(attr)}
+                1=>{mounted_at=unescape_path(attr)}
+                2=>{fs=string_to_fs(attr)}
+                _ if attr.starts_with('[') =>{break}
+                _ =>{
+                    index_archives |= attr=="Archives";
+                    include_deleted |= attr=="Deleted";
+                }
+            }
+        }
+        let mut idx = 0;
+        for x in 0..line.len(){
+            if line.as_bytes()[x] == b'['{
+                idx = x;
+            }
+        }
+
+        for dir in line[idx+1..line.len()-1].split(", "){
+            if !dir.is_empty(){
+                ignored_dirs.push(unescape_path(dir));
+            }
+        }
+        let detected_fs = probe::probe(&drive);
+        output.push(Drive { fs, drive, mounted_at, ignored_dirs, index_archives, include_deleted, detected_fs})
+    }
+    output
+}
+pub fn save_settings(settings: Settings){
+
+    let binary_path = env::current_exe().unwrap();
+    let parent_dir = binary_path.parent().unwrap();
+    let save_settings_path = parent_dir.join("settings").join("settings.txt");
+    let file = match std::fs::OpenOptions::new().write(true).truncate(true).open(save_settings_path){
+        Ok(a) => {a},
+        Err(e) =>{
+            // If it is an appimage
+            dbg!(e);
+            let appimage_path = env::var("APPIMAGE")
+                    .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "APPIMAGE env var not set")).unwrap();
+            let appimage_path = Path::new(&appimage_path);
+            let app_dir = appimage_path.parent().unwrap();
+            let settings_dir = app_dir.join("settings");
+            let path = settings_dir.join("settings.txt");
+            std::fs::OpenOptions::new().write(true).truncate(true).open(path).unwrap()
+        }
+    };
+
+    let mut writer = BufWriter::new(file);
+    // Write new lines, overwriting everything
+    for i in 0..11{
+        match i{
+            0 => {writeln!(writer, "columns:{:?}",settings.columns).unwrap()}
+            1 => {writeln!(writer, "sort_in_use:{:?}",settings.sort_in_use).unwrap()}
+            2 => {writeln!(writer, "index_on_startup:{:?}",settings.index_on_startup).unwrap()}
+            3 => {writeln!(writer, "index_every_minutes:{:?}",settings.index_every_minutes).unwrap()}
+            4 => {writeln!(writer, "instant_search:{:?}",settings.instant_search).unwrap()}
+            5 => {writeln!(writer, "journal:{:?}",settings.journal).unwrap()}
+            6 => {writeln!(writer, "ignore_case:{:?}",settings.ignore_case).unwrap()}
+            7 => {writeln!(writer, "search_full_path:{:?}",settings.search_full_path).unwrap()}
+            8 => {writeln!(writer, "light_mode:{:?}",settings.light_mode).unwrap()}
+            9 => {writeln!(writer, "archive_max_size_mb:{:?}",settings.archive_max_size_mb).unwrap()}
+            10 => {writeln!(writer, "archive_max_depth:{:?}",settings.archive_max_depth).unwrap()}
+            _ => {}
+        }
+
+
+    }
+    writer.flush().unwrap();
+}
+pub fn load_settings() -> Settings{
+
+    let binary_path = env::current_exe().unwrap();
+    let parent_dir = binary_path.parent().unwrap();
+    let save_settings_path = parent_dir.join("settings").join("settings.txt");
+    let file = match std::fs::File::open(save_settings_path){
+        Ok(a) => {a},
+        Err(_) =>{
+            // If it is an appimage
+            match env::var("APPIMAGE"){
+                Err(_) => {return Settings::default();}
+                Ok(s) =>{
+                    let appimage_path = Path::new(&s);
+                    let app_dir = appimage_path.parent().unwrap();
+                    let settings_dir = app_dir.join("settings");
+                    let path = settings_dir.join("settings.txt");
+                    match std::fs::File::open(path){
+                        Ok(file) => {file}
+                        Err(_) => {return Settings::default();}
+                    }
+                }
+            }
+        }
+    };
+
+    let reader = std::io::BufReader::new(file);
+
+    let mut sort_in_use = Sort::default();
+    let mut index_on_startup = true;
+    let mut index_every_minutes = 0;
+    let mut instant_search = true;
+    let mut journal = false;
+    let mut ignore_case = true;
+    let mut columns = Vec::new();
+    let mut search_full_path = true;
+    let mut light_mode = true;
+    let mut archive_max_size_mb = 100;
+    let mut archive_max_depth = 2;
+
+    let mut i = 0;
+    for line in reader.lines(){
+        let line = line.unwrap();
+        for attr in line.split(':'){
+            match i{
+                1=>{
+                    for c in attr[0..attr.len()-1].split(','){
+                        if let Ok(width) = c.trim_start_matches(['[', ' ']).parse::<u16>(){
+                            columns.push(width);
+                        }
+                    }
+                }
+                3=>{sort_in_use=string_to_sort(attr)}
+                5=>{index_on_startup=attr=="true"}
+                7=>{index_every_minutes=attr.parse::<u32>().expect("Line {i} is not a number")}
+                9=>{instant_search=attr=="true"}
+                11=>{journal=attr=="true"}
+                13=>{ignore_case=attr=="true"}
+                15=>{search_full_path=attr=="true"}
+                17=>{light_mode=attr=="true"}
+                19=>{archive_max_size_mb=attr.parse::<u64>().unwrap_or(archive_max_size_mb)}
+                21=>{archive_max_depth=attr.parse::<u32>().unwrap_or(archive_max_depth)}
+
+                _ =>{}
+            }
+            i+= 1;
+        }
+    }
+    Settings{
+        columns,
+        sort_in_use,
+        index_every_minutes,
+        index_on_startup,
+        instant_search,
+        journal,
+        ignore_case,
+        search_full_path,
+        light_mode,
+        archive_max_size_mb,
+        archive_max_depth
+    }
+}
@@ -0,0 +1,576 @@
+// The following code decompresses LZMA, LZMA2 and .xz streams following the LZMA specification of the LZMA SDK
+// https://www.7-zip.org/sdk.html (lzma-specification.txt) and the .xz format https://tukaani.org/xz/xz-file-format.txt
+// Integrity checks (CRC32/CRC64/SHA-256) are skipped, broken data is caught by the decoder itself
+
+const NUM_STATES: usize = 12;
+const NUM_POS_STATES_MAX: usize = 16;
+const NUM_LEN_TO_POS_STATES: usize = 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const NUM_FULL_DISTANCES: usize = 128;
+const MATCH_MIN_LEN: usize = 2;
+/// Probabilities are 11 bits, they start at 0.5
+const PROB_INIT: u16 = 1024;
+const XZ_MAGIC: &[u8] = b"\xFD7zXZ\0";
+const XZ_FILTER_LZMA2: u64 = 0x21;
+/// LZMA-alone headers: properties, dictionary size, uncompressed size (all ones if unknown)
+const LZMA_HEADER_SIZE: usize = 13;
+
+struct RangeDecoder<'a>{
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+impl RangeDecoder<'_>{
+    fn new(data: &[u8]) -> Option<RangeDecoder<'_>>{
+        // The first byte is always 0
+        if data.len() < 5 || data[0] != 0{
+            return None;
+        }
+        let code = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        Some(RangeDecoder{data, pos: 5, range: 0xFFFF_FFFF, code})
+    }
+    fn normalize(self: &mut Self) -> Option<()>{
+        if self.range < 1 << 24{
+            self.range <<= 8;
+            self.code = (self.code << 8) | *self.data.get(self.pos)? as u32;
+            self.pos += 1;
+        }
+        Some(())
+    }
+    fn bit(self: &mut Self, prob: &mut u16) -> Option<u32>{
+        let bound = (self.range >> 11) * *prob as u32;
+        let bit = if self.code < bound{
+            self.range = bound;
+            *prob += (2048 - *prob) >> 5;
+            0
+        }else{
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> 5;
+            1
+        };
+        self.normalize()?;
+        Some(bit)
+    }
+    /// Bits with a fixed probability of 0.5
+    fn direct_bits(self: &mut Self, n: u32) -> Option<u32>{
+        let mut v = 0;
+        for _ in 0..n{
+            self.range >>= 1;
+            let bit = if self.code >= self.range{
+                self.code -= self.range;
+                1
+            }else{
+                0
+            };
+            v = (v << 1) | bit;
+            self.normalize()?;
+        }
+        Some(v)
+    }
+    /// Decodes n bits, highest first, every node of the tree has its own probability
+    fn bit_tree(self: &mut Self, probs: &mut [u16], n: u32) -> Option<u32>{
+        let mut m = 1;
+        for _ in 0..n{
+            m = (m << 1) | self.bit(&mut probs[m as usize])?;
+        }
+        Some(m - (1 << n))
+    }
+    /// Like bit_tree but the lowest bit comes first
+    fn reverse_bit_tree(self: &mut Self, probs: &mut [u16], n: u32) -> Option<u32>{
+        let mut m = 1;
+        let mut symbol = 0;
+        for i in 0..n{
+            let bit = self.bit(&mut probs[m as usize])?;
+            m = (m << 1) | bit;
+            symbol |= bit << i;
+        }
+        Some(symbol)
+    }
+}
+struct LenDecoder{
+    choice: u16,
+    choice2: u16,
+    low: [u16; NUM_POS_STATES_MAX << 3],
+    mid: [u16; NUM_POS_STATES_MAX << 3],
+    high: [u16; 256],
+}
+impl LenDecoder{
+    fn new() -> LenDecoder{
+        LenDecoder{choice: PROB_INIT, choice2: PROB_INIT, low: [PROB_INIT; NUM_POS_STATES_MAX << 3], mid: [PROB_INIT; NUM_POS_STATES_MAX << 3], high: [PROB_INIT; 256]}
+    }
+    /// Returns the length minus MATCH_MIN_LEN
+    fn decode(self: &mut Self, rc: &mut RangeDecoder, pos_state: usize) -> Option<usize>{
+        if rc.bit(&mut self.choice)? == 0{
+            return Some(rc.bit_tree(&mut self.low[pos_state << 3..], 3)? as usize);
+        }
+        if rc.bit(&mut self.choice2)? == 0{
+            return Some(8 + rc.bit_tree(&mut self.mid[pos_state << 3..], 3)? as usize);
+        }
+        Some(16 + rc.bit_tree(&mut self.high, 8)? as usize)
+    }
+}
+struct LzmaDecoder{
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    state: usize,
+    reps: [usize; 4],
+    literal: Vec<u16>,
+    is_match: [u16; NUM_STATES << 4],
+    is_rep: [u16; NUM_STATES],
+    is_rep0: [u16; NUM_STATES],
+    is_rep1: [u16; NUM_STATES],
+    is_rep2: [u16; NUM_STATES],
+    is_rep0_long: [u16; NUM_STATES << 4],
+    pos_slot: [u16; NUM_LEN_TO_POS_STATES << 6],
+    pos_decoders: [u16; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 16],
+    match_len: LenDecoder,
+    rep_len: LenDecoder,
+}
+impl LzmaDecoder{
+    /// The properties byte packs the number of literal context bits, literal position bits and position bits
+    fn new(properties: u8) -> Option<LzmaDecoder>{
+        if properties >= 9*5*5{
+            return None;
+        }
+        let lc = (properties % 9) as u32;
+        let lp = (properties / 9 % 5) as u32;
+        let pb = (properties / 45) as u32;
+        Some(LzmaDecoder{
+            lc, lp, pb,
+            state: 0,
+            reps: [0; 4],
+            literal: vec![PROB_INIT; 0x300 << (lc + lp)],
+            is_match: [PROB_INIT; NUM_STATES << 4],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep0: [PROB_INIT; NUM_STATES],
+            is_rep1: [PROB_INIT; NUM_STATES],
+            is_rep2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [PROB_INIT; NUM_STATES << 4],
+            pos_slot: [PROB_INIT; NUM_LEN_TO_POS_STATES << 6],
+            pos_decoders: [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [PROB_INIT; 16],
+            match_len: LenDecoder::new(),
+            rep_len: LenDecoder::new(),
+        })
+    }
+    fn reset(self: &mut Self){
+        let properties = (self.pb*45 + self.lp*9 + self.lc) as u8;
+        *self = LzmaDecoder::new(properties).unwrap();
+    }
+    fn decode_distance(self: &mut Self, rc: &mut RangeDecoder, len: usize) -> Option<usize>{
+        let len_state = len.min(NUM_LEN_TO_POS_STATES - 1);
+        let slot = rc.bit_tree(&mut self.pos_slot[len_state << 6..], 6)?;
+        if slot < 4{
+            return Some(slot as usize);
+        }
+        let direct = (slot >> 1) - 1;
+        let mut distance = (2 | (slot & 1)) << direct;
+        if slot < END_POS_MODEL_INDEX{
+            distance += rc.reverse_bit_tree(&mut self.pos_decoders[(distance - slot) as usize..], direct)?;
+        }else{
+            distance += rc.direct_bits(direct - 4)? << 4;
+            distance += rc.reverse_bit_tree(&mut self.align, 4)?;
+        }
+        Some(distance as usize)
+    }
+    /// Decodes until `size` bytes were written (or the end marker if the size is unknown).
+    /// `out` is also the dictionary, matches can't reach before `dict_start`. None if `out` would grow past `max` bytes
+    fn decode(self: &mut Self, rc: &mut RangeDecoder, out: &mut Vec<u8>, dict_start: usize, size: Option<usize>, max: usize) -> Option<()>{
+        let start = out.len();
+        loop{
+            if let Some(size) = size && out.len() - start >= size{
+                return Some(());
+            }
+            let pos = out.len() - dict_start;
+            let pos_state = pos & ((1 << self.pb) - 1);
+            if rc.bit(&mut self.is_match[(self.state << 4) + pos_state])? == 0{
+                let previous = if pos > 0{out[out.len() - 1] as usize}else{0};
+                let lit_state = ((pos & ((1 << self.lp) - 1)) << self.lc) + (previous >> (8 - self.lc));
+                let probs = &mut self.literal[0x300*lit_state..0x300*(lit_state + 1)];
+                let mut symbol = 1;
+                if self.state >= 7{
+                    // After a match the byte that follows the match is a good guess
+                    let mut match_byte = *out.get(out.len().checked_sub(self.reps[0] + 1)?)? as usize;
+                    while symbol < 0x100{
+                        let match_bit = (match_byte >> 7) & 1;
+                        match_byte <<= 1;
+                        let bit = rc.bit(&mut probs[((1 + match_bit) << 8) + symbol])? as usize;
+                        symbol = (symbol << 1) | bit;
+                        if match_bit != bit{
+                            break;
+                        }
+                    }
+                }
+                while symbol < 0x100{
+                    symbol = (symbol << 1) | rc.bit(&mut probs[symbol])? as usize;
+                }
+                if out.len() >= max{
+                    return None;
+                }
+                out.push(symbol as u8);
+                self.state = if self.state < 4{0}else if self.state < 10{self.state - 3}else{self.state - 6};
+                continue;
+            }
+            let len;
+            if rc.bit(&mut self.is_rep[self.state])? != 0{
+                if pos == 0{
+                    return None;
+                }
+                if rc.bit(&mut self.is_rep0[self.state])? == 0{
+                    if rc.bit(&mut self.is_rep0_long[(self.state << 4) + pos_state])? == 0{
+                        // Short rep: a single byte from the last distance
+                        self.state = if self.state < 7{9}else{11};
+                        if out.len() >= max{
+                            return None;
+                        }
+                        out.push(*out.get(out.len().checked_sub(self.reps[0] + 1)?)?);
+                        continue;
+                    }
+                }else{
+                    let distance;
+                    if rc.bit(&mut self.is_rep1[self.state])? == 0{
+                        distance = self.reps[1];
+                    }else{
+                        if rc.bit(&mut self.is_rep2[self.state])? == 0{
+                            distance = self.reps[2];
+                        }else{
+                            distance = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                        }
+                        self.reps[2] = self.reps[1];
+                    }
+                    self.reps[1] = self.reps[0];
+                    self.reps[0] = distance;
+                }
+                len = self.rep_len.decode(rc, pos_state)?;
+                self.state = if self.state < 7{8}else{11};
+            }else{
+                self.reps[3] = self.reps[2];
+                self.reps[2] = self.reps[1];
+                self.reps[1] = self.reps[0];
+                len = self.match_len.decode(rc, pos_state)?;
+                self.state = if self.state < 7{7}else{10};
+                self.reps[0] = self.decode_distance(rc, len)?;
+                if self.reps[0] == 0xFFFF_FFFF{
+                    // End marker
+                    return Some(());
+                }
+            }
+            let distance = self.reps[0] + 1;
+            let len = len + MATCH_MIN_LEN;
+            if distance > pos || size.is_some_and(|size| out.len() - start + len > size) || out.len() + len > max{
+                return None;
+            }
+            // The copy may overlap with what it is writing
+            let from = out.len() - distance;
+            for i in 0..len{
+                out.push(out[from + i]);
+            }
+        }
+    }
+}
+/// Decompresses an LZMA2 stream (a sequence of LZMA and uncompressed chunks), None if `out` would grow past `max` bytes.
+/// Returns how many bytes of `data` the stream used
+pub fn lzma2(data: &[u8], out: &mut Vec<u8>, max: usize) -> Option<usize>{
+    let mut pos = 0;
+    let mut decoder: Option<LzmaDecoder> = None;
+    let mut dict_start = out.len();
+    loop{
+        let control = *data.get(pos)?;
+        pos += 1;
+        if control == 0{
+            return Some(pos);
+        }
+        let chunk_size = |at: usize| Some(u16::from_be_bytes([*data.get(at)?, *data.get(at + 1)?]) as usize + 1);
+        if control == 1 || control == 2{
+            // Uncompressed chunk, 1 also resets the dictionary
+            if control == 1{
+                dict_start = out.len();
+            }
+            let size = chunk_size(pos)?;
+            if out.len() + size > max{
+                return None;
+            }
+            out.extend_from_slice(data.get(pos + 2..pos + 2 + size)?);
+            pos += 2 + size;
+            continue;
+        }
+        if control < 0x80{
+            return None;
+        }
+        let unpacked = (((control & 0x1F) as usize) << 16) + chunk_size(pos)?;
+        let packed = chunk_size(pos + 2)?;
+        pos += 4;
+        // What gets reset: 1 = state, 2 = state and properties, 3 = everything including the dictionary
+        let reset = (control >> 5) & 3;
+        if reset == 3{
+            dict_start = out.len();
+        }
+        if reset >= 2{
+            decoder = Some(LzmaDecoder::new(*data.get(pos)?)?);
+            pos += 1;
+        }else if reset == 1{
+            decoder.as_mut()?.reset();
+        }
+        let mut rc = RangeDecoder::new(data.get(pos..pos + packed)?)?;
+        decoder.as_mut()?.decode(&mut rc, out, dict_start, Some(unpacked), max)?;
+        pos += packed;
+    }
+}
+/// Decompresses an LZMA-alone stream (.lzma files), None if it would be bigger than `max` bytes
+pub fn unlzma(data: &[u8], max: usize) -> Option<Vec<u8>>{
+    if data.len() < LZMA_HEADER_SIZE{
+        return None;
+    }
+    let mut decoder = LzmaDecoder::new(data[0])?;
+    let size = u64::from_le_bytes(data[5..13].try_into().unwrap());
+    let size = if size == u64::MAX{None}else{Some(size as usize)};
+    if size.is_some_and(|size| size > max){
+        return None;
+    }
+    let mut rc = RangeDecoder::new(&data[LZMA_HEADER_SIZE..])?;
+    let mut out = Vec::new();
+    decoder.decode(&mut rc, &mut out, 0, size, max)?;
+    Some(out)
+}
+/// Decompresses an .xz stream, only the LZMA2 filter is supported (not the BCJ/delta filters).
+/// None if it would be bigger than `max` bytes
+pub fn unxz(data: &[u8], max: usize) -> Option<Vec<u8>>{
+    if !data.starts_with(XZ_MAGIC) || data.len() < 12{
+        return None;
+    }
+    // Every block is followed by its check, its size depends on the type
+    let check_size = match data[7] & 0xF{
+        0 => {0}
+        check => {4 << ((check - 1) / 3)}
+    };
+    let mut pos = 12;
+    let mut out = Vec::new();
+    loop{
+        // A block header size of 0 means that the index (the end of the blocks) was reached
+        let header_size = *data.get(pos)? as usize;
+        if header_size == 0{
+            // The index has the sizes of every block, it is padded to 4 bytes and followed by its CRC32
+            // and the stream footer, which ends with "YZ". Without them the file was cut short
+            let mut i = pos + 1;
+            for _ in 0..varint(data, &mut i)?{
+                varint(data, &mut i)?;
+                varint(data, &mut i)?;
+            }
+            let footer = i.next_multiple_of(4) + 4;
+            return (data.get(footer + 10..footer + 12)? == b"YZ").then_some(out);
+        }
+        let header = data.get(pos..pos + (header_size + 1)*4)?;
+        let flags = header[1];
+        if flags & 0x3 != 0{
+            return None;
+        }
+        let mut i = 2;
+        // Compressed and uncompressed sizes are optional
+        if flags & 0x40 != 0{
+            varint(header, &mut i)?;
+        }
+        if flags & 0x80 != 0{
+            varint(header, &mut i)?;
+        }
+        if varint(header, &mut i)? != XZ_FILTER_LZMA2{
+            return None;
+        }
+        pos += header.len();
+        pos += lzma2(&data[pos..], &mut out, max)?;
+        // Blocks are padded to 4 bytes
+        pos = pos.next_multiple_of(4) + check_size;
+    }
+}
+/// Numbers in xz headers use 7 bits per byte, the high bit means that more bytes follow
+fn varint(b: &[u8], i: &mut usize) -> Option<u64>{
+    let mut v = 0;
+    for shift in 0..9{
+        let byte = *b.get(*i)?;
+        *i += 1;
+        v |= ((byte & 0x7F) as u64) << (shift*7);
+        if byte & 0x80 == 0{
+            return Some(v);
+        }
+    }
+    None
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::inflate::tests::{noise, text};
+
+    // Made with Python's lzma module
+    /// An empty .xz file
+    const XZ_EMPTY: &[u8] = &[
+        0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00, 0x00, 0x04, 0xE6, 0xD6, 0xB4, 0x46, 0x00, 0x00, 0x00, 0x00, 0x1C, 0xDF, 0x44, 0x21,
+        0x1F, 0xB6, 0xF3, 0x7D, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5A,
+    ];
+    /// text(800)
+    const XZ_TEXT: &[u8] = &[
+        0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00, 0x00, 0x04, 0xE6, 0xD6, 0xB4, 0x46, 0x02, 0x00, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00,
+        0x74, 0x2F, 0xE5, 0xA3, 0xE0, 0x03, 0x1F, 0x00, 0x64, 0x5D, 0x00, 0x36, 0x1A, 0x4A, 0x1F, 0x08, 0xA0, 0x26, 0x03, 0x4D,
+        0x06, 0x9D, 0xF8, 0xB2, 0x8A, 0x22, 0x45, 0x18, 0xA9, 0x67, 0x6F, 0x7E, 0x1F, 0xC4, 0x02, 0x0D, 0xF9, 0x4B, 0x64, 0x57,
+        0x38, 0x98, 0x5E, 0x7F, 0x8D, 0x6A, 0x6C, 0xB8, 0x04, 0x0D, 0x3F, 0x28, 0xD2, 0xC9, 0x74, 0x23, 0x22, 0x4D, 0x84, 0x6E,
+        0xD8, 0x37, 0x72, 0x7B, 0xB7, 0x7A, 0x4C, 0x57, 0x49, 0x6E, 0x25, 0xA3, 0x12, 0xBD, 0xFE, 0xC3, 0xC7, 0xD4, 0x70, 0x2F,
+        0x07, 0x9C, 0xD8, 0x1A, 0x35, 0xB5, 0xE3, 0x65, 0xF0, 0xAF, 0x87, 0x99, 0x44, 0xE7, 0x75, 0x66, 0xFB, 0xC0, 0x51, 0x63,
+        0x2B, 0xD8, 0xDA, 0xE1, 0xD4, 0xC1, 0xAF, 0xEE, 0x56, 0x5F, 0x00, 0x00, 0x3D, 0xEA, 0x90, 0x63, 0xB2, 0x91, 0x04, 0x1B,
+        0x00, 0x01, 0x80, 0x01, 0xA0, 0x06, 0x00, 0x00, 0x02, 0x8C, 0x63, 0xA9, 0xB1, 0xC4, 0x67, 0xFB, 0x02, 0x00, 0x00, 0x00,
+        0x00, 0x04, 0x59, 0x5A,
+    ];
+    /// noise(100), stored in an uncompressed LZMA2 chunk
+    const XZ_NOISE: &[u8] = &[
+        0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00, 0x00, 0x04, 0xE6, 0xD6, 0xB4, 0x46, 0x02, 0x00, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00,
+        0x74, 0x2F, 0xE5, 0xA3, 0x01, 0x00, 0x63, 0xC6, 0x4F, 0x14, 0xAF, 0xD8, 0x4B, 0x9A, 0x2E, 0x62, 0x07, 0x77, 0xCA, 0x7D,
+        0x4B, 0xFA, 0xEB, 0x6D, 0xA1, 0xA0, 0xCB, 0x53, 0x28, 0x15, 0x10, 0x93, 0x8C, 0x23, 0xA5, 0xD3, 0xD9, 0x38, 0xED, 0x5A,
+        0x85, 0x30, 0x9A, 0x08, 0x2D, 0xD6, 0xDE, 0xE3, 0x4E, 0x92, 0xAB, 0xA6, 0x10, 0xC5, 0x6F, 0xE7, 0xA5, 0xA7, 0xEF, 0x55,
+        0xA7, 0x68, 0x5A, 0xB2, 0x80, 0x70, 0xC9, 0x11, 0x62, 0xD3, 0xE2, 0x4A, 0xAE, 0x9D, 0xFC, 0x44, 0x2C, 0x80, 0x1D, 0xB1,
+        0x1B, 0xED, 0xD1, 0xC7, 0x11, 0xD0, 0xF2, 0xC9, 0x23, 0xF9, 0x86, 0xA0, 0xC1, 0x3B, 0x8E, 0xFD, 0x60, 0x12, 0x87, 0xF0,
+        0x91, 0xA8, 0x64, 0x69, 0xAB, 0x8E, 0xDF, 0x00, 0x75, 0x2D, 0x1B, 0xA8, 0x94, 0x04, 0xA4, 0x58, 0x00, 0x01, 0x7C, 0x64,
+        0x90, 0x26, 0xD3, 0xE9, 0x1F, 0xB6, 0xF3, 0x7D, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5A,
+    ];
+    /// The first 1000 bytes of text(1000) 3000 times, more than one LZMA2 chunk can hold
+    const XZ_MULTI_CHUNK: &[u8] = &[
+        0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00, 0x00, 0x04, 0xE6, 0xD6, 0xB4, 0x46, 0x02, 0x00, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00,
+        0x74, 0x2F, 0xE5, 0xA3, 0xFF, 0xFF, 0xC5, 0x01, 0xDC, 0x5D, 0x00, 0x36, 0x1A, 0x4A, 0x1F, 0x08, 0xA0, 0x26, 0x03, 0x4D,
+        0x06, 0x9D, 0xF8, 0xB2, 0x8A, 0x22, 0x45, 0x18, 0xA9, 0x67, 0x6F, 0x7E, 0x1F, 0xC4, 0x02, 0x0D, 0xF9, 0x4B, 0x64, 0x57,
+        0x38, 0x98, 0x5E, 0x7F, 0x8D, 0x6A, 0x6C, 0xB8, 0x04, 0x0D, 0x3F, 0x28, 0xD2, 0xC9, 0x74, 0x23, 0x22, 0x4D, 0x84, 0x6E,
+        0xD8, 0x37, 0x72, 0x7B, 0xB7, 0x7A, 0x4C, 0x57, 0x49, 0x6E, 0x25, 0xA3, 0x12, 0xBD, 0xFE, 0xC3, 0xC7, 0xD4, 0x70, 0x2F,
+        0x07, 0x9C, 0xD8, 0x1A, 0x35, 0xB5, 0xE3, 0x65, 0xF0, 0xAF, 0x87, 0x99, 0x44, 0xE7, 0x75, 0x66, 0xFB, 0xC0, 0x51, 0x63,
+        0x2B, 0xD8, 0xDA, 0xE1, 0xD4, 0xC1, 0xB0, 0x83, 0xA3, 0x22, 0x10, 0xB4, 0x43, 0x1C, 0x4A, 0x22, 0x71, 0xC0, 0x50, 0x87,
+        0x99, 0x69, 0xA6, 0xBD, 0xDE, 0x44, 0xFE, 0xB9, 0x45, 0xC2, 0x96, 0xAA, 0x2F, 0xD8, 0x37, 0xB1, 0x46, 0x67, 0x3D, 0xC7,
+        0x67, 0x49, 0x82, 0x1D, 0x36, 0x62, 0xBB, 0x51, 0xB7, 0x07, 0x96, 0x83, 0x3E, 0xA2, 0x1D, 0x54, 0x5E, 0xFC, 0x11, 0xBC,
+        0x9E, 0xE3, 0xA6, 0x44, 0x7D, 0xF4, 0xEE, 0x10, 0x6E, 0x5B, 0x13, 0xE9, 0x93, 0xA9, 0x0C, 0xC4, 0x40, 0xFA, 0x51, 0xE8,
+        0x58, 0x8D, 0x24, 0x21, 0x46, 0x35, 0x1D, 0xFB, 0x83, 0x90, 0x23, 0x20, 0x85, 0x21, 0x97, 0xEB, 0x47, 0x8D, 0xD3, 0x42,
+        0x68, 0x43, 0x0F, 0x0D, 0x5A, 0xFF, 0xE3, 0x5D, 0x0C, 0x2A, 0xFE, 0x04, 0x5F, 0xAA, 0x0F, 0x3E, 0x4B, 0x66, 0x42, 0x90,
+        0x13, 0x0E, 0xFF, 0x10, 0x93, 0xF8, 0x71, 0x78, 0x59, 0xF8, 0x0B, 0xCD, 0xFF, 0x95, 0x28, 0x46, 0x0F, 0xA9, 0xFC, 0x7C,
+        0xDE, 0xFB, 0x9A, 0x30, 0x2E, 0x56, 0xC0, 0x8F, 0x85, 0xF3, 0x83, 0x81, 0xC0, 0x65, 0xC4, 0x25, 0x53, 0xF8, 0xF5, 0x91,
+        0x36, 0x31, 0x05, 0xA5, 0xB0, 0xEE, 0x6F, 0xC1, 0x70, 0x4D, 0x47, 0x0C, 0xD1, 0x91, 0x11, 0xAA, 0xAD, 0x60, 0x1D, 0xBA,
+        0xCE, 0xB1, 0x27, 0x18, 0x5C, 0x59, 0x86, 0xE9, 0x66, 0x52, 0x58, 0xBE, 0xE9, 0x76, 0xAC, 0x59, 0xE4, 0xE5, 0x5B, 0x05,
+        0x08, 0xF9, 0xC7, 0xDA, 0xAD, 0xFC, 0xFB, 0x52, 0x2B, 0x74, 0xCD, 0x1E, 0x5B, 0x20, 0x42, 0xF9, 0xDD, 0x53, 0x3D, 0xF8,
+        0x29, 0x64, 0x09, 0x3B, 0x80, 0xCB, 0x2A, 0x6C, 0xDF, 0xB5, 0x3B, 0xF0, 0xC4, 0xBD, 0x2E, 0x5F, 0xAA, 0x0F, 0x3E, 0x4B,
+        0x66, 0x42, 0x90, 0x13, 0x0E, 0xFF, 0x10, 0x93, 0xF8, 0x71, 0x78, 0x59, 0xF8, 0x0B, 0xCD, 0xFF, 0x95, 0x28, 0x46, 0x0F,
+        0xA9, 0xFC, 0x7C, 0xDE, 0xFB, 0x9A, 0x30, 0x2E, 0x56, 0xC0, 0x8F, 0x85, 0xF3, 0x83, 0x81, 0xC0, 0x65, 0xC4, 0x25, 0x53,
+        0xF8, 0xF5, 0x91, 0x36, 0x31, 0x05, 0xA5, 0xB0, 0xEE, 0x6F, 0xC1, 0x70, 0x4D, 0x47, 0x0C, 0xD1, 0x91, 0x11, 0xAA, 0xAD,
+        0x60, 0x1D, 0xBA, 0xCE, 0xB1, 0x27, 0x18, 0x5C, 0x59, 0x86, 0xE9, 0x66, 0x52, 0x58, 0xBE, 0xE9, 0x76, 0xAC, 0x59, 0xE4,
+        0xE5, 0x5B, 0x05, 0x08, 0xF9, 0xC7, 0xDA, 0xAD, 0xFC, 0xFB, 0x52, 0x2B, 0x74, 0xCD, 0x1E, 0x5B, 0x20, 0x42, 0xF9, 0xDD,
+        0x53, 0x3D, 0xF8, 0x29, 0x64, 0x09, 0x3B, 0x80, 0xCB, 0x2A, 0x6C, 0xDF, 0xB5, 0x3B, 0xF0, 0xC4, 0xBD, 0x2E, 0x5F, 0xAA,
+        0x0F, 0x3E, 0x4B, 0x66, 0x42, 0x90, 0x13, 0x0E, 0xFF, 0x10, 0x93, 0xF8, 0x71, 0x78, 0x59, 0xF8, 0x0B, 0xCD, 0xFF, 0x95,
+        0x28, 0x46, 0x0F, 0xA3, 0xAD, 0xAC, 0x79, 0x8D, 0xC6, 0xF9, 0x00, 0x85, 0x00, 0xEC, 0x73, 0x53, 0xA7, 0xFD, 0xBE, 0xAE,
+        0x7C, 0x31, 0x1A, 0x9F, 0xB7, 0x8D, 0x31, 0x6E, 0x70, 0x9E, 0xA7, 0x23, 0x5F, 0xEC, 0x28, 0xCB, 0x85, 0xD1, 0x95, 0x98,
+        0x8A, 0x7E, 0x2A, 0x91, 0xF2, 0x27, 0x75, 0xF7, 0x19, 0xC0, 0x06, 0x98, 0x4D, 0x98, 0xFD, 0xD8, 0xAF, 0xD5, 0x90, 0x0F,
+        0xC4, 0x25, 0x53, 0xF8, 0xF5, 0x91, 0x36, 0x31, 0x05, 0xA5, 0xB0, 0xEE, 0x6F, 0xC1, 0x70, 0x4D, 0x47, 0x0C, 0xD1, 0x91,
+        0x11, 0xAA, 0xAD, 0x60, 0x1D, 0xBA, 0xCE, 0xB1, 0x27, 0x18, 0x5C, 0x59, 0x86, 0xE9, 0x66, 0x52, 0x58, 0xBE, 0xE9, 0x76,
+        0xAC, 0x59, 0xE4, 0xE5, 0x5B, 0x05, 0x08, 0xF9, 0xC7, 0xDA, 0xAD, 0xFC, 0xFB, 0x52, 0x2B, 0x74, 0xCD, 0x1E, 0x5B, 0x20,
+        0x42, 0xF9, 0xDD, 0x53, 0x3D, 0xF8, 0x29, 0x64, 0x09, 0x3B, 0x80, 0xCB, 0x2A, 0x6C, 0xDF, 0xB5, 0x3B, 0xF0, 0xC4, 0xBD,
+        0x0F, 0xC2, 0x25, 0x6A, 0x49, 0x00, 0x00, 0x00, 0xB5, 0x70, 0x6D, 0xCE, 0xA7, 0x00, 0x17, 0x68, 0x00, 0x01, 0x83, 0x05,
+        0xC0, 0x8D, 0xB7, 0x01, 0xE7, 0x53, 0xB2, 0xD0, 0xB1, 0xC4, 0x67, 0xFB, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5A,
+    ];
+    /// An empty .lzma file (unknown size, ends with an end marker)
+    const LZMA_EMPTY: &[u8] = &[
+        0x5D, 0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x83, 0xFF, 0xFB, 0xFF, 0xFF, 0xC0,
+        0x00, 0x00, 0x00,
+    ];
+    /// text(800) in a .lzma file
+    const LZMA_TEXT: &[u8] = &[
+        0x5D, 0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x36, 0x1A, 0x4A, 0x1F, 0x08, 0xA0,
+        0x26, 0x03, 0x4D, 0x06, 0x9D, 0xF8, 0xB2, 0x8A, 0x22, 0x45, 0x18, 0xA9, 0x67, 0x6F, 0x7E, 0x1F, 0xC4, 0x02, 0x0D, 0xF9,
+        0x4B, 0x64, 0x57, 0x38, 0x98, 0x5E, 0x7F, 0x8D, 0x6A, 0x6C, 0xB8, 0x04, 0x0D, 0x3F, 0x28, 0xD2, 0xC9, 0x74, 0x23, 0x22,
+        0x4D, 0x84, 0x6E, 0xD8, 0x37, 0x72, 0x7B, 0xB7, 0x7A, 0x4C, 0x57, 0x49, 0x6E, 0x25, 0xA3, 0x12, 0xBD, 0xFE, 0xC3, 0xC7,
+        0xD4, 0x70, 0x2F, 0x07, 0x9C, 0xD8, 0x1A, 0x35, 0xB5, 0xE3, 0x65, 0xF0, 0xAF, 0x87, 0x99, 0x44, 0xE7, 0x75, 0x66, 0xFB,
+        0xC0, 0x51, 0x63, 0x2B, 0xD8, 0xDA, 0xE1, 0xD4, 0xC1, 0xAF, 0xFD, 0x96, 0x4E, 0x0F, 0xFF, 0xF9, 0x16, 0x5B, 0xE0,
+    ];
+    /// text(800) in a raw LZMA2 stream
+    const LZMA2_TEXT: &[u8] = &[
+        0xE0, 0x03, 0x1F, 0x00, 0x64, 0x5D, 0x00, 0x36, 0x1A, 0x4A, 0x1F, 0x08, 0xA0, 0x26, 0x03, 0x4D, 0x06, 0x9D, 0xF8, 0xB2,
+        0x8A, 0x22, 0x45, 0x18, 0xA9, 0x67, 0x6F, 0x7E, 0x1F, 0xC4, 0x02, 0x0D, 0xF9, 0x4B, 0x64, 0x57, 0x38, 0x98, 0x5E, 0x7F,
+        0x8D, 0x6A, 0x6C, 0xB8, 0x04, 0x0D, 0x3F, 0x28, 0xD2, 0xC9, 0x74, 0x23, 0x22, 0x4D, 0x84, 0x6E, 0xD8, 0x37, 0x72, 0x7B,
+        0xB7, 0x7A, 0x4C, 0x57, 0x49, 0x6E, 0x25, 0xA3, 0x12, 0xBD, 0xFE, 0xC3, 0xC7, 0xD4, 0x70, 0x2F, 0x07, 0x9C, 0xD8, 0x1A,
+        0x35, 0xB5, 0xE3, 0x65, 0xF0, 0xAF, 0x87, 0x99, 0x44, 0xE7, 0x75, 0x66, 0xFB, 0xC0, 0x51, 0x63, 0x2B, 0xD8, 0xDA, 0xE1,
+        0xD4, 0xC1, 0xAF, 0xEE, 0x56, 0x5F, 0x00, 0x00,
+    ];
+
+    fn multi_chunk_expected() -> Vec<u8>{
+        text(1000).repeat(3000)
+    }
+
+    #[test]
+    fn empty(){
+        assert_eq!(unxz(XZ_EMPTY, usize::MAX), Some(Vec::new()));
+        assert_eq!(unlzma(LZMA_EMPTY, usize::MAX), Some(Vec::new()));
+        assert_eq!(unxz(&[], usize::MAX), None);
+        assert_eq!(unlzma(&[], usize::MAX), None);
+    }
+    #[test]
+    fn xz(){
+        assert_eq!(unxz(XZ_TEXT, usize::MAX), Some(text(800)));
+        assert_eq!(unxz(XZ_NOISE, usize::MAX), Some(noise(100)));
+        assert_eq!(unxz(XZ_MULTI_CHUNK, usize::MAX), Some(multi_chunk_expected()));
+    }
+    #[test]
+    fn lzma_alone(){
+        assert_eq!(unlzma(LZMA_TEXT, usize::MAX), Some(text(800)));
+    }
+    #[test]
+    fn raw_lzma2(){
+        let mut out = Vec::new();
+        assert_eq!(lzma2(LZMA2_TEXT, &mut out, usize::MAX), Some(LZMA2_TEXT.len()));
+        assert_eq!(out, text(800));
+    }
+    #[test]
+    fn max(){
+        assert_eq!(unxz(XZ_TEXT, 799), None);
+        assert_eq!(unxz(XZ_TEXT, 800), Some(text(800)));
+        assert_eq!(unxz(XZ_NOISE, 99), None);
+        assert_eq!(unxz(XZ_NOISE, 100), Some(noise(100)));
+        assert_eq!(unxz(XZ_MULTI_CHUNK, 2_999_999), None);
+        assert_eq!(unlzma(LZMA_TEXT, 799), None);
+        assert_eq!(unlzma(LZMA_TEXT, 800), Some(text(800)));
+        assert_eq!(lzma2(LZMA2_TEXT, &mut Vec::new(), 799), None);
+    }
+    #[test]
+    fn truncated(){
+        for data in [XZ_EMPTY, XZ_TEXT, XZ_NOISE]{
+            for len in 0..data.len(){
+                assert_eq!(unxz(&data[..len], usize::MAX), None, "cut at {len}");
+            }
+        }
+        // Every cut decompresses megabytes, a few of them are enough
+        for len in (0..XZ_MULTI_CHUNK.len()).step_by(61){
+            assert_eq!(unxz(&XZ_MULTI_CHUNK[..len], usize::MAX), None, "cut at {len}");
+        }
+        for data in [LZMA_EMPTY, LZMA_TEXT]{
+            for len in 0..data.len(){
+                assert_eq!(unlzma(&data[..len], usize::MAX), None, "cut at {len}");
+            }
+        }
+        for len in 0..LZMA2_TEXT.len(){
+            assert_eq!(lzma2(&LZMA2_TEXT[..len], &mut Vec::new(), usize::MAX), None, "cut at {len}");
+        }
+    }
+    #[test]
+    fn corrupt(){
+        let mut data = XZ_TEXT.to_vec();
+        data[0] ^= 1;
+        assert_eq!(unxz(&data, usize::MAX), None);
+        // Invalid properties (lc + lp + pb past what the format allows)
+        let mut data = LZMA_TEXT.to_vec();
+        data[0] = 225;
+        assert_eq!(unlzma(&data, usize::MAX), None);
+        // Any flipped bit either fails or gives something, it never panics
+        for bit in 0..XZ_TEXT.len()*8{
+            let mut data = XZ_TEXT.to_vec();
+            data[bit / 8] ^= 1 << (bit % 8);
+            unxz(&data, 1 << 20);
+        }
+        for bit in 0..LZMA_TEXT.len()*8{
+            let mut data = LZMA_TEXT.to_vec();
+            data[bit / 8] ^= 1 << (bit % 8);
+            unlzma(&data, 1 << 20);
+        }
+    }
+}
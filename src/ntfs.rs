@@ -0,0 +1,523 @@
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at, u64_at};
+
+// The following code decodes NTFS by reading the Master File Table directly
+// https://flatcap.github.io/linux-ntfs/ntfs/index.html
+
+const ROOT_RECORD: u64 = 5;
+/// Records below this number are reserved for the filesystem metadata ($MFT, $Bitmap, $Extend...)
+const FIRST_USER_RECORD: u64 = 16;
+const ATTR_STANDARD_INFORMATION: u32 = 0x10;
+const ATTR_ATTRIBUTE_LIST: u32 = 0x20;
+const ATTR_FILE_NAME: u32 = 0x30;
+const ATTR_DATA: u32 = 0x80;
+const ATTR_INDEX_ALLOCATION: u32 = 0xA0;
+const ATTR_END: u32 = 0xFFFFFFFF;
+/// NTFS only stores 8.3 names in the DOS namespace, they are duplicates of the Win32 name
+const NAMESPACE_DOS: u8 = 2;
+/// Records are read in batches of this many
+const RECORDS_PER_READ: u64 = 1024;
+
+/// FILETIME is the number of 100ns intervals since 1601-01-01
+pub fn filetime_to_epoch(t: u64) -> i64{
+    (t / 10_000_000) as i64 - 11_644_473_600
+}
+/// (starting cluster, length in clusters) of each run of an attribute, sparse runs have no cluster
+type Runlist = Vec<(Option<u64>, u64)>;
+fn decode_runlist(b: &[u8]) -> Runlist{
+    let mut runs = Vec::new();
+    let mut i = 0;
+    let mut lcn: i64 = 0;
+    while i < b.len() && b[i] != 0{
+        let length_size = (b[i] & 0x0F) as usize;
+        let offset_size = (b[i] >> 4) as usize;
+        i += 1;
+        if length_size > 8 || offset_size > 8 || i + length_size + offset_size > b.len(){
+            break;
+        }
+        let mut length = 0u64;
+        for k in 0..length_size{
+            length |= (b[i+k] as u64) << (8*k);
+        }
+        i += length_size;
+        if offset_size == 0{
+            runs.push((None, length));
+            continue;
+        }
+        // The offset is signed and relative to the previous run
+        let mut offset = 0i64;
+        for k in 0..offset_size{
+            offset |= (b[i+k] as i64) << (8*k);
+        }
+        if offset_size < 8 && b[i+offset_size-1] & 0x80 != 0{
+            offset -= 1i64 << (8*offset_size);
+        }
+        i += offset_size;
+        lcn = lcn.wrapping_add(offset);
+        runs.push((Some(lcn as u64), length));
+    }
+    runs
+}
+/// Returns the first attribute of a given type in a record
+fn find_attribute(record: &[u8], attr_type: u32) -> Option<&[u8]>{
+    let mut i = u16_at(record, 0x14) as usize;
+    while i + 16 <= record.len() && u32_at(record, i) != ATTR_END{
+        let attr_len = u32_at(record, i+4) as usize;
+        if attr_len < 16 || i + attr_len > record.len(){
+            break;
+        }
+        if u32_at(record, i) == attr_type{
+            return Some(&record[i..i+attr_len]);
+        }
+        i += attr_len;
+    }
+    None
+}
+/// Returns the starting VCN and the runlist of the unnamed non resident $DATA attribute of a record
+fn data_runlist(record: &[u8]) -> Option<(u64, Runlist)>{
+    let attr = find_attribute(record, ATTR_DATA)?;
+    if attr[8] == 0 || attr[9] != 0 || attr.len() < 0x40{
+        return None;
+    }
+    let runlist = u16_at(attr, 0x20) as usize;
+    Some((u64_at(attr, 0x10), decode_runlist(attr.get(runlist..)?)))
+}
+/// Everything we keep from a MFT record (and its extension records)
+#[derive(Debug, Default, Clone)]
+struct NtfsRecord{
+    /// (parent record, name)
+    names: Vec<(u64, String)>,
+    /// Set when the only name is the DOS one
+    dos_name: Option<(u64, String)>,
+    is_dir: bool,
+    size: u64,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    /// The file attributes of $STANDARD_INFORMATION
+    attributes: u32,
+    has_standard_information: bool,
+}
+struct NtfsDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    cluster_size: u64,
+    record_size: u64,
+    /// Runs of the $MFT itself
+    mft_runs: Runlist,
+    records: Vec<NtfsRecord>,
+    files: Vec<NtfsFile>,
+    ignored_dirs: Vec<String>
+}
+impl NtfsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut buffer = vec![0u8; 512];
+        file.read_at(&mut buffer, 0).map_err(Error::io(0))?;
+        if &buffer[3..11] != b"NTFS    "{ //OEM ID
+            return Err(Error::WrongFilesystem("NTFS"));
+        }
+        if [buffer[510], buffer[511]] != [0x55, 0xAA]{ //BootSignature flag
+            return Err(Error::corrupt(510, "invalid boot signature"));
+        }
+
+        let bytes_per_sector = u16_at(&buffer, 0x0B) as u64;
+        // Values above 0x80 mean 2^(256-x) sectors per cluster
+        let sectors_per_cluster = if buffer[0x0D] > 0x80{1u64.checked_shl(256 - buffer[0x0D] as u32).unwrap_or(0)}else{buffer[0x0D] as u64};
+        let cluster_size = bytes_per_sector*sectors_per_cluster;
+        let mft_cluster = u64_at(&buffer, 0x30);
+        // Positive values are clusters per record, negative values are 2^(-x) bytes
+        let clusters_per_record = buffer[0x40] as i8;
+        let record_size = if clusters_per_record > 0{
+            clusters_per_record as u64*cluster_size
+        }else{
+            1u64.checked_shl(-(clusters_per_record as i32) as u32).unwrap_or(0)
+        };
+        if cluster_size == 0 || !(256..=65536).contains(&record_size){
+            return Err(Error::corrupt(0x0B, "invalid cluster or MFT record size"));
+        }
+
+        let mut drive = NtfsDrive {file, directories: Vec::new(), mounted_at, cluster_size, record_size,
+            mft_runs: Vec::new(), records: Vec::new(), files: Vec::new(), ignored_dirs};
+        // The first record describes the MFT itself, its $DATA attribute tells us where the rest of the MFT is
+        let mft_offset = mft_cluster.saturating_mul(cluster_size);
+        let mut first = drive.read_bytes(mft_offset, record_size)?;
+        if !drive.apply_fixups(&mut first){
+            return Err(Error::corrupt(mft_offset, "the first record of the $MFT is corrupted"));
+        }
+        let volume_clusters = u64_at(&buffer, 0x28) / sectors_per_cluster;
+        if let Some((0, runs)) = data_runlist(&first){
+            drive.mft_runs = runs;
+        }
+        // A fragmented $MFT doesn't fit in one record, the other extents of its $DATA
+        // are in extension records listed by the $ATTRIBUTE_LIST
+        if let Some(list) = find_attribute(&first, ATTR_ATTRIBUTE_LIST){
+            let list = drive.attribute_value(list, volume_clusters, mft_offset)?;
+            drive.read_mft_extents(&list, mft_offset)?;
+        }
+        if drive.mft_runs.iter().try_fold(0u64, |sum, &(_, clusters)| sum.checked_add(clusters)).is_none_or(|sum| sum > volume_clusters){
+            return Err(Error::corrupt(mft_offset, "the $MFT is larger than the volume"));
+        }
+        Ok(drive)
+    }
+    /// Reads the value of a resident or non resident attribute
+    fn attribute_value(self: &Self, attr: &[u8], volume_clusters: u64, offset: u64) -> Result<Vec<u8>, Error>{
+        if attr.len() < if attr[8] == 0{0x18}else{0x40}{
+            return Err(Error::corrupt(offset, "an attribute is truncated"));
+        }
+        if attr[8] == 0{
+            let offset = u16_at(attr, 0x14) as usize;
+            let len = u32_at(attr, 0x10) as usize;
+            return Ok(attr.get(offset..offset+len).unwrap_or_default().to_vec());
+        }
+        let runlist = u16_at(attr, 0x20) as usize;
+        let runs = decode_runlist(attr.get(runlist..).unwrap_or_default());
+        let size = u64_at(attr, 0x30);
+        let clusters: u64 = runs.iter().map(|&(_, clusters)| clusters).sum();
+        if clusters > volume_clusters || size > clusters*self.cluster_size{
+            return Err(Error::corrupt(offset, "an attribute is larger than the volume"));
+        }
+        let mut value = Vec::new();
+        for (lcn, clusters) in runs{
+            match lcn{
+                Some(lcn) => {value.extend(self.read_bytes(lcn.saturating_mul(self.cluster_size), clusters*self.cluster_size)?);}
+                None => {value.resize(value.len() + (clusters*self.cluster_size) as usize, 0);}
+            }
+        }
+        value.truncate(size as usize);
+        Ok(value)
+    }
+    /// Adds the extents of the $MFT stored in extension records to `mft_runs`
+    fn read_mft_extents(self: &mut Self, list: &[u8], mft_offset: u64) -> Result<(), Error>{
+        // (starting VCN, record)
+        let mut extents = Vec::new();
+        let mut i = 0;
+        while i + 0x1A <= list.len(){
+            let entry_len = u16_at(list, i+4) as usize;
+            if entry_len < 0x1A{
+                break;
+            }
+            let record = u64_at(list, i+0x10) & 0x0000FFFFFFFFFFFF;
+            if u32_at(list, i) == ATTR_DATA && list[i+6] == 0 && record != 0{
+                extents.push((u64_at(list, i+8), record));
+            }
+            i += entry_len;
+        }
+        extents.sort();
+        for (vcn, record) in extents{
+            let mapped: u64 = self.mft_runs.iter().map(|&(_, clusters)| clusters).sum();
+            // Extension records of the $MFT have to be in the part of it we already know about
+            let Some(offset) = self.record_offset(record) else{
+                return Err(Error::corrupt(mft_offset, "an extension record of the $MFT is outside of it"));
+            };
+            let mut extension = self.read_bytes(offset, self.record_size)?;
+            if !self.apply_fixups(&mut extension){
+                return Err(Error::corrupt(offset, "an extension record of the $MFT is corrupted"));
+            }
+            match data_runlist(&extension){
+                Some((start, runs)) if start == vcn && vcn == mapped => {self.mft_runs.extend(runs);}
+                _ => {return Err(Error::corrupt(offset, "the $MFT extents are not contiguous"));}
+            }
+        }
+        Ok(())
+    }
+    /// Byte offset of a record on the device, if it is in the known runs of the $MFT
+    fn record_offset(self: &Self, record: u64) -> Option<u64>{
+        let mut vcn_offset = record.checked_mul(self.record_size)?;
+        for &(lcn, clusters) in &self.mft_runs{
+            let len = clusters*self.cluster_size;
+            if vcn_offset + self.record_size <= len{
+                return lcn.map(|lcn| lcn*self.cluster_size + vcn_offset);
+            }
+            if vcn_offset < len{
+                return None; // Split between two runs
+            }
+            vcn_offset -= len;
+        }
+        None
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    /// The last two bytes of every 512 byte stride are replaced with an update sequence number
+    /// (to detect torn writes), the original bytes are stored in the update sequence array
+    fn apply_fixups(self: &Self, record: &mut [u8]) -> bool{
+        if &record[0..4] != b"FILE"{
+            return false;
+        }
+        let usa_offset = u16_at(record, 0x04) as usize;
+        let usa_count = u16_at(record, 0x06) as usize;
+        if usa_count == 0 || usa_offset + usa_count*2 > record.len(){
+            return false;
+        }
+        let usn = [record[usa_offset], record[usa_offset+1]];
+        for s in 1..usa_count{
+            let end = s*512;
+            if end > record.len(){
+                break;
+            }
+            if record[end-2..end] != usn{
+                return false;
+            }
+            record[end-2] = record[usa_offset + s*2];
+            record[end-1] = record[usa_offset + s*2 + 1];
+        }
+        true
+    }
+    fn read_mft(mut self: Self) -> Result<Self, Error>{
+        let mut record_n = 0u64;
+        for (lcn, clusters) in self.mft_runs.clone(){
+            let count = clusters*self.cluster_size/self.record_size;
+            let Some(lcn) = lcn else{
+                record_n += count;
+                continue;
+            };
+            let mut done = 0;
+            while done < count{
+                let batch = RECORDS_PER_READ.min(count - done);
+                let bytes = self.read_bytes(lcn*self.cluster_size + done*self.record_size, batch*self.record_size)?;
+                for r in 0..batch{
+                    let start = (r*self.record_size) as usize;
+                    let mut record = bytes[start..start+self.record_size as usize].to_vec();
+                    self.parse_record(record_n + done + r, &mut record);
+                }
+                done += batch;
+            }
+            record_n += count;
+        }
+        Ok(self)
+    }
+    fn parse_record(self: &mut Self, n: u64, record: &mut [u8]){
+        if !self.apply_fixups(record){
+            return;
+        }
+        let flags = u16_at(record, 0x16);
+        if flags & 0x01 == 0{
+            return; // Not in use
+        }
+        // Extension records hold attributes that didn't fit in their base record
+        let base = u64_at(record, 0x20) & 0x0000FFFFFFFFFFFF;
+        let owner = if base != 0{base}else{n} as usize;
+        // A base record past the end of the MFT is garbage
+        let records: u64 = self.mft_runs.iter().map(|&(_, clusters)| clusters*self.cluster_size/self.record_size).sum();
+        if owner as u64 >= records{
+            return;
+        }
+        if self.records.len() <= owner{
+            self.records.resize(owner+1, NtfsRecord::default());
+        }
+        if base == 0{
+            self.records[owner].is_dir = flags & 0x02 != 0;
+        }
+        let mut i = u16_at(record, 0x14) as usize;
+        while i + 16 <= record.len(){
+            let attr_type = u32_at(record, i);
+            if attr_type == ATTR_END{
+                break;
+            }
+            let attr_len = u32_at(record, i+4) as usize;
+            if attr_len < 16 || i + attr_len > record.len(){
+                break;
+            }
+            let attr = &record[i..i+attr_len];
+            let non_resident = attr[8] != 0;
+            let attr_name_len = attr[9];
+            let value = if non_resident{
+                &attr[0..0]
+            }else{
+                let offset = u16_at(attr, 0x14) as usize;
+                let len = u32_at(attr, 0x10) as usize;
+                if offset + len > attr.len(){&attr[0..0]}else{&attr[offset..offset+len]}
+            };
+            let entry = &mut self.records[owner];
+            match attr_type{
+                ATTR_STANDARD_INFORMATION => {
+                    if value.len() >= 16{
+                        entry.create_timestamp = filetime_to_epoch(u64_at(value, 0x00));
+                        entry.last_modified_timestamp = filetime_to_epoch(u64_at(value, 0x08));
+                        entry.has_standard_information = true;
+                    }
+                    if value.len() >= 0x24{
+                        entry.attributes = u32_at(value, 0x20);
+                    }
+                }
+                ATTR_FILE_NAME if value.len() >= 0x42 => {
+                    let parent = u64_at(value, 0x00) & 0x0000FFFFFFFFFFFF;
+                    let name_len = value[0x40] as usize;
+                    let namespace = value[0x41];
+                    if 0x42 + name_len*2 <= value.len(){
+                        let name: Vec<u16> = (0..name_len).map(|k| u16_at(value, 0x42 + k*2)).collect();
+                        let name = String::from_utf16_lossy(&name);
+                        if !entry.has_standard_information{
+                            entry.create_timestamp = filetime_to_epoch(u64_at(value, 0x08));
+                            entry.last_modified_timestamp = filetime_to_epoch(u64_at(value, 0x10));
+                        }
+                        if namespace == NAMESPACE_DOS{
+                            entry.dos_name = Some((parent, name));
+                        }else if !entry.names.iter().any(|(p, n)| *p == parent && *n == name){
+                            entry.names.push((parent, name));
+                        }
+                    }
+                }
+                ATTR_DATA | ATTR_INDEX_ALLOCATION => {
+                    // Only the unnamed stream counts (named ones are alternate data streams)
+                    let is_main_stream = attr_type == ATTR_INDEX_ALLOCATION || attr_name_len == 0;
+                    if is_main_stream{
+                        if non_resident{
+                            // Only the first extent of the attribute has the real size
+                            if u64_at(attr, 0x10) == 0{
+                                entry.size = u64_at(attr, 0x30);
+                            }
+                        }else{
+                            entry.size = value.len() as u64;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            i += attr_len;
+        }
+    }
+    /// Returns the index inside of `directories` for a directory record, None if it isn't reachable from the root
+    fn directory_index(self: &mut Self, record: u64, dir_idx: &mut [Option<Option<u32>>]) -> Option<u32>{
+        // Walk up until a directory with a known index (or the root) is found
+        let mut chain = Vec::new();
+        let mut current = record;
+        loop{
+            if let Some(known) = dir_idx[current as usize]{
+                let mut idx = known;
+                for r in chain.into_iter().rev(){
+                    idx = match idx{
+                        Some(parent_idx) => {self.add_directory(r, parent_idx)}
+                        None => {None}
+                    };
+                    dir_idx[r as usize] = Some(idx);
+                }
+                return idx;
+            }
+            // Loops and system directories are not reachable
+            if chain.contains(&current) || current < FIRST_USER_RECORD{
+                for r in chain{
+                    dir_idx[r as usize] = Some(None);
+                }
+                dir_idx[current as usize] = Some(None);
+                return None;
+            }
+            chain.push(current);
+            match self.records[current as usize].names.first(){
+                Some((parent, _)) if (*parent as usize) < self.records.len() => {current = *parent;}
+                _ => {
+                    for r in chain{
+                        dir_idx[r as usize] = Some(None);
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+    fn add_directory(self: &mut Self, record: u64, parent_idx: u32) -> Option<u32>{
+        let name = self.records[record as usize].names[0].1.clone();
+        let full_name = self.directories[parent_idx as usize].name.clone() + &name + "/";
+        for i in self.ignored_dirs.clone(){
+            if full_name.starts_with(&i){
+                return None;
+            }
+        }
+        self.directories.push(Directory{name: full_name});
+        Some(self.directories.len() as u32 - 1)
+    }
+    fn index_from_root(mut self: Self) -> Self{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        for r in 0..self.records.len(){
+            if self.records[r].names.is_empty() && let Some(dos_name) = self.records[r].dos_name.take(){
+                self.records[r].names.push(dos_name);
+            }
+        }
+        if self.records.len() <= ROOT_RECORD as usize{
+            return self;
+        }
+        let mut dir_idx: Vec<Option<Option<u32>>> = vec![None; self.records.len()];
+        dir_idx[ROOT_RECORD as usize] = Some(Some(0));
+        for r in FIRST_USER_RECORD as usize..self.records.len(){
+            for k in 0..self.records[r].names.len(){
+                let (parent, name) = self.records[r].names[k].clone();
+                if parent as usize >= self.records.len() || !self.records[parent as usize].is_dir{
+                    continue;
+                }
+                let Some(parent) = self.directory_index(parent, &mut dir_idx) else{
+                    continue;
+                };
+                let record = &self.records[r];
+                let mut full_name = self.directories[parent as usize].name.clone() + &name;
+                if record.is_dir{
+                    full_name += "/";
+                }
+                let mut to_ignore = false;
+                for i in self.ignored_dirs.clone(){
+                    if full_name.starts_with(&i){
+                        to_ignore = true;
+                    }
+                }
+                if to_ignore{continue;}
+                self.files.push(NtfsFile{
+                    name,
+                    parent,
+                    size: record.size,
+                    is_dir: record.is_dir,
+                    create_timestamp: record.create_timestamp,
+                    last_modified_timestamp: record.last_modified_timestamp,
+                    attributes: record.attributes,
+                });
+            }
+        }
+        self
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct NtfsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    attributes: u32,
+}
+fn from_ntfs_files_to_files(f: &NtfsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        // Directories are flagged in the record header, not in their attributes
+        attributes: Attributes(f.attributes | Attributes::only_directory(f.is_dir).0),
+    }
+}
+
+pub struct NtfsIndexer;
+impl FilesystemIndexer for NtfsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Ntfs"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[3..11] == b"NTFS    "
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = NtfsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.read_mft()?.index_from_root();
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_ntfs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
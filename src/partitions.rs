@@ -0,0 +1,297 @@
+use std::io;
+use crate::blockdev::{self, BlockDevice};
+use crate::{u32_at, u64_at};
+
+// The following code reads MBR and GPT partition tables so that whole disks and disk images
+// can be indexed one partition at a time.
+// A partition is referred to by appending "#p<number>" to the path of the disk (like /dev/sda#p2),
+// numbers follow Linux: MBR primary partitions are 1-4, logical partitions start from 5
+
+const SECTOR_SIZE: u64 = 512;
+/// Partition types of MBR extended partitions (containers of logical partitions)
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// Protective MBR partition of a GPT disk
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+
+#[derive(Debug, Default, Clone)]
+pub struct Partition{
+    pub number: u32,
+    /// Where it starts on the disk in bytes
+    pub offset: u64,
+    pub size: u64,
+}
+/// A disk (or one of its partitions), offsets passed to read_at are relative to the start of the partition
+#[derive(Debug)]
+pub struct PartitionFile{
+    file: BlockDevice,
+    offset: u64,
+    /// None for a whole disk
+    size: Option<u64>,
+}
+impl PartitionFile{
+    pub fn read_at(self: &Self, buf: &mut [u8], offset: u64) -> io::Result<usize>{
+        let len = match self.size{
+            Some(size) => {buf.len().min(size.saturating_sub(offset) as usize)}
+            None => {buf.len()}
+        };
+        self.file.read_at(&mut buf[..len], self.offset + offset)
+    }
+}
+/// Splits "/dev/sda#p2" into ("/dev/sda", Some(2))
+pub fn split_partition(drive: &str) -> (&str, Option<u32>){
+    if let Some(i) = drive.rfind("#p") && let Ok(number) = drive[i+2..].parse(){
+        return (&drive[..i], Some(number));
+    }
+    (drive, None)
+}
+/// Opens a drive, if it ends with "#p<number>" only that partition of the disk is visible
+pub fn open(drive: &str) -> io::Result<PartitionFile>{
+    let (path, number) = split_partition(drive);
+    let file = blockdev::open(path)?;
+    let Some(number) = number else{
+        return Ok(PartitionFile{file, offset: 0, size: None});
+    };
+    match read_partition_table(&file).into_iter().find(|p| p.number == number){
+        Some(p) => {Ok(PartitionFile{file, offset: p.offset, size: Some(p.size)})}
+        None => {Err(io::Error::new(io::ErrorKind::NotFound, format!("{path} has no partition {number}")))}
+    }
+}
+fn read_sector(file: &BlockDevice, lba: u64) -> Option<Vec<u8>>{
+    let mut b = vec![0u8; SECTOR_SIZE as usize];
+    match file.read_at(&mut b, lba*SECTOR_SIZE){
+        Ok(n) if n == b.len() => {Some(b)}
+        _ => {None}
+    }
+}
+/// Every partition of a disk, empty if the disk doesn't have a (valid) MBR or GPT
+pub fn read_partition_table(file: &BlockDevice) -> Vec<Partition>{
+    let Some(mbr) = read_sector(file, 0) else{
+        return Vec::new();
+    };
+    if mbr[510] != 0x55 || mbr[511] != 0xAA{
+        return Vec::new();
+    }
+    let entries: Vec<&[u8]> = (0..4).map(|i| &mbr[446 + i*16..462 + i*16]).collect();
+    // Boot sectors of filesystems (FAT, NTFS...) also end with 55AA, in a real MBR every status byte is 0x00 or 0x80
+    if entries.iter().any(|e| e[0] != 0x00 && e[0] != 0x80){
+        return Vec::new();
+    }
+    if entries.iter().any(|e| e[4] == MBR_GPT_PROTECTIVE){
+        return read_gpt(file);
+    }
+    let mut partitions = Vec::new();
+    for (i, e) in entries.iter().enumerate(){
+        let start = u32_at(e, 8) as u64;
+        let sectors = u32_at(e, 12) as u64;
+        if e[4] == 0 || sectors == 0{
+            continue;
+        }
+        if MBR_EXTENDED.contains(&e[4]){
+            read_logical_partitions(file, start, &mut partitions);
+        }else{
+            partitions.push(Partition{number: i as u32 + 1, offset: start*SECTOR_SIZE, size: sectors*SECTOR_SIZE});
+        }
+    }
+    partitions
+}
+/// Logical partitions are a linked list of EBRs, every EBR starts relative to the extended partition
+fn read_logical_partitions(file: &BlockDevice, extended_start: u64, partitions: &mut Vec<Partition>){
+    let mut ebr_lba = extended_start;
+    let mut number = 5;
+    // A broken chain could point back to itself
+    for _ in 0..128{
+        let Some(ebr) = read_sector(file, ebr_lba) else{
+            return;
+        };
+        if ebr[510] != 0x55 || ebr[511] != 0xAA{
+            return;
+        }
+        let start = u32_at(&ebr, 446 + 8) as u64;
+        let sectors = u32_at(&ebr, 446 + 12) as u64;
+        if ebr[446 + 4] != 0 && sectors != 0{
+            partitions.push(Partition{number, offset: (ebr_lba + start)*SECTOR_SIZE, size: sectors*SECTOR_SIZE});
+            number += 1;
+        }
+        let next = u32_at(&ebr, 462 + 8) as u64;
+        if next == 0{
+            return;
+        }
+        ebr_lba = extended_start + next;
+    }
+}
+fn read_gpt(file: &BlockDevice) -> Vec<Partition>{
+    let mut partitions = Vec::new();
+    // Disks with 4K sectors have the header at 4096 instead of 512
+    for sector_size in [512, 4096]{
+        let mut header = vec![0u8; 512];
+        if file.read_at(&mut header, sector_size).is_err() || &header[0..8] != b"EFI PART"{
+            continue;
+        }
+        let entries_lba = u64_at(&header, 72);
+        let count = u32_at(&header, 80) as u64;
+        let entry_size = u32_at(&header, 84) as u64;
+        if !(128..=4096).contains(&entry_size) || !entry_size.is_multiple_of(128) || count > 1024{
+            break;
+        }
+        let fits = entries_lba.checked_mul(sector_size).and_then(|start| start.checked_add(count*entry_size))
+            .is_some_and(|end| file.size().is_ok_and(|size| end <= size));
+        if !fits{
+            break;
+        }
+        let mut entries = vec![0u8; (count*entry_size) as usize];
+        if file.read_at(&mut entries, entries_lba*sector_size).is_err(){
+            break;
+        }
+        for (i, e) in entries.chunks_exact(entry_size as usize).enumerate(){
+            // Unused entries have a zero type GUID
+            if e[0..16].iter().all(|&b| b == 0){
+                continue;
+            }
+            let first = u64_at(e, 32);
+            let last = u64_at(e, 40);
+            if last < first || last.checked_add(1).and_then(|end| end.checked_mul(sector_size)).is_none(){
+                continue;
+            }
+            partitions.push(Partition{number: i as u32 + 1, offset: first*sector_size, size: (last - first + 1)*sector_size});
+        }
+        break;
+    }
+    partitions
+}
+#[cfg(test)]
+pub(crate) mod tests{
+    use super::*;
+    use std::fs;
+
+    /// Opens `bytes` as a whole disk. They are written to a temporary file that is removed once it is open
+    pub(crate) fn disk(name: &str, bytes: &[u8]) -> PartitionFile{
+        let path = std::env::temp_dir().join(format!("anything-test-{}-{name}", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let disk = open(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        disk
+    }
+    /// A disk of `sectors` zeroed sectors
+    fn blank(sectors: usize) -> Vec<u8>{
+        vec![0u8; sectors*SECTOR_SIZE as usize]
+    }
+    /// Writes entry `i` of the MBR (or EBR) at `lba` and its 55AA signature
+    fn mbr_entry(disk: &mut [u8], lba: u64, i: usize, kind: u8, start: u32, sectors: u32){
+        let sector = &mut disk[(lba*SECTOR_SIZE) as usize..((lba + 1)*SECTOR_SIZE) as usize];
+        let e = &mut sector[446 + i*16..462 + i*16];
+        e[4] = kind;
+        e[8..12].copy_from_slice(&start.to_le_bytes());
+        e[12..16].copy_from_slice(&sectors.to_le_bytes());
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+    }
+    /// Writes a GPT header at LBA 1 (behind a protective MBR) with `count` entries of `entry_size` bytes at LBA 2
+    fn gpt_header(disk: &mut [u8], count: u32, entry_size: u32){
+        mbr_entry(disk, 0, 0, MBR_GPT_PROTECTIVE, 1, u32::MAX);
+        let header = &mut disk[512..1024];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&count.to_le_bytes());
+        header[84..88].copy_from_slice(&entry_size.to_le_bytes());
+    }
+    /// Writes GPT entry `i` (with a type GUID that isn't zero) of 128 bytes
+    fn gpt_entry(disk: &mut [u8], i: usize, first: u64, last: u64){
+        let e = &mut disk[1024 + i*128..1024 + (i + 1)*128];
+        e[0..16].fill(0xAB);
+        e[32..40].copy_from_slice(&first.to_le_bytes());
+        e[40..48].copy_from_slice(&last.to_le_bytes());
+    }
+    /// (number, offset, size) of every partition, offsets and sizes in sectors
+    fn partitions(name: &str, bytes: &[u8]) -> Vec<(u32, u64, u64)>{
+        read_partition_table(&disk(name, bytes).file).iter()
+            .map(|p| (p.number, p.offset/SECTOR_SIZE, p.size/SECTOR_SIZE)).collect()
+    }
+
+    #[test]
+    fn mbr(){
+        let mut d = blank(64);
+        mbr_entry(&mut d, 0, 0, 0x83, 2, 10);
+        mbr_entry(&mut d, 0, 2, 0x07, 20, 30);
+        // A zero sized partition is skipped
+        mbr_entry(&mut d, 0, 3, 0x0C, 50, 0);
+        assert_eq!(partitions("mbr", &d), [(1, 2, 10), (3, 20, 30)]);
+    }
+    #[test]
+    fn mbr_without_signature(){
+        let mut d = blank(64);
+        mbr_entry(&mut d, 0, 0, 0x83, 2, 10);
+        d[511] = 0;
+        assert_eq!(partitions("mbr-without-signature", &d), []);
+    }
+    #[test]
+    fn boot_sector_is_not_an_mbr(){
+        let mut d = blank(64);
+        mbr_entry(&mut d, 0, 0, 0x83, 2, 10);
+        // A status byte that is neither 0x00 nor 0x80, like the code of a FAT boot sector
+        d[446 + 16] = 0x4E;
+        assert_eq!(partitions("boot-sector", &d), []);
+    }
+    #[test]
+    fn logical_partitions(){
+        let mut d = blank(64);
+        mbr_entry(&mut d, 0, 0, 0x83, 1, 9);
+        mbr_entry(&mut d, 0, 1, 0x0F, 10, 50);
+        // Logical partitions start relative to their EBR, the next EBR relative to the extended partition
+        mbr_entry(&mut d, 10, 0, 0x83, 1, 4);
+        mbr_entry(&mut d, 10, 1, 0x05, 10, 20);
+        mbr_entry(&mut d, 20, 0, 0x07, 2, 5);
+        assert_eq!(partitions("logical", &d), [(1, 1, 9), (5, 11, 4), (6, 22, 5)]);
+    }
+    #[test]
+    fn logical_partitions_stop_at_a_missing_ebr(){
+        let mut d = blank(64);
+        mbr_entry(&mut d, 0, 0, 0x05, 10, 50);
+        mbr_entry(&mut d, 10, 0, 0x83, 1, 4);
+        // The next EBR is past the end of the disk
+        mbr_entry(&mut d, 10, 1, 0x05, 1000, 20);
+        assert_eq!(partitions("missing-ebr", &d), [(5, 11, 4)]);
+    }
+    #[test]
+    fn gpt(){
+        let mut d = blank(64);
+        gpt_header(&mut d, 4, 128);
+        gpt_entry(&mut d, 0, 34, 47);
+        gpt_entry(&mut d, 1, 48, 63);
+        // Entry 2 is unused, entry 3 ends before it starts
+        gpt_entry(&mut d, 3, 40, 39);
+        assert_eq!(partitions("gpt", &d), [(1, 34, 14), (2, 48, 16)]);
+    }
+    #[test]
+    fn gpt_with_4k_sectors(){
+        let mut d = vec![0u8; 16*4096];
+        mbr_entry(&mut d, 0, 0, MBR_GPT_PROTECTIVE, 1, u32::MAX);
+        let header = &mut d[4096..4096 + 512];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&1u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        let e = &mut d[2*4096..2*4096 + 128];
+        e[0..16].fill(0xAB);
+        e[32..40].copy_from_slice(&6u64.to_le_bytes());
+        e[40..48].copy_from_slice(&15u64.to_le_bytes());
+        let p = read_partition_table(&disk("gpt-4k", &d).file);
+        assert_eq!(p.iter().map(|p| (p.number, p.offset, p.size)).collect::<Vec<_>>(), [(1, 6*4096, 10*4096)]);
+    }
+    #[test]
+    fn gpt_with_invalid_entry_size(){
+        for entry_size in [0, 100, 129, 8192]{
+            let mut d = blank(64);
+            gpt_header(&mut d, 4, entry_size);
+            gpt_entry(&mut d, 0, 34, 47);
+            assert_eq!(partitions("gpt-entry-size", &d), [], "entry size {entry_size}");
+        }
+    }
+    #[test]
+    fn gpt_entries_past_the_end_of_the_disk(){
+        // 128 entries of 128 bytes take 32 sectors from LBA 2, past the end of a disk of 32 sectors
+        let mut d = blank(32);
+        gpt_header(&mut d, 128, 128);
+        gpt_entry(&mut d, 0, 20, 31);
+        assert_eq!(partitions("gpt-past-the-end", &d), []);
+    }
+}
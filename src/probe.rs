@@ -0,0 +1,25 @@
+use crate::{indexer, partitions};
+
+// Recognizes the filesystem of a drive by looking at the signatures in its boot sector/superblock,
+// every backend in the registry checks its own (in the order they were registered).
+// Signatures are the same ones used by blkid/libblkid
+
+/// How much of the drive has to be read to see every signature (btrfs keeps its superblock at 64KiB)
+const PROBE_SIZE: usize = 0x10000 + 0x1000;
+
+/// Returns the name of the filesystem, None if the drive can't be read (usually because of lacking permission).
+/// `device` may point to a partition of a disk ("/dev/sda#p2")
+pub fn probe(device: &str) -> Option<&'static str>{
+    let file = partitions::open(device).ok()?;
+    let mut b = vec![0u8; PROBE_SIZE];
+    // Small drives/images may be shorter than PROBE_SIZE, whatever was read is left in the buffer
+    let mut read = 0;
+    while read < b.len(){
+        match file.read_at(&mut b[read..], read as u64){
+            Ok(0) => {break;}
+            Ok(n) => {read += n;}
+            Err(_) => {if read == 0{return None;}else{break;}}
+        }
+    }
+    Some(indexer::registry().read().unwrap().probe(&b, &file))
+}
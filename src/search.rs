@@ -0,0 +1,310 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use crate::{Attributes, Directory, File, Index, Settings};
+
+// Queries are made of predicates separated by "\", a file is found if it satisfies all of them
+// (see Search Options in the README)
+
+/// What to search for
+#[derive(Debug, Default, Clone)]
+pub struct Query{
+    /// Like what is typed in the search bar: "xyz" or predicates like "\_*xyz\!*_.txt"
+    pub text: String,
+    pub ignore_case: bool,
+    /// Whether the full path of each file is searched instead of its name when the text contains a "/"
+    pub search_full_path: bool,
+}
+impl Query{
+    /// Ignores case and searches full paths, like the default settings
+    pub fn new(text: &str) -> Self{
+        Query{text: text.to_string(), ignore_case: true, search_full_path: true}
+    }
+    /// Uses the search options of the settings
+    pub fn with_settings(text: &str, settings: &Settings) -> Self{
+        Query{text: text.to_string(), ignore_case: settings.ignore_case, search_full_path: settings.search_full_path}
+    }
+}
+/// A file that matched a query
+#[derive(Debug, Default, Clone)]
+pub struct SearchResult{
+    /// A copy of the file in the index, its parent is a directory of the index that was searched
+    pub file: File,
+}
+impl SearchResult{
+    /// Full path of the file, `index` has to be the one that was searched
+    pub fn path(self: &Self, index: &Index) -> String{
+        index.path(&self.file)
+    }
+}
+/// Vec< negation, starts_with, ends_with
+fn convert_string_to_predicates(searching_for: String)->Vec<(bool,bool,bool,String)>{
+    let mut output = Vec::new();
+    if searching_for.contains("\\"){
+        let parts: Vec<&str> = searching_for.split("\\").collect();
+        for p in parts{
+            let mut negation = false;
+            let mut starts_with = false;
+            let mut ends_with = false;
+            let string;
+
+            if !p.is_empty(){
+                if p.starts_with("!"){
+                    negation = true;
+                    if p.starts_with("!_*"){
+                        starts_with = true;
+                    } else if p.starts_with("!*_"){
+                        ends_with = true;
+                    }
+                }
+                if p.starts_with("_*"){
+                    starts_with = true;
+                } else if p.starts_with("*_"){
+                    ends_with = true;
+                }
+                if negation && (starts_with || ends_with){
+                    string = p[3..].to_string();
+                }else if !negation && (starts_with || ends_with){
+                    string = p[2..].to_string();
+                }else{
+                    string = p[1..].to_string();
+                }
+                output.push((negation,starts_with,ends_with,string));
+            }
+        }
+        output
+    }else{
+        vec![(false,false,false,searching_for)]
+    }
+}
+/// The attributes a predicate like "attrib:hs" asks for (see Attributes::to_letters), None if it isn't one.
+/// Only plain (or negated) predicates can be about attributes
+fn attribute_predicate(p: &(bool,bool,bool,String)) -> Option<(bool, u32)>{
+    if p.1 || p.2{
+        return None;
+    }
+    let letters = p.3.strip_prefix("attrib:")?;
+    if letters.is_empty(){
+        return None;
+    }
+    Some((p.0, Attributes::from_letters(letters)?))
+}
+/// What a name is compared as when case is ignored: uppercased through the up-case table of its drive when
+/// the drive has one (like exFAT volumes, so that names match the way the filesystem itself matches them),
+/// lowercased otherwise
+fn fold_case(s: &str, upcase: Option<&[u16]>) -> String{
+    match upcase{
+        Some(table) => {
+            let units: Vec<u16> = s.encode_utf16().map(|u| table.get(u as usize).copied().unwrap_or(u)).collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => {s.to_lowercase()}
+    }
+}
+/// The up-case table of the drive that the directory belongs to
+fn upcase_of(upcase: &[(Range<u32>, Arc<[u16]>)], directory: u32) -> Option<&[u16]>{
+    upcase.iter().find(|(directories, _)| directories.contains(&directory)).map(|(_, table)| &table[..])
+}
+pub fn search(items: &[File], directories: &[Directory], upcase: &[(Range<u32>, Arc<[u16]>)], query: &Query, cancel_flag: &Receiver<u8>)->Vec<File>{
+    let mut output: Vec<File> = Vec::new();
+    let mut pred = convert_string_to_predicates(query.text.clone());
+    // Attribute predicates are checked once the names have been matched
+    let mut attributes = Vec::new();
+    pred.retain(|p| match attribute_predicate(p){
+        Some(a) => {attributes.push(a); false}
+        None => {true}
+    });
+    // Cancelled searches return what they found so far, also filtered by attributes
+    let with_attributes = |mut files: Vec<File>| {
+        files.retain(|f| attributes.iter().all(|&(negation, bits)| f.attributes.contains(bits) != negation));
+        files
+    };
+    if pred.is_empty(){
+        output = items.to_vec();
+    }
+    let contains_slash = query.text.contains('/');
+    // dbg!(&pred);
+
+    for (i, p) in pred.iter().enumerate(){
+        if i == 0{
+            //Initial pred build all the results
+            for item in items{
+                if let Ok(1) = cancel_flag.try_recv(){
+                    return with_attributes(output);
+                }
+                let f: File = item.clone();
+                let mut n;
+                let mut m = p.3.clone();
+                if query.search_full_path && contains_slash {
+                    n = directories[f.parent as usize].name.clone() + &f.name;
+                }else{
+                    n = f.name.clone();
+                }
+                if query.ignore_case{
+                    let table = upcase_of(upcase, f.parent);
+                    n = fold_case(&n, table);
+                    m = fold_case(&m, table);
+                }
+                // Negate
+                if p.0{
+                    // Not Starts With
+                    if p.1{
+                        if !n.starts_with(&m){
+                            output.push(f);
+                        }
+                    }
+                    // Not Ends With
+                    else if p.2{
+                        if !n.ends_with(&m){
+                            output.push(f);
+                        }
+                    }
+                    // Not contains
+                    else{
+                        if !n.contains(&m){
+                            output.push(f);
+                        }
+                    }
+                // Normal
+                }else{
+                    // Starts With
+                    if p.1{
+                        if n.starts_with(&m){
+                            output.push(f);
+                        }
+                    }
+                    // Ends With
+                    else if p.2{
+                        if n.ends_with(&m){
+                            output.push(f);
+                        }
+                    }
+                    // contains
+                    else{
+                        if n.contains(&m){
+                            output.push(f);
+                        }
+                    }
+                }
+            }
+        } else {
+            //Later predicates only use from the previous results
+            let mut temp = Vec::new();
+            for o in &output{
+                if let Ok(1) = cancel_flag.try_recv(){
+                    return with_attributes(temp);
+                }
+                let f: File = o.clone();
+                let mut n;
+                let mut m = p.3.clone();
+                if query.search_full_path && contains_slash {
+                    n = directories[f.parent as usize].name.clone() + &f.name;
+                }else{
+                    n = f.name.clone();
+                }
+                if query.ignore_case{
+                    let table = upcase_of(upcase, f.parent);
+                    n = fold_case(&n, table);
+                    m = fold_case(&m, table);
+                }
+                // Negate
+                if p.0{
+                    // Not Starts With
+                    if p.1{
+                        if !n.starts_with(&m){
+                            temp.push(f);
+                        }
+                    }
+                    // Not Ends With
+                    else if p.2{
+                        if !n.ends_with(&m){
+                            temp.push(f);
+                        }
+                    }
+                    // Not contains
+                    else{
+                        if !n.contains(&m){
+                            temp.push(f);
+                        }
+                    }
+                // Normal
+                }else{
+                    // Starts With
+                    if p.1{
+                        if n.starts_with(&m){
+                            temp.push(f);
+                        }
+                    }
+                    // Ends With
+                    else if p.2{
+                        if n.ends_with(&m){
+                            temp.push(f);
+                        }
+                    }
+                    // contains
+                    else{
+                        if n.contains(&m){
+                            temp.push(f);
+                        }
+                    }
+                }
+            }
+            output = temp;
+        }
+    }
+    with_attributes(output)
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::sync::mpsc;
+
+    /// The attribute predicates of a query, None for the ones about names
+    fn attribute_predicates(text: &str) -> Vec<Option<(bool, u32)>>{
+        convert_string_to_predicates(text.to_string()).iter().map(attribute_predicate).collect()
+    }
+    /// The names of the files (all in "/") that match `text`
+    fn found(text: &str) -> Vec<String>{
+        let file = |name: &str, attributes: u32| File{name: name.to_string(), attributes: Attributes(attributes), ..File::default()};
+        let items = [
+            file("notes.txt", 0),
+            file("hidden.txt", Attributes::HIDDEN),
+            file("readonly.txt", Attributes::READ_ONLY),
+            file("boot.ini", Attributes::HIDDEN | Attributes::SYSTEM | Attributes::READ_ONLY),
+            file("pagefile.sys", Attributes::HIDDEN | Attributes::SYSTEM),
+            file("folder", Attributes::DIRECTORY),
+        ];
+        let directories = [Directory{name: "/".to_string()}];
+        let (_cancel, cancel_flag) = mpsc::channel();
+        search(&items, &directories, &[], &Query::new(text), &cancel_flag).into_iter().map(|f| f.name).collect()
+    }
+
+    #[test]
+    fn attribute_predicates_are_parsed(){
+        assert_eq!(attribute_predicates("attrib:h"), [Some((false, Attributes::HIDDEN))]);
+        assert_eq!(attribute_predicates("\\ attrib:Hs"), [Some((false, Attributes::HIDDEN | Attributes::SYSTEM))]);
+        assert_eq!(attribute_predicates("\\*_.txt\\!attrib:r"), [None, Some((true, Attributes::READ_ONLY))]);
+    }
+    #[test]
+    fn other_predicates_are_about_names(){
+        // Unknown letters, no letters, starts with and ends with
+        assert_eq!(attribute_predicates("attrib:x"), [None]);
+        assert_eq!(attribute_predicates("attrib:"), [None]);
+        assert_eq!(attribute_predicates("\\_*attrib:h\\*_attrib:h\\!_*attrib:h"), [None, None, None]);
+    }
+    #[test]
+    fn attributes_filter_the_results(){
+        assert_eq!(found("attrib:h"), ["hidden.txt", "boot.ini", "pagefile.sys"]);
+        assert_eq!(found("attrib:hs"), ["boot.ini", "pagefile.sys"]);
+        assert_eq!(found("\\!attrib:h"), ["notes.txt", "readonly.txt", "folder"]);
+        assert_eq!(found("attrib:d"), ["folder"]);
+        assert_eq!(found("\\!attrib:d\\!attrib:s"), ["notes.txt", "hidden.txt", "readonly.txt"]);
+    }
+    #[test]
+    fn attributes_and_names(){
+        assert_eq!(found("\\*_.txt\\!attrib:r"), ["notes.txt", "hidden.txt"]);
+        assert_eq!(found("\\ attrib:r\\ o"), ["readonly.txt", "boot.ini"]);
+        // Letters that aren't attributes are searched in names
+        assert_eq!(found("attrib:x"), [] as [&str; 0]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, inflate, lzma, u16_at, u32_at, u64_at, zstd};
+
+// The following code decodes SquashFS 4.0 following the kernel documentation
+// https://docs.kernel.org/filesystems/squashfs.html and the format description of squashfs-tools
+// Only the inode and directory tables are read, they are split in metadata blocks of 8KiB
+// which are usually compressed
+
+const MAGIC: &[u8] = b"hsqs";
+const GZIP: u16 = 1;
+const LZMA: u16 = 2;
+const XZ: u16 = 4;
+const ZSTD: u16 = 6;
+/// The highest bit of a metadata block header means the block is stored uncompressed
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+/// Metadata blocks are at most 8 KiB once decompressed
+const METADATA_BLOCK_SIZE: usize = 8192;
+const BASIC_DIRECTORY: u16 = 1;
+const BASIC_FILE: u16 = 2;
+const BASIC_SYMLINK: u16 = 3;
+const EXTENDED_DIRECTORY: u16 = 8;
+const EXTENDED_FILE: u16 = 9;
+const EXTENDED_SYMLINK: u16 = 10;
+/// Every inode starts with type, permissions, uid, gid, modification time and inode number
+const INODE_HEADER_SIZE: usize = 16;
+/// The size of a directory listing counts "." and ".." as 3 bytes which aren't stored
+const DIRECTORY_SIZE_OFFSET: usize = 3;
+
+/// AppImages are an ELF executable followed by the SquashFS image, which starts where the ELF ends
+/// (after the section headers, the same way the AppImage runtime finds it)
+pub fn appimage_offset(b: &[u8]) -> Option<u64>{
+    if b.len() < 64 || &b[0..4] != b"\x7FELF"{
+        return None;
+    }
+    match b[4]{
+        1 => {Some(u32_at(b, 0x20) as u64 + u16_at(b, 0x2E) as u64*u16_at(b, 0x30) as u64)}
+        2 => {Some(u64_at(b, 0x28) + u16_at(b, 0x3A) as u64*u16_at(b, 0x3C) as u64)}
+        _ => {None}
+    }
+}
+/// The part of an inode that matters to us
+#[derive(Debug, Default, Clone)]
+struct Inode{
+    is_dir: bool,
+    size: u64,
+    last_modified_timestamp: i64,
+    /// Where the listing of a directory starts in the directory table and how long it is
+    dir_block: u32,
+    dir_offset: usize,
+    dir_size: usize,
+}
+struct SquashfsDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    /// Where the SquashFS image starts (not 0 for AppImages)
+    offset: u64,
+    compressor: u16,
+    root_inode: u64,
+    inode_table: u64,
+    directory_table: u64,
+    /// Decompressed metadata blocks by their position, with the position of the next block
+    metadata: HashMap<u64, (Vec<u8>, u64)>,
+    /// Inode references of the directories already indexed, damaged entries could point back to a parent
+    visited: HashSet<u64>,
+    files: Vec<SquashfsFile>,
+    ignored_dirs: Vec<String>
+}
+impl SquashfsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut header = vec![0u8; 64];
+        file.read_at(&mut header, 0).map_err(Error::io(0))?;
+        let offset = appimage_offset(&header).unwrap_or(0);
+        let mut sb = vec![0u8; 96];
+        file.read_at(&mut sb, offset).map_err(Error::io(offset))?;
+        if &sb[0..4] != MAGIC{
+            return Err(Error::WrongFilesystem("SquashFS"));
+        }
+        if u16_at(&sb, 28) != 4{
+            return Err(Error::Unsupported(format!("SquashFS {}.{} (only 4.0 is supported)", u16_at(&sb, 28), u16_at(&sb, 30))));
+        }
+        let compressor = u16_at(&sb, 20);
+        if !matches!(compressor, GZIP | LZMA | XZ | ZSTD){
+            return Err(Error::Unsupported(format!("SquashFS compression {compressor} (only gzip, lzma, xz and zstd)")));
+        }
+        Ok(SquashfsDrive {file, directories: Vec::new(), mounted_at, offset, compressor, root_inode: u64_at(&sb, 32),
+            inode_table: u64_at(&sb, 64), directory_table: u64_at(&sb, 72), metadata: HashMap::new(), visited: HashSet::new(), files: Vec::new(), ignored_dirs})
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, self.offset + from).map_err(Error::io(self.offset + from))?;
+        Ok(b)
+    }
+    fn decompress(self: &Self, data: &[u8]) -> Option<Vec<u8>>{
+        match self.compressor{
+            GZIP => {
+                // zlib stream: 2 bytes header, the DEFLATE data and the adler32 checksum
+                let mut out = Vec::new();
+                inflate::inflate(data.get(2..)?, &mut out, METADATA_BLOCK_SIZE)?;
+                Some(out)
+            }
+            LZMA => {lzma::unlzma(data, METADATA_BLOCK_SIZE)}
+            XZ => {lzma::unxz(data, METADATA_BLOCK_SIZE)}
+            _ => {zstd::decompress(data, METADATA_BLOCK_SIZE)}
+        }
+    }
+    /// Reads from a table made of metadata blocks, starting `offset` bytes into the block at `block`
+    /// None if a block can't be decompressed
+    fn read_metadata(self: &mut Self, mut block: u64, mut offset: usize, size: usize) -> Result<Option<Vec<u8>>, Error>{
+        let mut b = Vec::with_capacity(size);
+        while b.len() < size{
+            if !self.metadata.contains_key(&block){
+                let header = u16_at(&self.read_bytes(block, 2)?, 0);
+                let stored = (header & !METADATA_UNCOMPRESSED) as u64;
+                let raw = self.read_bytes(block + 2, stored)?;
+                let data = if header & METADATA_UNCOMPRESSED != 0{
+                    raw
+                }else{
+                    match self.decompress(&raw){Some(data) => {data} None => {return Ok(None);}}
+                };
+                self.metadata.insert(block, (data, block + 2 + stored));
+            }
+            let (data, next) = &self.metadata[&block];
+            if data.is_empty(){
+                return Ok(None);
+            }
+            if offset >= data.len(){
+                offset -= data.len();
+                block = *next;
+                continue;
+            }
+            let n = (data.len() - offset).min(size - b.len());
+            b.extend_from_slice(&data[offset..offset + n]);
+            offset += n;
+        }
+        Ok(Some(b))
+    }
+    /// Inodes are referred to by the position of their metadata block in the inode table (upper bits)
+    /// and their offset inside of the decompressed block (lower 16 bits)
+    fn read_inode(self: &mut Self, reference: u64) -> Result<Option<Inode>, Error>{
+        let block = self.inode_table + (reference >> 16);
+        let offset = (reference & 0xFFFF) as usize;
+        let Some(header) = self.read_metadata(block, offset, INODE_HEADER_SIZE)? else{
+            return Ok(None);
+        };
+        let inode_type = u16_at(&header, 0);
+        // SquashFS only stores the modification time
+        let mut inode = Inode{last_modified_timestamp: u32_at(&header, 8) as i64, ..Default::default()};
+        let body_size = match inode_type{
+            BASIC_DIRECTORY => {16}
+            EXTENDED_DIRECTORY => {24}
+            BASIC_FILE => {16}
+            EXTENDED_FILE => {16}
+            BASIC_SYMLINK | EXTENDED_SYMLINK => {8}
+            _ => {return Ok(Some(inode));}
+        };
+        let Some(b) = self.read_metadata(block, offset, INODE_HEADER_SIZE + body_size)? else{
+            return Ok(None);
+        };
+        match inode_type{
+            BASIC_DIRECTORY => {
+                inode.is_dir = true;
+                inode.dir_block = u32_at(&b, 16);
+                inode.dir_size = u16_at(&b, 24) as usize;
+                inode.dir_offset = u16_at(&b, 26) as usize;
+            }
+            EXTENDED_DIRECTORY => {
+                inode.is_dir = true;
+                inode.dir_size = u32_at(&b, 20) as usize;
+                inode.dir_block = u32_at(&b, 24);
+                inode.dir_offset = u16_at(&b, 34) as usize;
+            }
+            BASIC_FILE => {inode.size = u32_at(&b, 28) as u64;}
+            EXTENDED_FILE => {inode.size = u64_at(&b, 24);}
+            // The size of a symlink is the length of its target
+            _ => {inode.size = u32_at(&b, 20) as u64;}
+        }
+        inode.size = if inode.is_dir{inode.dir_size as u64}else{inode.size};
+        Ok(Some(inode))
+    }
+    /// Returns (inode reference, name) for every entry in a directory
+    fn read_dir(self: &mut Self, inode: &Inode) -> Result<Vec<(u64, String)>, Error>{
+        let mut entries = Vec::new();
+        if inode.dir_size <= DIRECTORY_SIZE_OFFSET{
+            return Ok(entries);
+        }
+        let Some(b) = self.read_metadata(self.directory_table + inode.dir_block as u64, inode.dir_offset, inode.dir_size - DIRECTORY_SIZE_OFFSET)? else{
+            return Ok(entries);
+        };
+        // Entries are grouped under headers with the metadata block of their inodes (and an inode number base)
+        let mut i = 0;
+        while i + 12 <= b.len(){
+            let count = u32_at(&b, i) as usize + 1;
+            let start = u32_at(&b, i + 4) as u64;
+            i += 12;
+            for _ in 0..count{
+                if i + 8 > b.len(){
+                    break;
+                }
+                let offset = u16_at(&b, i) as u64;
+                let name_size = u16_at(&b, i + 6) as usize + 1;
+                let Some(name) = b.get(i + 8..i + 8 + name_size) else{
+                    break;
+                };
+                entries.push(((start << 16) | offset, String::from_utf8_lossy(name).to_string()));
+                i += 8 + name_size;
+            }
+        }
+        Ok(entries)
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let Some(root) = self.read_inode(self.root_inode)? else{
+            return Err(Error::corrupt(self.offset + self.inode_table + (self.root_inode >> 16), "root inode not found"));
+        };
+        self.visited.insert(self.root_inode);
+        self.index(&root, 0)?;
+        Ok(self)
+    }
+    fn index(self: &mut Self, directory: &Inode, parent: u32) -> Result<(), Error>{
+        let mut new_dirs = Vec::new();
+        for (reference, name) in self.read_dir(directory)?{
+            let Some(inode) = self.read_inode(reference)? else{
+                continue;
+            };
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if inode.is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(SquashfsFile{
+                name: name.clone(),
+                parent,
+                size: inode.size,
+                is_dir: inode.is_dir,
+                create_timestamp: inode.last_modified_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if inode.is_dir && self.visited.insert(reference){
+                new_dirs.push((inode, full_name));
+            }
+        }
+        for (inode, name) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(&inode, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct SquashfsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_squashfs_files_to_files(f: &SquashfsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// Also reads AppImages, metadata compressed with gzip, lzma, xz or zstd
+pub struct SquashfsIndexer;
+impl FilesystemIndexer for SquashfsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Squashfs"
+    }
+    fn probe(self: &Self, b: &[u8], file: &PartitionFile) -> bool{
+        if &b[0..4] == MAGIC{
+            return true;
+        }
+        // AppImages are executables with a SquashFS image appended
+        let Some(offset) = appimage_offset(b) else{
+            return false;
+        };
+        let mut magic = [0u8; 4];
+        file.read_at(&mut magic, offset).is_ok() && magic == MAGIC
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = SquashfsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_squashfs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: false, reads_device: true, deleted_files: false}
+    }
+}
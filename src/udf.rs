@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File, u16_at, u32_at, u64_at};
+use crate::iso9660::date_to_epoch;
+
+// The following code decodes UDF (ECMA-167 with the OSTA UDF 2.60 restrictions)
+// http://www.osta.org/specs/pdf/udf260.pdf
+// Physical, sparable and metadata partitions are supported,
+// virtual partitions (VAT, used by CD-R/DVD-R written in multiple sessions) are not
+
+const TAG_ANCHOR: u16 = 2;
+const TAG_PARTITION: u16 = 5;
+const TAG_LOGICAL_VOLUME: u16 = 6;
+const TAG_TERMINATING: u16 = 8;
+const TAG_FILE_SET: u16 = 256;
+const TAG_FILE_IDENTIFIER: u16 = 257;
+const TAG_ALLOCATION_EXTENT: u16 = 258;
+const TAG_FILE_ENTRY: u16 = 261;
+const TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+const FILE_TYPE_DIRECTORY: u8 = 4;
+const FID_DELETED: u8 = 0x04;
+const FID_PARENT: u8 = 0x08;
+/// Type of the allocation descriptors of a file entry (lowest 3 bits of the ICB flags)
+const AD_SHORT: u16 = 0;
+const AD_LONG: u16 = 1;
+const AD_EXTENDED: u16 = 2;
+const AD_EMBEDDED: u16 = 3;
+/// Extent types (highest 2 bits of the extent length)
+const EXTENT_RECORDED: u32 = 0;
+const EXTENT_NEXT_DESCRIPTORS: u32 = 3;
+
+/// 12 bytes timestamp, the timezone is in minutes (-2047 means unspecified)
+fn timestamp_to_epoch(b: &[u8]) -> i64{
+    let type_and_tz = u16_at(b, 0);
+    // 12 bit signed number
+    let tz = ((type_and_tz << 4) as i16 >> 4) as i64;
+    let offset_secs = if tz == -2047{0}else{tz*60};
+    date_to_epoch(u16_at(b, 2) as i16 as i32, b[4] as u32, b[5] as u32, b[6] as u32, b[7] as u32, b[8] as u32, offset_secs)
+}
+/// d-string: the first byte says if characters are 8 or 16 (big endian) bits
+fn dstring_to_string(b: &[u8]) -> String{
+    if b.is_empty(){
+        return String::new();
+    }
+    match b[0]{
+        16 | 255 => {
+            let units: Vec<u16> = b[1..].chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => {b[1..].iter().map(|&c| c as char).collect()}
+    }
+}
+#[derive(Debug, Clone)]
+enum PartitionMap{
+    /// Blocks are read straight from the partition with this number
+    Physical(u16),
+    /// Blocks are inside of the metadata file, stored in the partition with this number as (block, count) extents
+    Metadata(u16, Vec<(u32, u32)>),
+    Virtual,
+}
+/// The part of a (extended) file entry that matters to us
+#[derive(Debug, Default, Clone)]
+struct Entry{
+    is_dir: bool,
+    size: u64,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    /// Data stored inside of the entry itself
+    embedded: Option<Vec<u8>>,
+    /// (partition reference, block, length in bytes)
+    extents: Vec<(u16, u32, u32)>,
+}
+struct UdfDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    /// (partition number, first block) from the partition descriptors
+    partitions: Vec<(u16, u32)>,
+    /// Partition references used by the file system point here
+    maps: Vec<PartitionMap>,
+    /// Partition reference and block of the root directory file entry
+    root: (u16, u32),
+    /// (partition, block) of the file entries of the directories already indexed, against loops in damaged volumes
+    visited: HashSet<(u16, u32)>,
+    files: Vec<UdfFile>,
+    ignored_dirs: Vec<String>
+}
+impl UdfDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        // The anchor is always at block 256, the block size is whatever makes it be found there
+        let mut anchor = vec![0u8; 512];
+        let mut block_size = 0;
+        for bs in [2048, 512, 4096, 1024]{
+            if file.read_at(&mut anchor, 256*bs).is_ok() && u16_at(&anchor, 0) == TAG_ANCHOR && u32_at(&anchor, 12) == 256{
+                block_size = bs;
+                break;
+            }
+        }
+        if block_size == 0{
+            return Err(Error::WrongFilesystem("UDF"));
+        }
+        let mut drive = UdfDrive {file, directories: Vec::new(), mounted_at, block_size, partitions: Vec::new(),
+            maps: Vec::new(), root: (0, 0), visited: HashSet::new(), files: Vec::new(), ignored_dirs};
+
+        // Main volume descriptor sequence
+        let vds = drive.read_bytes(u32_at(&anchor, 20) as u64*block_size, u32_at(&anchor, 16) as u64)?;
+        let mut lvd = None;
+        for d in vds.chunks_exact(block_size as usize){
+            match u16_at(d, 0){
+                TAG_PARTITION => {drive.partitions.push((u16_at(d, 22), u32_at(d, 188)));}
+                TAG_LOGICAL_VOLUME => {lvd = Some(d.to_vec());}
+                TAG_TERMINATING => {break;}
+                _ => {}
+            }
+        }
+        let Some(lvd) = lvd else{
+            return Err(Error::corrupt(u32_at(&anchor, 20) as u64*block_size, "logical volume descriptor not found"));
+        };
+        let mut at = 440;
+        for _ in 0..u32_at(&lvd, 268){
+            // Type 2 maps are 64 bytes long
+            if at + 64 > lvd.len(){
+                break;
+            }
+            let len = lvd[at+1] as usize;
+            if len == 0{
+                break;
+            }
+            if lvd[at] == 1{
+                drive.maps.push(PartitionMap::Physical(u16_at(&lvd, at+4)));
+            }else{
+                let identifier = &lvd[at+5..at+28];
+                let number = u16_at(&lvd, at+38);
+                if identifier.starts_with(b"*UDF Metadata Partition"){
+                    drive.maps.push(PartitionMap::Metadata(number, Vec::new()));
+                    let entry = drive.read_entry_at(drive.partition_start(number)? + u32_at(&lvd, at+40) as u64, drive.maps.len() as u16 - 1)?;
+                    let extents = entry.extents.iter().map(|&(_, block, len)| (block, len.div_ceil(block_size as u32))).collect();
+                    *drive.maps.last_mut().unwrap() = PartitionMap::Metadata(number, extents);
+                }else if identifier.starts_with(b"*UDF Virtual Partition"){
+                    drive.maps.push(PartitionMap::Virtual);
+                }else{
+                    // Sparable partitions only remap defective packets, they are read like physical ones
+                    drive.maps.push(PartitionMap::Physical(number));
+                }
+            }
+            at += len;
+        }
+        // The file set descriptor points to the root directory
+        let fsd = drive.read_blocks(u16_at(&lvd, 256), u32_at(&lvd, 252), block_size as u32)?;
+        if u16_at(&fsd, 0) != TAG_FILE_SET{
+            return Err(Error::corrupt(drive.to_absolute(u16_at(&lvd, 256), u32_at(&lvd, 252))?*block_size, "file set descriptor not found"));
+        }
+        drive.root = (u16_at(&fsd, 408), u32_at(&fsd, 404));
+        Ok(drive)
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    fn partition_start(self: &Self, number: u16) -> Result<u64, Error>{
+        match self.partitions.iter().find(|p| p.0 == number){
+            Some(p) => {Ok(p.1 as u64)}
+            None => {Err(Error::corrupt(256*self.block_size, format!("partition {number} not found")))}
+        }
+    }
+    /// Turns a block of a partition into a block of the drive
+    fn to_absolute(self: &Self, partition_ref: u16, block: u32) -> Result<u64, Error>{
+        match self.maps.get(partition_ref as usize){
+            Some(PartitionMap::Physical(number)) => {Ok(self.partition_start(*number)? + block as u64)}
+            Some(PartitionMap::Metadata(number, extents)) => {
+                let mut block = block;
+                for &(start, count) in extents{
+                    if block < count{
+                        return Ok(self.partition_start(*number)? + start as u64 + block as u64);
+                    }
+                    block -= count;
+                }
+                Err(Error::corrupt(self.partition_start(*number)?*self.block_size, "block outside of the metadata partition"))
+            }
+            Some(PartitionMap::Virtual) => {Err(Error::Unsupported("UDF virtual partitions (VAT)".to_string()))}
+            None => {Err(Error::corrupt(256*self.block_size, format!("partition map {partition_ref} not found")))}
+        }
+    }
+    fn read_blocks(self: &Self, partition_ref: u16, block: u32, len: u32) -> Result<Vec<u8>, Error>{
+        let count = (len as u64).div_ceil(self.block_size);
+        let mut b = Vec::with_capacity((count*self.block_size) as usize);
+        // Runs of blocks that are also next to each other on the drive are read at once
+        let mut i = 0;
+        while i < count{
+            let first = self.to_absolute(partition_ref, block.wrapping_add(i as u32))?;
+            let mut run = 1;
+            while i + run < count && self.to_absolute(partition_ref, block.wrapping_add((i + run) as u32))? == first + run{
+                run += 1;
+            }
+            b.append(&mut self.read_bytes(first*self.block_size, run*self.block_size)?);
+            i += run;
+        }
+        b.truncate(len as usize);
+        Ok(b)
+    }
+    fn read_entry(self: &Self, partition_ref: u16, block: u32) -> Result<Entry, Error>{
+        self.read_entry_at(self.to_absolute(partition_ref, block)?, partition_ref)
+    }
+    fn read_entry_at(self: &Self, absolute: u64, partition_ref: u16) -> Result<Entry, Error>{
+        let b = self.read_bytes(absolute*self.block_size, self.block_size)?;
+        let tag = u16_at(&b, 0);
+        if tag != TAG_FILE_ENTRY && tag != TAG_EXTENDED_FILE_ENTRY{
+            return Ok(Entry::default());
+        }
+        let mut entry = Entry{
+            is_dir: b[27] == FILE_TYPE_DIRECTORY,
+            size: u64_at(&b, 56),
+            ..Default::default()
+        };
+        // Only extended file entries have a creation time
+        let (ea_at, ad_at) = if tag == TAG_EXTENDED_FILE_ENTRY{
+            entry.last_modified_timestamp = timestamp_to_epoch(&b[92..104]);
+            entry.create_timestamp = timestamp_to_epoch(&b[104..116]);
+            (208, 216)
+        }else{
+            entry.last_modified_timestamp = timestamp_to_epoch(&b[84..96]);
+            entry.create_timestamp = entry.last_modified_timestamp;
+            (168, 176)
+        };
+        let ad_start = (ad_at + u32_at(&b, ea_at) as usize).min(b.len());
+        let ad_end = (ad_start + u32_at(&b, ea_at + 4) as usize).min(b.len());
+        let ad_type = u16_at(&b, 34) & 7;
+        if ad_type == AD_EMBEDDED{
+            entry.embedded = Some(b[ad_start..ad_end].to_vec());
+        }else{
+            self.read_allocation_descriptors(&b[ad_start..ad_end], ad_type, partition_ref, &mut entry.extents, 0)?;
+        }
+        Ok(entry)
+    }
+    fn read_allocation_descriptors(self: &Self, b: &[u8], ad_type: u16, partition_ref: u16, extents: &mut Vec<(u16, u32, u32)>, depth: u32) -> Result<(), Error>{
+        let size = match ad_type{
+            AD_SHORT => {8}
+            AD_LONG => {16}
+            AD_EXTENDED => {20}
+            _ => {return Ok(());}
+        };
+        for ad in b.chunks_exact(size){
+            let len = u32_at(ad, 0) & 0x3FFF_FFFF;
+            let extent_type = u32_at(ad, 0) >> 30;
+            if len == 0{
+                break;
+            }
+            let (partition, block) = match ad_type{
+                AD_SHORT => {(partition_ref, u32_at(ad, 4))}
+                AD_LONG => {(u16_at(ad, 8), u32_at(ad, 4))}
+                _ => {(u16_at(ad, 16), u32_at(ad, 12))}
+            };
+            if extent_type == EXTENT_NEXT_DESCRIPTORS{
+                // The list continues in an allocation extent descriptor
+                if depth < 64{
+                    let aed = self.read_blocks(partition, block, len)?;
+                    if aed.len() >= 24 && u16_at(&aed, 0) == TAG_ALLOCATION_EXTENT{
+                        let end = (24 + u32_at(&aed, 20) as usize).min(aed.len());
+                        self.read_allocation_descriptors(&aed[24..end], ad_type, partition, extents, depth + 1)?;
+                    }
+                }
+                break;
+            }
+            if extent_type == EXTENT_RECORDED{
+                extents.push((partition, block, len));
+            }
+        }
+        Ok(())
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        let root = self.read_entry(self.root.0, self.root.1)?;
+        self.visited.insert(self.root);
+        self.index(root, 0)?;
+        Ok(self)
+    }
+    fn index(self: &mut Self, dir: Entry, parent: u32) -> Result<(), Error>{
+        let bytes = match dir.embedded{
+            Some(b) => {b}
+            None => {
+                let mut b = Vec::new();
+                for &(partition, block, len) in &dir.extents{
+                    b.append(&mut self.read_blocks(partition, block, len)?);
+                }
+                b
+            }
+        };
+        let mut new_dirs = Vec::new();
+        let mut i = 0;
+        while i + 38 <= bytes.len() && u16_at(&bytes, i) == TAG_FILE_IDENTIFIER{
+            let characteristics = bytes[i+18];
+            let name_len = bytes[i+19] as usize;
+            let icb_block = u32_at(&bytes, i+24);
+            let icb_partition = u16_at(&bytes, i+28);
+            let iu_len = u16_at(&bytes, i+36) as usize;
+            let name_at = i + 38 + iu_len;
+            // Descriptors are padded to 4 bytes
+            i = (name_at + name_len + 3) & !3;
+            if characteristics & (FID_PARENT | FID_DELETED) != 0 || name_at + name_len > bytes.len(){
+                continue;
+            }
+            let name = dstring_to_string(&bytes[name_at..name_at+name_len]);
+            let entry = self.read_entry(icb_partition, icb_block)?;
+
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if entry.is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(UdfFile{
+                name,
+                parent,
+                size: entry.size,
+                is_dir: entry.is_dir,
+                create_timestamp: entry.create_timestamp,
+                last_modified_timestamp: entry.last_modified_timestamp
+            });
+            if entry.is_dir && self.visited.insert((icb_partition, icb_block)){
+                new_dirs.push((full_name, entry));
+            }
+        }
+        for (name, entry) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(entry, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct UdfFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_udf_files_to_files(f: &UdfFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+/// The volume recognition sequence (2048 bytes descriptors from 32KiB) has an NSR descriptor on UDF
+fn is_udf(b: &[u8]) -> bool{
+    (0x8000..b.len() - 0x800).step_by(0x800).any(|i| &b[i+1..i+6] == b"NSR02" || &b[i+1..i+6] == b"NSR03")
+}
+/// DVDs/Blu-rays and their images
+/// `drive` can be an optical drive (/dev/sr0) or an image file
+pub struct UdfIndexer;
+impl FilesystemIndexer for UdfIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Udf"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        is_udf(b)
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = UdfDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_udf_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet};
+use crate::partitions::{self, PartitionFile};
+use crate::indexer::{Capabilities, FilesystemIndexer};
+use crate::{Attributes, Directory, Drive, Error, File};
+
+// The following code decodes XFS (v4 and v5) following the XFS Algorithms & Data Structures document
+// https://www.kernel.org/pub/linux/utils/fs/xfs/docs/xfs_filesystem_structure.pdf
+// Unlike the other filesystems everything in XFS is big endian
+
+const INCOMPAT_FTYPE: u32 = 0x1;
+const DIFLAG2_BIGTIME: u64 = 0x8;
+const DIFLAG2_NREXT64: u64 = 0x10;
+const FORMAT_LOCAL: u8 = 1;
+const FORMAT_EXTENTS: u8 = 2;
+const FORMAT_BTREE: u8 = 3;
+/// Directory data blocks live below this byte offset, leaf/node/free index blocks are above it
+const DIR2_LEAF_OFFSET: u64 = 32 << 30;
+/// bigtime timestamps count nanoseconds from the smallest 32 bit timestamp (1901-12-13)
+const BIGTIME_EPOCH_OFFSET: i64 = 1 << 31;
+/// Inodes are allocated in chunks of 64
+const INODES_PER_CHUNK: u64 = 64;
+
+fn be16(b: &[u8], i: usize) -> u16{
+    u16::from_be_bytes([b[i], b[i+1]])
+}
+fn be32(b: &[u8], i: usize) -> u32{
+    u32::from_be_bytes([b[i], b[i+1], b[i+2], b[i+3]])
+}
+fn be64(b: &[u8], i: usize) -> u64{
+    u64::from_be_bytes([b[i], b[i+1], b[i+2], b[i+3], b[i+4], b[i+5], b[i+6], b[i+7]])
+}
+/// A data fork extent (startoff and startblock in filesystem blocks)
+#[derive(Debug, Default, Clone, Copy)]
+struct Extent{
+    startoff: u64,
+    startblock: u64,
+    blockcount: u64,
+}
+fn extent_at(b: &[u8], i: usize) -> Extent{
+    // 128 bits: 1 bit flag, 54 bits startoff, 52 bits startblock, 21 bits blockcount
+    let hi = be64(b, i);
+    let lo = be64(b, i+8);
+    Extent{
+        startoff: (hi >> 9) & ((1 << 54) - 1),
+        startblock: ((hi & 0x1FF) << 43) | (lo >> 21),
+        blockcount: lo & ((1 << 21) - 1),
+    }
+}
+/// The part of an inode that matters to us
+#[derive(Debug, Default, Clone)]
+struct Inode{
+    mode: u16,
+    size: u64,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+    format: u8,
+    nextents: u64,
+    /// The data fork (shortform directory, extent list or root of the extent B+tree)
+    fork: Vec<u8>,
+}
+impl Inode{
+    fn is_dir(self: &Self) -> bool{
+        self.mode & 0xF000 == 0x4000
+    }
+}
+struct XfsDrive{
+    file: PartitionFile,
+    directories: Vec<Directory>,
+    mounted_at: String,
+    block_size: u64,
+    sector_size: u64,
+    inode_size: u64,
+    ag_blocks: u64,
+    ag_count: u64,
+    ag_blk_log: u32,
+    inop_blog: u32,
+    dir_block_size: u64,
+    /// v5 filesystems have bigger headers (with checksums) everywhere
+    v5: bool,
+    has_ftype: bool,
+    root_ino: u64,
+    /// Every allocated inode, loaded chunk by chunk from the inode B+trees
+    inodes: HashMap<u64, Inode>,
+    /// Directory inodes already indexed, a damaged entry pointing to a parent would be followed forever
+    visited: HashSet<u64>,
+    files: Vec<XfsFile>,
+    ignored_dirs: Vec<String>
+}
+impl XfsDrive{
+    fn new(device: String, mounted_at: String, ignored_dirs: Vec<String>)-> Result<Self, Error>{
+        let file = partitions::open(&device).map_err(|e| Error::open(&device, e))?;
+        let mut sb = vec![0u8; 512];
+        file.read_at(&mut sb, 0).map_err(Error::io(0))?;
+        if &sb[0..4] != b"XFSB"{
+            return Err(Error::WrongFilesystem("XFS"));
+        }
+
+        let block_size = be32(&sb, 4) as u64;
+        let root_ino = be64(&sb, 56);
+        let ag_blocks = be32(&sb, 84) as u64;
+        let ag_count = be32(&sb, 88) as u64;
+        let sector_size = be16(&sb, 102) as u64;
+        let v5 = be16(&sb, 100) & 0xF == 5;
+        let inode_size = be16(&sb, 104) as u64;
+        let inop_blog = sb[123] as u32;
+        let ag_blk_log = sb[124] as u32;
+        let valid = |size: u64, min: u64, max: u64| size.is_power_of_two() && (min..=max).contains(&size);
+        if !valid(block_size, 512, 65536) || !valid(sector_size, 512, 32768) || !valid(inode_size, 256, 2048) ||
+            ag_blocks == 0 || ag_blk_log > 31 || sb[192] > 8{
+            return Err(Error::corrupt(4, "invalid superblock geometry"));
+        }
+        let dir_block_size = block_size << sb[192];
+        // v5 always has the file type in directory entries, v4 has it as a features2 bit
+        let has_ftype = if v5{be32(&sb, 216) & INCOMPAT_FTYPE != 0}else{be32(&sb, 200) & 0x200 != 0};
+        Ok(XfsDrive {file, directories: Vec::new(), mounted_at, block_size, sector_size, inode_size, ag_blocks, ag_count,
+            ag_blk_log, inop_blog, dir_block_size, v5, has_ftype, root_ino, inodes: HashMap::new(), visited: HashSet::new(), files: Vec::new(), ignored_dirs})
+    }
+    fn read_bytes(self: &Self, from: u64, size: u64) -> Result<Vec<u8>, Error>{
+        let mut b = vec![0_u8; size as usize];
+        self.file.read_at(&mut b, from).map_err(Error::io(from))?;
+        Ok(b)
+    }
+    /// Block numbers in the filesystem are (allocation group << agblklog) | block inside of the group
+    fn fsb_to_byte(self: &Self, fsb: u64) -> u64{
+        let ag = fsb >> self.ag_blk_log;
+        let block = fsb & ((1 << self.ag_blk_log) - 1);
+        (ag*self.ag_blocks + block)*self.block_size
+    }
+    /// Walks the inode B+tree of every allocation group and reads every allocated inode,
+    /// whole chunks are read at once so this is a lot faster than reading inodes one by one
+    fn load_inodes(mut self: Self) -> Result<Self, Error>{
+        for ag in 0..self.ag_count{
+            // The AGI header is in the third sector of the allocation group
+            let agi = self.read_bytes(ag*self.ag_blocks*self.block_size + 2*self.sector_size, self.sector_size)?;
+            if &agi[0..4] != b"XAGI"{
+                continue;
+            }
+            let root = be32(&agi, 20) as u64;
+            let level = be32(&agi, 24);
+            let mut chunks = Vec::new();
+            self.walk_inobt(ag, root, level.saturating_sub(1), &mut chunks)?;
+            for (agino, free) in chunks{
+                let first_ino = (ag << (self.ag_blk_log + self.inop_blog)) | agino;
+                let bytes = self.read_bytes(self.fsb_to_byte(first_ino >> self.inop_blog), INODES_PER_CHUNK*self.inode_size)?;
+                for i in 0..INODES_PER_CHUNK{
+                    if free & (1 << i) != 0{
+                        continue;
+                    }
+                    let start = (i*self.inode_size) as usize;
+                    if let Some(inode) = self.parse_inode(&bytes[start..start+self.inode_size as usize]){
+                        self.inodes.insert(first_ino + i, inode);
+                    }
+                }
+            }
+        }
+        Ok(self)
+    }
+    /// Pushes (first inode of the chunk relative to the allocation group, free mask) for every inode chunk
+    fn walk_inobt(self: &Self, ag: u64, agbno: u64, level: u32, chunks: &mut Vec<(u64, u64)>) -> Result<(), Error>{
+        let b = self.read_bytes((ag*self.ag_blocks + agbno)*self.block_size, self.block_size)?;
+        if &b[0..4] != b"IABT" && &b[0..4] != b"IAB3"{
+            return Ok(());
+        }
+        let header = if self.v5{56}else{16};
+        let numrecs = be16(&b, 6) as usize;
+        if level == 0{
+            for r in 0..numrecs{
+                let rec = header + r*16;
+                if rec + 16 > b.len(){
+                    break;
+                }
+                chunks.push((be32(&b, rec) as u64, be64(&b, rec+8)));
+            }
+        }else{
+            let maxrecs = (b.len() - header) / 8;
+            for r in 0..numrecs.min(maxrecs){
+                let ptr = be32(&b, header + maxrecs*4 + r*4) as u64;
+                self.walk_inobt(ag, ptr, level - 1, chunks)?;
+            }
+        }
+        Ok(())
+    }
+    fn read_inode(self: &Self, ino: u64) -> Result<Option<Inode>, Error>{
+        if let Some(inode) = self.inodes.get(&ino){
+            return Ok(Some(inode.clone()));
+        }
+        let fsb = ino >> self.inop_blog;
+        let offset = ino & ((1 << self.inop_blog) - 1);
+        let b = self.read_bytes(self.fsb_to_byte(fsb) + offset*self.inode_size, self.inode_size)?;
+        Ok(self.parse_inode(&b))
+    }
+    fn parse_inode(self: &Self, b: &[u8]) -> Option<Inode>{
+        if &b[0..2] != b"IN"{
+            return None;
+        }
+        let version = b[4];
+        let flags2 = if version >= 3{be64(b, 120)}else{0};
+        let time = |i: usize| -> i64{
+            if flags2 & DIFLAG2_BIGTIME != 0{
+                (be64(b, i) / 1_000_000_000) as i64 - BIGTIME_EPOCH_OFFSET
+            }else{
+                be32(b, i) as i32 as i64
+            }
+        };
+        let last_modified_timestamp = time(40);
+        // Only v3 inodes have a creation time, the inode change time is the closest thing
+        let create_timestamp = if version >= 3{time(144)}else{time(48)};
+        let nextents = if flags2 & DIFLAG2_NREXT64 != 0{be64(b, 24)}else{be32(b, 76) as u64};
+        let core_size = if version >= 3{176}else{100};
+        // forkoff is where the attribute fork starts (in 8 byte units), 0 means there isn't one
+        let forkoff = b[82] as usize * 8;
+        let fork_end = if forkoff == 0{b.len()}else{(core_size + forkoff).min(b.len())};
+        let mode = be16(b, 2);
+        // Only directories need their data fork later, dropping it for files saves a lot of memory
+        let fork = if mode & 0xF000 == 0x4000{b[core_size..fork_end].to_vec()}else{Vec::new()};
+        Some(Inode{
+            mode,
+            size: be64(b, 56),
+            create_timestamp,
+            last_modified_timestamp,
+            format: b[5],
+            nextents,
+            fork,
+        })
+    }
+    /// Returns every extent of the data fork
+    fn extents(self: &Self, inode: &Inode) -> Result<Vec<Extent>, Error>{
+        let mut extents = Vec::new();
+        match inode.format{
+            FORMAT_EXTENTS => {
+                for e in 0..inode.nextents as usize{
+                    if e*16 + 16 > inode.fork.len(){
+                        break;
+                    }
+                    extents.push(extent_at(&inode.fork, e*16));
+                }
+            }
+            FORMAT_BTREE => {
+                // The root of the B+tree is inside of the inode: level, numrecs, keys then pointers
+                let level = be16(&inode.fork, 0);
+                let numrecs = be16(&inode.fork, 2) as usize;
+                let maxrecs = inode.fork.len().saturating_sub(4) / 16;
+                // A root at level 0 would hold extents, not pointers, it doesn't exist
+                if level > 0{
+                    for r in 0..numrecs.min(maxrecs){
+                        let ptr = be64(&inode.fork, 4 + maxrecs*8 + r*8);
+                        self.walk_bmap_btree(ptr, level as u32 - 1, &mut extents)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(extents)
+    }
+    fn walk_bmap_btree(self: &Self, fsb: u64, level: u32, extents: &mut Vec<Extent>) -> Result<(), Error>{
+        let b = self.read_bytes(self.fsb_to_byte(fsb), self.block_size)?;
+        if &b[0..4] != b"BMAP" && &b[0..4] != b"BMA3"{
+            return Ok(());
+        }
+        let header = if self.v5{72}else{24};
+        let numrecs = be16(&b, 6) as usize;
+        if level == 0{
+            for r in 0..numrecs{
+                if header + r*16 + 16 > b.len(){
+                    break;
+                }
+                extents.push(extent_at(&b, header + r*16));
+            }
+        }else{
+            let maxrecs = (b.len() - header) / 16;
+            for r in 0..numrecs.min(maxrecs){
+                let ptr = be64(&b, header + maxrecs*8 + r*8);
+                self.walk_bmap_btree(ptr, level - 1, extents)?;
+            }
+        }
+        Ok(())
+    }
+    /// Returns (inode, name) for every entry in a directory (except "." and "..")
+    fn read_dir(self: &Self, inode: &Inode) -> Result<Vec<(u64, String)>, Error>{
+        let mut entries = Vec::new();
+        if inode.format == FORMAT_LOCAL{
+            // Shortform directory: everything is inside of the inode
+            let b = &inode.fork;
+            let count = b[0] as usize;
+            let ino_size = if b[1] > 0{8}else{4};
+            let mut i = 2 + ino_size;
+            for _ in 0..count{
+                if i >= b.len(){
+                    break;
+                }
+                let name_len = b[i] as usize;
+                let name_start = i + 3;
+                let mut ino_start = name_start + name_len;
+                if self.has_ftype{
+                    ino_start += 1;
+                }
+                if ino_start + ino_size > b.len(){
+                    break;
+                }
+                let name = String::from_utf8_lossy(&b[name_start..name_start+name_len]).to_string();
+                let ino = if ino_size == 8{be64(b, ino_start)}else{be32(b, ino_start) as u64};
+                entries.push((ino, name));
+                i = ino_start + ino_size;
+            }
+            return Ok(entries);
+        }
+        // Block, leaf and node directories all keep their entries in data blocks below DIR2_LEAF_OFFSET,
+        // the hash index above it isn't needed to list a directory
+        let limit = DIR2_LEAF_OFFSET / self.block_size;
+        let blocks_per_dir_block = self.dir_block_size / self.block_size;
+        let mut blocks = Vec::new();
+        for e in self.extents(inode)?{
+            for b in 0..e.blockcount{
+                if e.startoff + b < limit{
+                    blocks.push((e.startoff + b, e.startblock + b));
+                }
+            }
+        }
+        blocks.sort();
+        for chunk in blocks.chunks(blocks_per_dir_block as usize){
+            let mut bytes = Vec::new();
+            for &(_, fsb) in chunk{
+                bytes.append(&mut self.read_bytes(self.fsb_to_byte(fsb), self.block_size)?);
+            }
+            self.read_dir_block(&bytes, &mut entries);
+        }
+        Ok(entries)
+    }
+    fn read_dir_block(self: &Self, b: &[u8], entries: &mut Vec<(u64, String)>){
+        let magic = &b[0..4];
+        let is_block_dir = magic == b"XD2B" || magic == b"XDB3";
+        if !is_block_dir && magic != b"XD2D" && magic != b"XDD3"{
+            return;
+        }
+        let mut end = b.len();
+        if is_block_dir{
+            // Single block directories end with the hash leaf entries and a tail with their count
+            let count = be32(b, b.len() - 8) as usize;
+            end = b.len().saturating_sub(8 + count*8);
+        }
+        let mut i = if self.v5{64}else{16};
+        while i + 8 <= end{
+            // Unused space starts with a 0xFFFF tag followed by its length
+            if be16(b, i) == 0xFFFF{
+                let length = be16(b, i+2) as usize;
+                if length == 0{
+                    break;
+                }
+                i += length;
+                continue;
+            }
+            if i + 9 > end{
+                break;
+            }
+            let ino = be64(b, i);
+            let name_len = b[i+8] as usize;
+            if i + 9 + name_len > end{
+                break;
+            }
+            let name = String::from_utf8_lossy(&b[i+9..i+9+name_len]).to_string();
+            if name != "." && name != ".."{
+                entries.push((ino, name));
+            }
+            // inumber, namelen, name, (ftype), tag rounded up to 8 bytes
+            let ftype = if self.has_ftype{1}else{0};
+            i += (8 + 1 + name_len + ftype + 2).div_ceil(8) * 8;
+        }
+    }
+    fn index_from_root(mut self: Self) -> Result<Self, Error>{
+        self.directories.push(Directory { name: self.mounted_at.trim_end_matches('/').to_string() + "/" });
+        if let Some(root) = self.read_inode(self.root_ino)?{
+            self.visited.insert(self.root_ino);
+            self.index(&root, 0)?;
+        }
+        Ok(self)
+    }
+    fn index(self: &mut Self, directory: &Inode, parent: u32) -> Result<(), Error>{
+        let mut new_dirs = Vec::new();
+        for (ino, name) in self.read_dir(directory)?{
+            let Some(inode) = self.read_inode(ino)? else{continue;};
+            let is_dir = inode.is_dir();
+            let mut full_name = self.directories[parent as usize].name.clone() + &name;
+            if is_dir{
+                full_name += "/";
+            }
+            let mut to_ignore = false;
+            for i in self.ignored_dirs.clone(){
+                if full_name.starts_with(&i){
+                    to_ignore = true;
+                }
+            }
+            if to_ignore{continue;}
+            self.files.push(XfsFile{
+                name: name.clone(),
+                parent,
+                size: inode.size,
+                is_dir,
+                create_timestamp: inode.create_timestamp,
+                last_modified_timestamp: inode.last_modified_timestamp
+            });
+            if is_dir && self.visited.insert(ino){
+                new_dirs.push((inode, full_name));
+            }
+        }
+        for (inode, name) in new_dirs{
+            self.directories.push(Directory{name});
+            self.index(&inode, self.directories.len() as u32 - 1)?;
+        }
+        Ok(())
+    }
+}
+/// A file, timestamps use unix epoch
+#[derive(Debug, Default, Clone)]
+struct XfsFile{
+    name: String,
+    parent: u32,
+    size: u64,
+    is_dir: bool,
+    create_timestamp: i64,
+    last_modified_timestamp: i64,
+}
+fn from_xfs_files_to_files(f: &XfsFile, idx: u32)->File{
+    File{
+        name:f.name.clone(),
+        parent:f.parent + idx,
+        size:f.size,
+        is_dir:f.is_dir,
+        create_timestamp:f.create_timestamp,
+        last_modified_timestamp: f.last_modified_timestamp,
+        deleted: None,
+        attributes: Attributes::only_directory(f.is_dir)
+    }
+}
+
+pub struct XfsIndexer;
+impl FilesystemIndexer for XfsIndexer{
+    fn name(self: &Self) -> &'static str{
+        "Xfs"
+    }
+    fn probe(self: &Self, b: &[u8], _file: &PartitionFile) -> bool{
+        &b[0..4] == b"XFSB"
+    }
+    fn index(self: &Self, drive: &Drive, idx: u32) -> Result<(Vec<File>, Vec<Directory>), Error>{
+        let drive = XfsDrive::new(drive.drive.clone(), drive.mounted_at.clone(), drive.ignored_dirs.clone())?.load_inodes()?.index_from_root()?;
+        let mut output = Vec::new();
+        for f in &drive.files{
+            output.push(from_xfs_files_to_files(f, idx));
+        }
+        Ok((output,drive.directories))
+    }
+    fn capabilities(self: &Self) -> Capabilities{
+        Capabilities{creation_time: true, reads_device: true, deleted_files: false}
+    }
+}
@@ -0,0 +1,648 @@
+use crate::{u16_at, u32_at};
+
+// The following code decompresses Zstandard frames following RFC 8878 https://www.rfc-editor.org/rfc/rfc8878
+// Tables are built the same way as in the educational decoder of the reference implementation,
+// dictionaries are not supported and checksums are skipped
+
+const MAGIC: u32 = 0xFD2F_B528;
+/// Skippable frames have any value from 0x184D2A50 to 0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const MAX_HUFFMAN_BITS: u32 = 11;
+const LL_BASE: [u32; 36] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+const LL_BITS: [u32; 36] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+const ML_BASE: [u32; 53] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+    35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051, 4099, 8195, 16387, 32771, 65539];
+const ML_BITS: [u32; 53] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+/// Predefined distributions of literal lengths, match lengths and offsets (-1 means "less than 1")
+const LL_DEFAULT: [i16; 36] = [4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1];
+const ML_DEFAULT: [i16; 53] = [1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1];
+const OF_DEFAULT: [i16; 29] = [1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1];
+
+fn highest_bit(v: u32) -> u32{
+    31 - v.leading_zeros()
+}
+/// Reads bits from the lowest bit of every byte (FSE table descriptions)
+struct ForwardBits<'a>{
+    data: &'a [u8],
+    /// In bits
+    pos: usize,
+}
+impl ForwardBits<'_>{
+    fn bits(self: &mut Self, n: u32) -> u32{
+        let mut v = 0;
+        for i in 0..n{
+            let byte = self.data.get(self.pos / 8).copied().unwrap_or(0);
+            v |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        v
+    }
+}
+/// Reads bits from the end of a stream, the highest 1 bit of the last byte marks where the stream starts.
+/// Reading past the beginning gives zeros, that is how the end of some streams is found
+struct BackwardBits<'a>{
+    data: &'a [u8],
+    /// Bits left, negative after reading past the beginning
+    pos: isize,
+}
+impl BackwardBits<'_>{
+    fn new(data: &[u8]) -> Option<BackwardBits<'_>>{
+        let last = *data.last()?;
+        if last == 0{
+            return None;
+        }
+        Some(BackwardBits{data, pos: (data.len() as isize - 1)*8 + highest_bit(last as u32) as isize})
+    }
+    /// The bits at the lowest 64 bits from `bit` (missing bytes are zeros)
+    fn word(self: &Self, bit: usize) -> u64{
+        let mut w = [0u8; 8];
+        for (i, b) in w.iter_mut().enumerate(){
+            *b = self.data.get(bit / 8 + i).copied().unwrap_or(0);
+        }
+        u64::from_le_bytes(w) >> (bit % 8)
+    }
+    fn bits(self: &mut Self, n: u32) -> u32{
+        if n == 0{
+            return 0;
+        }
+        self.pos -= n as isize;
+        if self.pos >= 0{
+            return (self.word(self.pos as usize) & ((1u64 << n) - 1)) as u32;
+        }
+        // Only the bits from the beginning are left, the rest is zero
+        let available = self.pos + n as isize;
+        if available <= 0{
+            return 0;
+        }
+        ((self.word(0) & ((1u64 << available) - 1)) << -self.pos) as u32
+    }
+}
+/// A Finite State Entropy decoding table
+#[derive(Debug, Clone)]
+struct Fse{
+    accuracy_log: u32,
+    symbols: Vec<u8>,
+    bits: Vec<u8>,
+    base: Vec<u16>,
+}
+impl Fse{
+    fn new(distribution: &[i16], accuracy_log: u32) -> Option<Fse>{
+        let size = 1usize << accuracy_log;
+        let mut symbols = vec![0u8; size];
+        let mut next = vec![0u16; distribution.len()];
+        // Symbols with a "less than 1" probability go at the end of the table
+        let mut high = size;
+        for (s, &p) in distribution.iter().enumerate(){
+            if p == -1{
+                high -= 1;
+                symbols[high] = s as u8;
+                next[s] = 1;
+            }else{
+                next[s] = p as u16;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut pos = 0;
+        for (s, &p) in distribution.iter().enumerate(){
+            for _ in 0..p.max(0){
+                symbols[pos] = s as u8;
+                pos = (pos + step) & (size - 1);
+                while pos >= high{
+                    pos = (pos + step) & (size - 1);
+                }
+            }
+        }
+        if pos != 0{
+            return None;
+        }
+        let mut bits = vec![0u8; size];
+        let mut base = vec![0u16; size];
+        for i in 0..size{
+            let state = next[symbols[i] as usize];
+            next[symbols[i] as usize] += 1;
+            bits[i] = (accuracy_log - highest_bit(state as u32)) as u8;
+            base[i] = ((state as usize) << bits[i]).wrapping_sub(size) as u16;
+        }
+        Some(Fse{accuracy_log, symbols, bits, base})
+    }
+    /// Every state gives the same symbol
+    fn rle(symbol: u8) -> Fse{
+        Fse{accuracy_log: 0, symbols: vec![symbol], bits: vec![0], base: vec![0]}
+    }
+    /// Reads a table description, returns the table and how many bytes it used
+    fn read(data: &[u8], max_accuracy_log: u32) -> Option<(Fse, usize)>{
+        let mut r = ForwardBits{data, pos: 0};
+        let accuracy_log = r.bits(4) + 5;
+        if accuracy_log > max_accuracy_log{
+            return None;
+        }
+        let mut remaining = 1i32 << accuracy_log;
+        let mut distribution = Vec::new();
+        while remaining > 0 && distribution.len() < 256{
+            let bits = highest_bit(remaining as u32 + 1) + 1;
+            let mut v = r.bits(bits);
+            let lower_mask = (1 << (bits - 1)) - 1;
+            let threshold = (1 << bits) - 1 - (remaining as u32 + 1);
+            // Small values use one bit less
+            if v & lower_mask < threshold{
+                r.pos -= 1;
+                v &= lower_mask;
+            }else if v > lower_mask{
+                v -= threshold;
+            }
+            let p = v as i32 - 1;
+            remaining -= p.abs();
+            distribution.push(p as i16);
+            if p == 0{
+                // Followed by how many more symbols have a probability of 0
+                loop{
+                    let repeat = r.bits(2);
+                    distribution.resize(distribution.len() + repeat as usize, 0);
+                    if repeat != 3{
+                        break;
+                    }
+                }
+            }
+        }
+        let used = r.pos.div_ceil(8);
+        if remaining != 0 || used > data.len(){
+            return None;
+        }
+        Some((Fse::new(&distribution, accuracy_log)?, used))
+    }
+    fn update(self: &Self, state: &mut usize, r: &mut BackwardBits){
+        *state = self.base[*state] as usize + r.bits(self.bits[*state] as u32) as usize;
+    }
+}
+#[derive(Debug, Clone)]
+struct Huffman{
+    max_bits: u32,
+    symbols: Vec<u8>,
+    bits: Vec<u8>,
+}
+impl Huffman{
+    /// Reads a tree description, returns the table and how many bytes it used
+    fn read(data: &[u8]) -> Option<(Huffman, usize)>{
+        let header = *data.first()? as usize;
+        let mut weights = Vec::new();
+        let used = if header < 128{
+            // The weights are FSE compressed with two interleaved states, `header` is the compressed size
+            let compressed = data.get(1..1 + header)?;
+            let (fse, n) = Fse::read(compressed, 6)?;
+            let mut r = BackwardBits::new(&compressed[n..])?;
+            let mut state1 = r.bits(fse.accuracy_log) as usize;
+            let mut state2 = r.bits(fse.accuracy_log) as usize;
+            loop{
+                weights.push(fse.symbols[state1]);
+                fse.update(&mut state1, &mut r);
+                if r.pos < 0{
+                    weights.push(fse.symbols[state2]);
+                    break;
+                }
+                weights.push(fse.symbols[state2]);
+                fse.update(&mut state2, &mut r);
+                if r.pos < 0{
+                    weights.push(fse.symbols[state1]);
+                    break;
+                }
+                if weights.len() > 255{
+                    return None;
+                }
+            }
+            1 + header
+        }else{
+            // 4 bits for every weight
+            let count = header - 127;
+            let b = data.get(1..1 + count.div_ceil(2))?;
+            for i in 0..count{
+                weights.push(if i % 2 == 0{b[i / 2] >> 4}else{b[i / 2] & 0xF});
+            }
+            1 + count.div_ceil(2)
+        };
+        // The weight of the last symbol is whatever completes the sum to a power of 2
+        let mut total = 0u32;
+        for &w in &weights{
+            if w as u32 > MAX_HUFFMAN_BITS{
+                return None;
+            }
+            if w > 0{
+                total += 1 << (w - 1);
+            }
+        }
+        if total == 0{
+            return None;
+        }
+        let max_bits = highest_bit(total) + 1;
+        let left = (1 << max_bits) - total;
+        if max_bits > MAX_HUFFMAN_BITS || !left.is_power_of_two(){
+            return None;
+        }
+        weights.push(highest_bit(left) as u8 + 1);
+        let lengths: Vec<u32> = weights.iter().map(|&w| if w > 0{max_bits + 1 - w as u32}else{0}).collect();
+        let mut count = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for &l in &lengths{
+            count[l as usize] += 1;
+        }
+        // Longer codes come first in the table
+        let size = 1usize << max_bits;
+        let mut symbols = vec![0u8; size];
+        let mut bits = vec![0u8; size];
+        let mut rank_start = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for l in (1..=max_bits as usize).rev(){
+            rank_start[l - 1] = rank_start[l] + count[l]*(1 << (max_bits as usize - l));
+            bits[rank_start[l]..rank_start[l - 1]].fill(l as u8);
+        }
+        for (s, &l) in lengths.iter().enumerate(){
+            if l != 0{
+                let len = 1 << (max_bits - l);
+                symbols[rank_start[l as usize]..rank_start[l as usize] + len].fill(s as u8);
+                rank_start[l as usize] += len;
+            }
+        }
+        Some((Huffman{max_bits, symbols, bits}, used))
+    }
+    fn decode_stream(self: &Self, data: &[u8], count: usize, out: &mut Vec<u8>) -> Option<()>{
+        let mut r = BackwardBits::new(data)?;
+        let mask = (1 << self.max_bits) - 1;
+        let mut state = r.bits(self.max_bits) as usize;
+        for _ in 0..count{
+            out.push(self.symbols[state]);
+            let n = self.bits[state] as u32;
+            state = ((state << n) | r.bits(n) as usize) & mask;
+        }
+        Some(())
+    }
+}
+/// What a block can reuse from the previous blocks of the frame
+struct FrameState{
+    reps: [usize; 3],
+    huffman: Option<Huffman>,
+    literal_lengths: Option<Fse>,
+    offsets: Option<Fse>,
+    match_lengths: Option<Fse>,
+}
+/// Decompresses every frame of the data, None if it would be bigger than `max` bytes
+pub fn decompress(data: &[u8], max: usize) -> Option<Vec<u8>>{
+    if data.is_empty(){
+        return None;
+    }
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len(){
+        let magic = u32_at(data.get(pos..pos + 4)?, 0);
+        if magic & 0xFFFF_FFF0 == SKIPPABLE_MAGIC{
+            pos += 8 + u32_at(data.get(pos..pos + 8)?, 4) as usize;
+            continue;
+        }
+        if magic != MAGIC{
+            return None;
+        }
+        pos = decode_frame(data, pos + 4, &mut out, max)?;
+    }
+    // The last frame was cut short
+    (pos == data.len()).then_some(out)
+}
+/// Returns where the frame ends
+fn decode_frame(data: &[u8], mut pos: usize, out: &mut Vec<u8>, max: usize) -> Option<usize>{
+    let descriptor = *data.get(pos)?;
+    pos += 1;
+    let single_segment = descriptor & 0x20 != 0;
+    let dictionary_size = [0, 1, 2, 4][(descriptor & 0x3) as usize];
+    let content_size_size = match descriptor >> 6{
+        0 => {if single_segment{1}else{0}}
+        1 => {2}
+        2 => {4}
+        _ => {8}
+    };
+    // Window descriptor
+    if !single_segment{
+        pos += 1;
+    }
+    if data.get(pos..pos + dictionary_size)?.iter().any(|&b| b != 0){
+        return None;
+    }
+    pos += dictionary_size + content_size_size;
+    let start = out.len();
+    let mut state = FrameState{reps: [1, 4, 8], huffman: None, literal_lengths: None, offsets: None, match_lengths: None};
+    loop{
+        let header = data.get(pos..pos + 3)?;
+        let header = header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16;
+        pos += 3;
+        let size = header >> 3;
+        // Raw and RLE blocks are `size` bytes once decompressed
+        if (header >> 1) & 0x3 < 2 && out.len() + size > max{
+            return None;
+        }
+        match (header >> 1) & 0x3{
+            0 => {
+                out.extend_from_slice(data.get(pos..pos + size)?);
+                pos += size;
+            }
+            1 => {
+                // The size is how many times the byte is repeated
+                out.resize(out.len() + size, *data.get(pos)?);
+                pos += 1;
+            }
+            2 => {
+                decode_block(data.get(pos..pos + size)?, &mut state, out, start, max)?;
+                pos += size;
+            }
+            _ => {return None;}
+        }
+        if header & 1 != 0{
+            break;
+        }
+    }
+    // Content checksum
+    if descriptor & 0x4 != 0{
+        pos += 4;
+    }
+    Some(pos)
+}
+/// Returns the literals and how many bytes of the block they used
+fn decode_literals(block: &[u8], state: &mut FrameState) -> Option<(Vec<u8>, usize)>{
+    let b0 = *block.first()? as usize;
+    let byte = |i: usize| block.get(i).map(|&b| b as usize);
+    let block_type = b0 & 0x3;
+    let size_format = (b0 >> 2) & 0x3;
+    if block_type == 0 || block_type == 1{
+        let (size, header) = match size_format{
+            0 | 2 => {(b0 >> 3, 1)}
+            1 => {((b0 >> 4) + (byte(1)? << 4), 2)}
+            _ => {((b0 >> 4) + (byte(1)? << 4) + (byte(2)? << 12), 3)}
+        };
+        if block_type == 0{
+            return Some((block.get(header..header + size)?.to_vec(), header + size));
+        }
+        return Some((vec![*block.get(header)?; size], header + 1));
+    }
+    let (header, bits, streams) = match size_format{
+        0 => {(3, 10, 1)}
+        1 => {(3, 10, 4)}
+        2 => {(4, 14, 4)}
+        _ => {(5, 18, 4)}
+    };
+    let mut h = 0u64;
+    for i in 0..header{
+        h |= (byte(i)? as u64) << (8*i);
+    }
+    let size = ((h >> 4) & ((1 << bits) - 1)) as usize;
+    let compressed = ((h >> (4 + bits)) & ((1 << bits) - 1)) as usize;
+    let mut data = block.get(header..header + compressed)?;
+    // Block type 2 has a new Huffman tree, type 3 reuses the previous one
+    if block_type == 2{
+        let (huffman, used) = Huffman::read(data)?;
+        state.huffman = Some(huffman);
+        data = &data[used..];
+    }
+    let huffman = state.huffman.as_ref()?;
+    let mut literals = Vec::with_capacity(size);
+    if streams == 1{
+        huffman.decode_stream(data, size, &mut literals)?;
+    }else{
+        // 4 streams, the jump table has the size of the first 3
+        if data.len() < 6{
+            return None;
+        }
+        let sizes = [u16_at(data, 0) as usize, u16_at(data, 2) as usize, u16_at(data, 4) as usize];
+        let mut start = 6;
+        let per_stream = size.div_ceil(4);
+        for i in 0..4{
+            let end = sizes.get(i).map_or(data.len(), |s| start + s);
+            let count = if i < 3{per_stream}else{size.checked_sub(3*per_stream)?};
+            huffman.decode_stream(data.get(start..end)?, count, &mut literals)?;
+            start = end;
+        }
+    }
+    Some((literals, header + compressed))
+}
+/// Reads the table of one of the 3 sequence symbols given its compression mode
+fn sequence_table(mode: u8, data: &[u8], pos: &mut usize, default: &[i16], default_log: u32, max_log: u32, previous: &Option<Fse>) -> Option<Fse>{
+    match mode{
+        0 => {Fse::new(default, default_log)}
+        1 => {
+            let symbol = *data.get(*pos)?;
+            *pos += 1;
+            Some(Fse::rle(symbol))
+        }
+        2 => {
+            let (fse, used) = Fse::read(data.get(*pos..)?, max_log)?;
+            *pos += used;
+            Some(fse)
+        }
+        _ => {previous.clone()}
+    }
+}
+/// None if the block is broken or `out` would grow past `max` bytes
+fn decode_block(block: &[u8], state: &mut FrameState, out: &mut Vec<u8>, frame_start: usize, max: usize) -> Option<()>{
+    let (literals, mut pos) = decode_literals(block, state)?;
+    if out.len() + literals.len() > max{
+        return None;
+    }
+    let b0 = *block.get(pos)? as usize;
+    let count = if b0 < 128{
+        pos += 1;
+        b0
+    }else if b0 < 255{
+        pos += 2;
+        ((b0 - 128) << 8) + *block.get(pos - 1)? as usize
+    }else{
+        pos += 3;
+        *block.get(pos - 2)? as usize + ((*block.get(pos - 1)? as usize) << 8) + 0x7F00
+    };
+    if count == 0{
+        out.extend_from_slice(&literals);
+        return Some(());
+    }
+    let modes = *block.get(pos)?;
+    pos += 1;
+    let ll = sequence_table(modes >> 6, block, &mut pos, &LL_DEFAULT, 6, 9, &state.literal_lengths)?;
+    let of = sequence_table((modes >> 4) & 0x3, block, &mut pos, &OF_DEFAULT, 5, 8, &state.offsets)?;
+    let ml = sequence_table((modes >> 2) & 0x3, block, &mut pos, &ML_DEFAULT, 6, 9, &state.match_lengths)?;
+    let mut r = BackwardBits::new(block.get(pos..)?)?;
+    let mut ll_state = r.bits(ll.accuracy_log) as usize;
+    let mut of_state = r.bits(of.accuracy_log) as usize;
+    let mut ml_state = r.bits(ml.accuracy_log) as usize;
+    let mut literal = 0;
+    for i in 0..count{
+        let of_code = of.symbols[of_state] as u32;
+        let ll_code = ll.symbols[ll_state] as usize;
+        let ml_code = ml.symbols[ml_state] as usize;
+        if of_code > 31 || ll_code >= LL_BASE.len() || ml_code >= ML_BASE.len(){
+            return None;
+        }
+        let offset_value = (1usize << of_code) + r.bits(of_code) as usize;
+        let match_length = (ML_BASE[ml_code] + r.bits(ML_BITS[ml_code])) as usize;
+        let literal_length = (LL_BASE[ll_code] + r.bits(LL_BITS[ll_code])) as usize;
+        // Values 1-3 refer to the last used offsets (shifted by one when there are no literals)
+        let offset = if offset_value > 3{
+            offset_value - 3
+        }else{
+            let index = offset_value - 1 + if literal_length == 0{1}else{0};
+            if index == 0{
+                state.reps[0]
+            }else{
+                let offset = if index == 3{state.reps[0].checked_sub(1)?}else{state.reps[index]};
+                if index != 1{
+                    state.reps[2] = state.reps[1];
+                }
+                state.reps[1] = state.reps[0];
+                state.reps[0] = offset;
+                offset
+            }
+        };
+        if offset_value > 3{
+            state.reps[2] = state.reps[1];
+            state.reps[1] = state.reps[0];
+            state.reps[0] = offset;
+        }
+        out.extend_from_slice(literals.get(literal..literal + literal_length)?);
+        literal += literal_length;
+        if offset == 0 || offset > out.len() - frame_start{
+            return None;
+        }
+        // The literals that are left still have to fit after the match
+        if out.len() + match_length + literals.len() - literal > max{
+            return None;
+        }
+        // The copy may overlap with what it is writing
+        let from = out.len() - offset;
+        for j in 0..match_length{
+            out.push(out[from + j]);
+        }
+        if i + 1 < count{
+            ll.update(&mut ll_state, &mut r);
+            ml.update(&mut ml_state, &mut r);
+            of.update(&mut of_state, &mut r);
+        }
+    }
+    out.extend_from_slice(literals.get(literal..)?);
+    state.literal_lengths = Some(ll);
+    state.offsets = Some(of);
+    state.match_lengths = Some(ml);
+    Some(())
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::inflate::tests::{noise, text};
+
+    // Made with the zstd command line tool, except for the RLE block
+    /// An empty file, with a checksum
+    const EMPTY: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x24, 0x00, 0x01, 0x00, 0x00, 0x99, 0xE9, 0xD8, 0x51,
+    ];
+    /// noise(100), in a raw block
+    const RAW: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x04, 0x58, 0x21, 0x03, 0x00, 0xC6, 0x4F, 0x14, 0xAF, 0xD8, 0x4B, 0x9A, 0x2E, 0x62, 0x07, 0x77,
+        0xCA, 0x7D, 0x4B, 0xFA, 0xEB, 0x6D, 0xA1, 0xA0, 0xCB, 0x53, 0x28, 0x15, 0x10, 0x93, 0x8C, 0x23, 0xA5, 0xD3, 0xD9, 0x38,
+        0xED, 0x5A, 0x85, 0x30, 0x9A, 0x08, 0x2D, 0xD6, 0xDE, 0xE3, 0x4E, 0x92, 0xAB, 0xA6, 0x10, 0xC5, 0x6F, 0xE7, 0xA5, 0xA7,
+        0xEF, 0x55, 0xA7, 0x68, 0x5A, 0xB2, 0x80, 0x70, 0xC9, 0x11, 0x62, 0xD3, 0xE2, 0x4A, 0xAE, 0x9D, 0xFC, 0x44, 0x2C, 0x80,
+        0x1D, 0xB1, 0x1B, 0xED, 0xD1, 0xC7, 0x11, 0xD0, 0xF2, 0xC9, 0x23, 0xF9, 0x86, 0xA0, 0xC1, 0x3B, 0x8E, 0xFD, 0x60, 0x12,
+        0x87, 0xF0, 0x91, 0xA8, 0x64, 0x69, 0xAB, 0x8E, 0xDF, 0xC9, 0x5D, 0x12, 0xA7,
+    ];
+    /// 200 "z" in an RLE block
+    const RLE: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x20, 0xC8, 0x43, 0x06, 0x00, 0x7A,
+    ];
+    /// text(800) without a checksum, the sequences use the repeat offsets
+    const TEXT: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x00, 0x58, 0xED, 0x03, 0x00, 0x02, 0x04, 0x0D, 0x13, 0xA0, 0x29, 0x1D, 0x19, 0x11, 0xED, 0xEF,
+        0xE6, 0xFF, 0xED, 0xDA, 0xB7, 0xFD, 0x1D, 0xEB, 0xF8, 0xC3, 0x73, 0x05, 0x87, 0x78, 0x55, 0xFD, 0xFF, 0x7F, 0xDB, 0xB6,
+        0x6D, 0x0F, 0xA3, 0x20, 0x06, 0xA1, 0x2E, 0xED, 0x14, 0x03, 0x98, 0x29, 0xC7, 0x94, 0x58, 0xA2, 0x21, 0x89, 0x87, 0x18,
+        0xD0, 0x4E, 0x31, 0x01, 0x1F, 0x00, 0x00, 0x14, 0x00, 0x1E, 0x00, 0x19, 0x00, 0x05, 0x80, 0x07, 0x40, 0x06, 0x40, 0x01,
+        0xE0, 0x01, 0x90, 0x01, 0x50, 0x00, 0x78, 0x00, 0xB2, 0xBE, 0x81, 0xC2, 0x37, 0xF0, 0xF6, 0x06, 0x32, 0xBD, 0x81, 0x92,
+        0x37, 0xF0, 0xF0, 0x06, 0xB2, 0xBB, 0x81, 0x62, 0x37, 0xF0, 0xEA, 0x06, 0x06, 0xDD, 0x2C, 0x07, 0x6A, 0x06, 0x6A, 0x01,
+        0xF5, 0x81, 0x9A, 0x81, 0x5A, 0x40, 0x7D, 0xA0, 0x66, 0xA0, 0x16, 0x6E, 0x3D, 0x9D,
+    ];
+    /// The first 1000 bytes of text(1000) 300 times, in more than one block
+    const MULTI_BLOCK: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x04, 0x58, 0x2C, 0x04, 0x00, 0x42, 0x04, 0x0D, 0x12, 0xB0, 0xA9, 0x03, 0x3C, 0x36, 0x72, 0xE2,
+        0xCB, 0x24, 0x8B, 0x56, 0x62, 0x77, 0x8A, 0xE5, 0xD9, 0xE1, 0x40, 0xE5, 0xFF, 0xFF, 0xFF, 0xB7, 0x6D, 0xDB, 0xB6, 0xDB,
+        0xB6, 0x6D, 0x6B, 0x14, 0xC4, 0x20, 0x94, 0xBB, 0xA4, 0x53, 0x06, 0x90, 0x95, 0xE1, 0x25, 0x74, 0xA4, 0xE3, 0x68, 0x10,
+        0x06, 0xA4, 0x53, 0x16, 0x29, 0x20, 0x70, 0xA3, 0x92, 0xC7, 0x15, 0xFC, 0xEB, 0x13, 0xB0, 0x07, 0x32, 0xA0, 0x00, 0x0F,
+        0x64, 0x40, 0x01, 0x2F, 0xC8, 0x40, 0x09, 0x1E, 0xC8, 0x82, 0x02, 0x5E, 0x90, 0x81, 0x12, 0x3C, 0x90, 0x05, 0x05, 0xBC,
+        0x20, 0x0B, 0x94, 0x3E, 0xE0, 0xF1, 0x01, 0xD9, 0x3E, 0x50, 0xF4, 0x81, 0x97, 0x17, 0x64, 0x78, 0x41, 0xB9, 0x0B, 0x9E,
+        0x5D, 0x90, 0xEB, 0x82, 0x46, 0xB7, 0xB0, 0x5A, 0xD6, 0x57, 0xB3, 0xB5, 0xD4, 0xB7, 0x66, 0xB5, 0xCC, 0xFA, 0xBA, 0xB5,
+        0x76, 0x02, 0x4C, 0x00, 0x00, 0x08, 0x6C, 0x01, 0x00, 0xFC, 0xFF, 0x39, 0x10, 0x02, 0x4D, 0x00, 0x00, 0x08, 0x6C, 0x01,
+        0x00, 0xDC, 0x13, 0x1D, 0x08, 0x01, 0x27, 0xE1, 0x7F, 0x42,
+    ];
+    /// "first " and "second" in two frames with a skippable frame between them
+    const FRAMES: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x04, 0x58, 0x31, 0x00, 0x00, 0x66, 0x69, 0x72, 0x73, 0x74, 0x20, 0xD2, 0xFD, 0xB5, 0x11, 0x50,
+        0x2A, 0x4D, 0x18, 0x03, 0x00, 0x00, 0x00, 0x61, 0x62, 0x63, 0x28, 0xB5, 0x2F, 0xFD, 0x04, 0x58, 0x31, 0x00, 0x00, 0x73,
+        0x65, 0x63, 0x6F, 0x6E, 0x64, 0x37, 0x00, 0x5E, 0x4D,
+    ];
+
+    fn multi_block_expected() -> Vec<u8>{
+        text(1000).repeat(300)
+    }
+
+    #[test]
+    fn empty(){
+        assert_eq!(decompress(EMPTY, usize::MAX), Some(Vec::new()));
+        assert_eq!(decompress(&[], usize::MAX), None);
+    }
+    #[test]
+    fn raw_and_rle_blocks(){
+        assert_eq!(decompress(RAW, usize::MAX), Some(noise(100)));
+        assert_eq!(decompress(RLE, usize::MAX), Some(vec![b'z'; 200]));
+    }
+    #[test]
+    fn compressed_blocks(){
+        assert_eq!(decompress(TEXT, usize::MAX), Some(text(800)));
+        assert_eq!(decompress(MULTI_BLOCK, usize::MAX), Some(multi_block_expected()));
+    }
+    #[test]
+    fn frames(){
+        assert_eq!(decompress(FRAMES, usize::MAX), Some(b"first second".to_vec()));
+    }
+    #[test]
+    fn max(){
+        assert_eq!(decompress(RAW, 99), None);
+        assert_eq!(decompress(RAW, 100), Some(noise(100)));
+        assert_eq!(decompress(RLE, 199), None);
+        assert_eq!(decompress(TEXT, 799), None);
+        assert_eq!(decompress(TEXT, 800), Some(text(800)));
+        assert_eq!(decompress(MULTI_BLOCK, 299_999), None);
+        assert_eq!(decompress(FRAMES, 11), None);
+    }
+    #[test]
+    fn truncated(){
+        for data in [EMPTY, RAW, RLE, TEXT]{
+            for len in 0..data.len(){
+                assert_eq!(decompress(&data[..len], usize::MAX), None, "cut at {len}");
+            }
+        }
+        // Cut right after the first frame or the skippable frame, what is left is still valid
+        for len in 0..FRAMES.len(){
+            let expected = if len == 19 || len == 30{Some(b"first ".to_vec())}else{None};
+            assert_eq!(decompress(&FRAMES[..len], usize::MAX), expected, "cut at {len}");
+        }
+        for len in (0..MULTI_BLOCK.len()).step_by(7){
+            assert_eq!(decompress(&MULTI_BLOCK[..len], usize::MAX), None, "cut at {len}");
+        }
+    }
+    #[test]
+    fn corrupt(){
+        let mut data = TEXT.to_vec();
+        data[0] ^= 1;
+        assert_eq!(decompress(&data, usize::MAX), None);
+        // Reserved block type
+        assert_eq!(decompress(&[0x28, 0xB5, 0x2F, 0xFD, 0x20, 0x00, 0x07, 0x00, 0x00], usize::MAX), None);
+        // Trailing garbage that isn't a frame
+        let mut data = RLE.to_vec();
+        data.push(0);
+        assert_eq!(decompress(&data, usize::MAX), None);
+        // Any flipped bit either fails or gives something, it never panics
+        for data in [RAW, TEXT, FRAMES]{
+            for bit in 0..data.len()*8{
+                let mut data = data.to_vec();
+                data[bit / 8] ^= 1 << (bit % 8);
+                decompress(&data, 1 << 20);
+            }
+        }
+    }
+}